@@ -10,6 +10,7 @@ serde_json = "1.0"
 use eframe::egui;
 use egui::{CentralPanel, Grid, RichText, Slider, TopBottomPanel};
 use rfd::FileDialog;
+use std::collections::VecDeque;
 use std::path::PathBuf;
 use std::process::{Command, Stdio};
 use std::sync::mpsc;
@@ -34,11 +35,16 @@ struct ConverterApp {
     // Conversion settings
     audio_format: AudioFormat,
     quality: f32,
+    // espeak language and optional variant, passed as "language+variant"
+    voice: String,
+    voice_variant: String,
     voice_speed: f32,
     voice_pitch: f32,
+    amplitude: u32,
     workers: usize,
     aggressive_cleanup: bool,
     enable_cache: bool,
+    output_policy: OutputPolicy,
 
     // UI state
     #[serde(skip)]
@@ -47,14 +53,34 @@ struct ConverterApp {
     progress_receiver: Option<mpsc::Receiver<ConversionProgress>>,
     #[serde(skip)]
     conversion_handle: Option<thread::JoinHandle<()>>,
+    // Process id of the running converter, so Stop can interrupt it cleanly
+    #[serde(skip)]
+    converter_pid: Arc<Mutex<Option<u32>>>,
 
     // Progress tracking
     #[serde(skip)]
     current_progress: ConversionProgress,
     #[serde(skip)]
     show_advanced: bool,
+    // Voices and variants from `--list-voices`, read the first time the settings are drawn
+    #[serde(skip)]
+    voice_list: Option<VoiceList>,
+    // What a dry run of the selected EPUB found, read in the background
+    #[serde(skip)]
+    book_info: Option<BookInfo>,
+
+    // Converter output shown in the console pane
+    #[serde(skip)]
+    console_receiver: Option<mpsc::Receiver<String>>,
+    #[serde(skip)]
+    console_lines: VecDeque<String>,
+    #[serde(skip)]
+    show_console: bool,
 }
 
+// Oldest console lines are dropped past this point
+const CONSOLE_MAX_LINES: usize = 1000;
+
 #[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
 enum AudioFormat {
     Vorbis,
@@ -81,6 +107,42 @@ impl AudioFormat {
             AudioFormat::Wav => "WAV (.wav)",
         }
     }
+
+    // What the converter passes its encoder for a 0-1 quality; mirrors
+    // Config::vorbis_quality/mp3_vbr_level/flac_compression_level in main.rs
+    fn quality_label(&self, quality: f32) -> String {
+        match self {
+            AudioFormat::Vorbis => format!("oggenc -q {}", (quality * 10.0) as u32),
+            AudioFormat::Flac => format!("FLAC level {}", (quality * 8.0).round().clamp(0.0, 8.0) as u32),
+            AudioFormat::Mp3 => format!("MP3 ~V{}", (9.0 - quality * 9.0) as u32),
+            AudioFormat::Wav => "uncompressed".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize)]
+enum OutputPolicy {
+    Refuse,
+    Overwrite,
+    Resume,
+}
+
+impl OutputPolicy {
+    fn cli_flag(&self) -> Option<&'static str> {
+        match self {
+            OutputPolicy::Refuse => None,
+            OutputPolicy::Overwrite => Some("--overwrite"),
+            OutputPolicy::Resume => Some("--resume"),
+        }
+    }
+
+    fn display_name(&self) -> &'static str {
+        match self {
+            OutputPolicy::Refuse => "Stop with an error",
+            OutputPolicy::Overwrite => "Delete existing files first",
+            OutputPolicy::Resume => "Resume, keeping existing files",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Default)]
@@ -98,16 +160,26 @@ impl Default for ConverterApp {
             output_dir: None,
             audio_format: AudioFormat::Vorbis,
             quality: 0.7,
+            voice: "en".to_string(),
+            voice_variant: String::new(),
             voice_speed: 1.0,
             voice_pitch: 1.0,
+            amplitude: 100,
             workers: num_cpus::get(),
             aggressive_cleanup: true,
             enable_cache: true,
+            output_policy: OutputPolicy::Refuse,
             status: ConversionStatus::Idle,
             progress_receiver: None,
             conversion_handle: None,
+            converter_pid: Arc::new(Mutex::new(None)),
             current_progress: ConversionProgress::default(),
             show_advanced: false,
+            voice_list: None,
+            book_info: None,
+            console_receiver: None,
+            console_lines: VecDeque::new(),
+            show_console: false,
         }
     }
 }
@@ -122,6 +194,16 @@ impl eframe::App for ConverterApp {
             }
         }
 
+        if let Some(receiver) = &self.console_receiver {
+            while let Ok(line) = receiver.try_recv() {
+                self.console_lines.push_back(line);
+                if self.console_lines.len() > CONSOLE_MAX_LINES {
+                    self.console_lines.pop_front();
+                }
+                ctx.request_repaint();
+            }
+        }
+
         // Top panel with title
         TopBottomPanel::top("top_panel").show(ctx, |ui| {
             ui.horizontal(|ui| {
@@ -150,6 +232,8 @@ impl eframe::App for ConverterApp {
                 self.draw_conversion_controls(ui);
                 ui.separator();
                 self.draw_progress_section(ui);
+                ui.separator();
+                self.draw_console(ui);
             });
         });
     }
@@ -178,6 +262,7 @@ impl ConverterApp {
                     .pick_file()
                     {
                         self.input_file = Some(path);
+                        self.book_info = None;
                     }
             }
             ui.label(
@@ -201,7 +286,60 @@ impl ConverterApp {
                 .unwrap_or("No folder selected".into())
             );
             ui.end_row();
+
+            ui.label("If Folder Not Empty:");
+            egui::ComboBox::from_id_source("output_policy")
+            .selected_text(self.output_policy.display_name())
+            .show_ui(ui, |ui| {
+                for policy in [OutputPolicy::Refuse, OutputPolicy::Overwrite, OutputPolicy::Resume] {
+                    ui.selectable_value(&mut self.output_policy, policy, policy.display_name());
+                }
+            });
+            ui.end_row();
         });
+
+        self.draw_book_info(ui);
+    }
+
+    // Title, author and size of the selected book, so parsing problems show
+    // up before a conversion is started
+    fn draw_book_info(&mut self, ui: &mut egui::Ui) {
+        let Some(input_file) = &self.input_file else {
+            return;
+        };
+        if self.book_info.is_none() {
+            self.book_info = Some(BookInfo::Parsing(inspect_book(input_file.clone())));
+        }
+        let finished = match &self.book_info {
+            Some(BookInfo::Parsing(receiver)) => receiver.try_recv().ok(),
+            _ => None,
+        };
+        match finished {
+            Some(Ok(summary)) => self.book_info = Some(BookInfo::Parsed(summary)),
+            Some(Err(e)) => self.book_info = Some(BookInfo::Failed(e)),
+            None => {}
+        }
+
+        match &self.book_info {
+            Some(BookInfo::Parsing(_)) => {
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    ui.label("Reading book...");
+                });
+            }
+            Some(BookInfo::Parsed(summary)) => {
+                ui.label(format!(
+                    "📖 {}{}",
+                    summary.title,
+                    summary.author.as_ref().map(|author| format!(" by {}", author)).unwrap_or_default()
+                ));
+                ui.label(format!("{} chapters, {} words, ~{} of audio", summary.chapters, summary.words, summary.duration));
+            }
+            Some(BookInfo::Failed(error)) => {
+                ui.label(RichText::new(format!("❌ {}", error)).color(egui::Color32::RED));
+            }
+            None => {}
+        }
     }
 
     fn draw_audio_settings(&mut self, ui: &mut egui::Ui) {
@@ -219,8 +357,41 @@ impl ConverterApp {
             });
             ui.end_row();
 
-            ui.label("Audio Quality:");
-            ui.add(Slider::new(&mut self.quality, 0.1..=1.0).text("Quality"));
+            // FLAC is lossless, so the slider only trades encoding time for size
+            ui.label(match self.audio_format {
+                AudioFormat::Flac => "Compression Effort:",
+                _ => "Audio Quality:",
+            });
+            ui.horizontal(|ui| {
+                let wav = matches!(self.audio_format, AudioFormat::Wav);
+                ui.add_enabled(!wav, Slider::new(&mut self.quality, 0.1..=1.0).text("Quality"));
+                ui.label(RichText::new(self.audio_format.quality_label(self.quality)).weak());
+            });
+            ui.end_row();
+
+            ui.label("Voice:");
+            ui.horizontal(|ui| {
+                let voice_list = self.voice_list.get_or_insert_with(list_voices);
+                if voice_list.voices.is_empty() {
+                    ui.add(egui::TextEdit::singleline(&mut self.voice).desired_width(60.0));
+                } else {
+                    egui::ComboBox::from_id_source("voice")
+                    .selected_text(self.voice.as_str())
+                    .show_ui(ui, |ui| {
+                        for (id, name) in &voice_list.voices {
+                            ui.selectable_value(&mut self.voice, id.clone(), format!("{} ({})", id, name));
+                        }
+                    });
+                }
+                egui::ComboBox::from_id_source("voice_variant")
+                .selected_text(if self.voice_variant.is_empty() { "Default variant" } else { self.voice_variant.as_str() })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.voice_variant, String::new(), "Default variant");
+                    for variant in voice_list.variants.iter() {
+                        ui.selectable_value(&mut self.voice_variant, variant.clone(), variant);
+                    }
+                });
+            });
             ui.end_row();
 
             ui.label("Voice Speed:");
@@ -247,6 +418,10 @@ impl ConverterApp {
                 ui.add(Slider::new(&mut self.workers, 1..=num_cpus::get() * 2).text("Threads"));
                 ui.end_row();
 
+                ui.label("Volume:");
+                ui.add(Slider::new(&mut self.amplitude, 0..=200).text("Amplitude"));
+                ui.end_row();
+
                 ui.label("Aggressive Text Cleanup:");
                 ui.checkbox(&mut self.aggressive_cleanup, "Enable aggressive preprocessing");
                 ui.end_row();
@@ -270,7 +445,13 @@ impl ConverterApp {
                 .ui_contains_pointer()
                 && can_convert
                 {
-                    self.start_conversion();
+                    self.start_conversion(false);
+                }
+
+                // The whole pipeline on chapter 0 only, to check format, quality and
+                // file naming on real output before converting the book
+                if ui.add_enabled(can_convert, egui::Button::new("🎧 Convert First Chapter")).clicked() {
+                    self.start_conversion(true);
                 }
 
                 if matches!(self.status, ConversionStatus::Running(_)) {
@@ -283,6 +464,10 @@ impl ConverterApp {
                 if ui.button("🔍 Check Dependencies").clicked() {
                     self.check_dependencies();
                 }
+
+                if ui.button("🩺 Self-Test").clicked() {
+                    self.run_self_test();
+                }
         });
 
         // Status display
@@ -328,20 +513,51 @@ impl ConverterApp {
         }
     }
 
-    fn start_conversion(&mut self) {
+    fn draw_console(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.heading("🖥️ Console");
+            if ui.button(if self.show_console { "▼" } else { "▶" }).clicked() {
+                self.show_console = !self.show_console;
+            }
+            if self.show_console && ui.button("Clear").clicked() {
+                self.console_lines.clear();
+            }
+        });
+
+        if self.show_console {
+            egui::ScrollArea::vertical()
+            .id_source("console_scroll")
+            .max_height(200.0)
+            .stick_to_bottom(true)
+            .show(ui, |ui| {
+                for line in &self.console_lines {
+                    ui.label(RichText::new(line).monospace());
+                }
+            });
+        }
+    }
+
+    fn start_conversion(&mut self, first_chapter_only: bool) {
         let input_file = self.input_file.clone().unwrap();
         let output_dir = self.output_dir.clone().unwrap();
         let audio_format = self.audio_format.clone();
         let quality = self.quality;
+        let voice = self.composed_voice();
         let voice_speed = self.voice_speed;
         let voice_pitch = self.voice_pitch;
+        let amplitude = self.amplitude;
         let workers = self.workers;
         let aggressive_cleanup = self.aggressive_cleanup;
         let enable_cache = self.enable_cache;
+        let output_policy = self.output_policy;
 
         let (progress_sender, progress_receiver) = mpsc::channel();
         self.progress_receiver = Some(progress_receiver);
+        let (console_sender, console_receiver) = mpsc::channel();
+        self.console_receiver = Some(console_receiver);
+        self.console_lines.clear();
         self.status = ConversionStatus::Running("Starting...".to_string());
+        let converter_pid = Arc::clone(&self.converter_pid);
 
         let handle = thread::spawn(move || {
             let result = run_conversion(
@@ -349,28 +565,77 @@ impl ConverterApp {
                 output_dir,
                 audio_format,
                 quality,
+                voice,
                 voice_speed,
                 voice_pitch,
+                amplitude,
                 workers,
                 aggressive_cleanup,
                 enable_cache,
+                output_policy,
+                first_chapter_only,
                 progress_sender,
+                console_sender.clone(),
+                converter_pid,
             );
 
             if let Err(e) = result {
                 eprintln!("Conversion failed: {}", e);
+                let _ = console_sender.send(format!("❌ {}", e));
             }
         });
 
         self.conversion_handle = Some(handle);
     }
 
+    fn composed_voice(&self) -> String {
+        let voice = self.voice.trim();
+        let voice = if voice.is_empty() { "en" } else { voice };
+        if self.voice_variant.is_empty() {
+            voice.to_string()
+        } else {
+            format!("{}+{}", voice, self.voice_variant)
+        }
+    }
+
     fn stop_conversion(&mut self) {
+        // SIGINT lets the converter finish its current chunks and write a resume
+        // marker; its closing message still reaches the console
+        if let Some(pid) = *self.converter_pid.lock().unwrap() {
+            let _ = Command::new("kill").arg("-INT").arg(pid.to_string()).status();
+        }
         self.status = ConversionStatus::Idle;
         self.conversion_handle = None;
         self.progress_receiver = None;
     }
 
+    // Runs `--selftest` in the background; its PASS/FAIL lines land in the console
+    fn run_self_test(&mut self) {
+        let (console_sender, console_receiver) = mpsc::channel();
+        self.console_receiver = Some(console_receiver);
+        self.console_lines.clear();
+        self.show_console = true;
+
+        thread::spawn(move || {
+            let _ = console_sender.send("🩺 Running self-test...".to_string());
+            let output = converter_path().and_then(|path| Command::new(path).arg("--selftest").output());
+            match output {
+                Ok(output) => {
+                    let stdout = String::from_utf8_lossy(&output.stdout);
+                    let stderr = String::from_utf8_lossy(&output.stderr);
+                    for line in stdout.lines().chain(stderr.lines()) {
+                        let _ = console_sender.send(line.to_string());
+                    }
+                    let verdict = if output.status.success() { "✅ Self-test passed" } else { "❌ Self-test failed" };
+                    let _ = console_sender.send(verdict.to_string());
+                }
+                Err(e) => {
+                    let _ = console_sender.send(format!("❌ Could not run the converter: {}", e));
+                }
+            }
+        });
+    }
+
     fn check_dependencies(&mut self) {
         let deps = check_system_dependencies();
         let mut message = String::new();
@@ -425,14 +690,19 @@ impl ConverterApp {
                 if let Ok(content) = std::fs::read_to_string(path) {
                     if let Ok(loaded) = serde_json::from_str::<ConverterApp>(&content) {
                         self.input_file = loaded.input_file;
+                        self.book_info = None;
                         self.output_dir = loaded.output_dir;
                         self.audio_format = loaded.audio_format;
                         self.quality = loaded.quality;
+                        self.voice = loaded.voice;
+                        self.voice_variant = loaded.voice_variant;
                         self.voice_speed = loaded.voice_speed;
                         self.voice_pitch = loaded.voice_pitch;
+                        self.amplitude = loaded.amplitude;
                         self.workers = loaded.workers;
                         self.aggressive_cleanup = loaded.aggressive_cleanup;
                         self.enable_cache = loaded.enable_cache;
+                        self.output_policy = loaded.output_policy;
                     }
                 }
             }
@@ -474,17 +744,130 @@ fn check_system_dependencies() -> SystemDependencies {
     deps
 }
 
+#[derive(Default)]
+struct VoiceList {
+    // (voice id, display name)
+    voices: Vec<(String, String)>,
+    variants: Vec<String>,
+}
+
+// Reads the table printed by `--list-voices`: indented "VOICE GENDER NAME"
+// rows, then the variants on the line after the "Variants" heading
+fn list_voices() -> VoiceList {
+    let Ok(output) = converter_path().and_then(|path| Command::new(path).arg("--list-voices").output()) else {
+        return VoiceList::default();
+    };
+    if !output.status.success() {
+        return VoiceList::default();
+    }
+
+    let mut list = VoiceList::default();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut lines = stdout.lines();
+    while let Some(line) = lines.next() {
+        if line.starts_with("Variants") {
+            list.variants = lines.next().unwrap_or_default().split_whitespace().map(str::to_string).collect();
+        } else if let Some(row) = line.strip_prefix("  ") {
+            let mut columns = row.split_whitespace();
+            if let (Some(id), Some(_gender)) = (columns.next(), columns.next()) {
+                if id != "VOICE" {
+                    list.voices.push((id.to_string(), columns.collect::<Vec<_>>().join(" ")));
+                }
+            }
+        }
+    }
+    list
+}
+
+struct BookSummary {
+    title: String,
+    author: Option<String>,
+    chapters: usize,
+    words: usize,
+    duration: String,
+}
+
+enum BookInfo {
+    Parsing(mpsc::Receiver<Result<BookSummary, String>>),
+    Parsed(BookSummary),
+    Failed(String),
+}
+
+// Runs `--dry-run` on a background thread and reads its "📖 Title by Author"
+// and "📊 N chapters, N words, ~D of audio" lines; a failure reports the
+// converter's error message
+fn inspect_book(input_file: PathBuf) -> mpsc::Receiver<Result<BookSummary, String>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let output = converter_path().and_then(|path| {
+            Command::new(path).arg("--dry-run").arg("-i").arg(&input_file).arg("--progress").arg("none").output()
+        });
+        let result = match output {
+            Ok(output) if output.status.success() => parse_dry_run(&String::from_utf8_lossy(&output.stdout))
+                .ok_or_else(|| "Could not read the converter's summary of this book".to_string()),
+            Ok(output) => {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                Err(stderr
+                    .lines()
+                    .rev()
+                    .find_map(|line| line.strip_prefix("Error: "))
+                    .unwrap_or("The converter could not read this book")
+                    .to_string())
+            }
+            Err(e) => Err(format!("Could not run the converter: {}", e)),
+        };
+        let _ = sender.send(result);
+    });
+    receiver
+}
+
+fn parse_dry_run(stdout: &str) -> Option<BookSummary> {
+    let heading = stdout.lines().find_map(|line| line.strip_prefix("📖 "))?;
+    let (title, author) = match heading.rsplit_once(" by ") {
+        Some((title, author)) => (title.to_string(), Some(author.to_string())),
+        None => (heading.to_string(), None),
+    };
+
+    let totals = stdout.lines().find_map(|line| line.strip_prefix("📊 "))?;
+    let mut parts = totals.split(", ");
+    let chapters = parts.next()?.split_whitespace().next()?.parse().ok()?;
+    let words = parts.next()?.split_whitespace().next()?.parse().ok()?;
+    let duration = parts.next()?.trim_start_matches('~').trim_end_matches(" of audio").to_string();
+
+    Some(BookSummary {
+        title,
+        author,
+        chapters,
+        words,
+        duration,
+    })
+}
+
+// The CLI converter is installed next to the GUI binary
+fn converter_path() -> std::io::Result<PathBuf> {
+    Ok(std::env::current_exe()?
+    .parent()
+    .unwrap()
+    .join("epub_audiobook_converter"))
+}
+
 fn run_conversion(
     input_file: PathBuf,
     output_dir: PathBuf,
     audio_format: AudioFormat,
     quality: f32,
+    voice: String,
     voice_speed: f32,
     voice_pitch: f32,
+    amplitude: u32,
     workers: usize,
     aggressive_cleanup: bool,
     enable_cache: bool,
+    output_policy: OutputPolicy,
+    first_chapter_only: bool,
     progress_sender: mpsc::Sender<ConversionProgress>,
+    console_sender: mpsc::Sender<String>,
+    converter_pid: Arc<Mutex<Option<u32>>>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // Build command arguments
     let mut args = vec![
@@ -496,10 +879,16 @@ fn run_conversion(
         audio_format.as_str().to_string(),
         "-q".to_string(),
         quality.to_string(),
+        "--voice".to_string(),
+        voice,
         "-s".to_string(),
         voice_speed.to_string(),
+        "--amplitude".to_string(),
+        amplitude.to_string(),
         "-w".to_string(),
         workers.to_string(),
+        "--progress".to_string(),
+        "machine".to_string(),
     ];
 
     if !aggressive_cleanup {
@@ -510,6 +899,15 @@ fn run_conversion(
         args.push("--no-cache".to_string());
     }
 
+    if let Some(flag) = output_policy.cli_flag() {
+        args.push(flag.to_string());
+    }
+
+    if first_chapter_only {
+        args.push("--chapters".to_string());
+        args.push("0".to_string());
+    }
+
     // Send initial progress
     let _ = progress_sender.send(ConversionProgress {
         current_chapter: "Initializing...".to_string(),
@@ -518,11 +916,7 @@ fn run_conversion(
                                  estimated_time_remaining: None,
     });
 
-    // Find the converter binary
-    let converter_path = std::env::current_exe()?
-    .parent()
-    .unwrap()
-    .join("epub_audiobook_converter");
+    let converter_path = converter_path()?;
 
     // Run the converter
     let mut child = Command::new(&converter_path)
@@ -530,40 +924,52 @@ fn run_conversion(
     .stdout(Stdio::piped())
     .stderr(Stdio::piped())
     .spawn()?;
+    *converter_pid.lock().unwrap() = Some(child.id());
+
+    use std::io::{BufRead, BufReader};
+
+    // Logs arrive on stderr; drain them on their own thread so a chatty run
+    // cannot fill the pipe and stall the converter
+    let stderr_reader = child.stderr.take().map(|stderr| {
+        let console_sender = console_sender.clone();
+        thread::spawn(move || {
+            let mut tail = VecDeque::new();
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                let _ = console_sender.send(line.clone());
+                tail.push_back(line);
+                if tail.len() > 5 {
+                    tail.pop_front();
+                }
+            }
+            tail.into_iter().collect::<Vec<_>>()
+        })
+    });
 
     // Monitor output for progress updates
     if let Some(stdout) = child.stdout.take() {
-        use std::io::{BufRead, BufReader};
         let reader = BufReader::new(stdout);
+        let mut parser = ProgressParser::new();
 
-        for line in reader.lines() {
-            if let Ok(line) = line {
-                // Parse progress from output
-                if line.contains("Found") && line.contains("chapters") {
-                    if let Some(total) = extract_number_from_line(&line, "Found", "chapters") {
-                        let _ = progress_sender.send(ConversionProgress {
-                            current_chapter: "Processing chapters...".to_string(),
-                                                     chapters_completed: 0,
-                                                     total_chapters: total,
-                                                     estimated_time_remaining: None,
-                        });
-                    }
-                } else if line.contains("Converting chapter") {
-                    // Extract chapter info if available
-                    let _ = progress_sender.send(ConversionProgress {
-                        current_chapter: line.clone(),
-                                                 chapters_completed: 0, // Would need more parsing
-                                                 total_chapters: 0,     // Would need state tracking
-                                                 estimated_time_remaining: None,
-                    });
+        for line in reader.lines().map_while(Result::ok) {
+            match parser.parse_line(&line) {
+                Some(progress) => {
+                    let _ = progress_sender.send(progress);
                 }
+                None if !line.starts_with("PROGRESS ") => {
+                    let _ = console_sender.send(line);
+                }
+                None => {}
             }
         }
     }
 
-    let output = child.wait_with_output()?;
+    let status = child.wait()?;
+    *converter_pid.lock().unwrap() = None;
+    let stderr_tail = stderr_reader
+        .and_then(|reader| reader.join().ok())
+        .unwrap_or_default();
 
-    if output.status.success() {
+    if status.success() {
         let _ = progress_sender.send(ConversionProgress {
             current_chapter: "Completed!".to_string(),
                                      chapters_completed: 100,
@@ -572,19 +978,71 @@ fn run_conversion(
         });
         Ok(())
     } else {
-        let error = String::from_utf8_lossy(&output.stderr);
-        Err(format!("Conversion failed: {}", error).into())
+        Err(format!("Conversion failed: {}", stderr_tail.join("\n")).into())
     }
 }
 
-fn extract_number_from_line(line: &str, before: &str, after: &str) -> Option<usize> {
-    if let Some(start) = line.find(before) {
-        if let Some(end) = line[start..].find(after) {
-            let number_part = &line[start + before.len()..start + end];
-            return number_part.trim().parse().ok();
+// Tracks conversion state across stdout lines so each update carries the
+// running totals instead of resetting them
+struct ProgressParser {
+    total_chapters: usize,
+    chapters_completed: usize,
+    current_chapter: String,
+    started: std::time::Instant,
+}
+
+impl ProgressParser {
+    fn new() -> Self {
+        Self {
+            total_chapters: 0,
+            chapters_completed: 0,
+            current_chapter: String::new(),
+            started: std::time::Instant::now(),
+        }
+    }
+
+    fn parse_line(&mut self, line: &str) -> Option<ConversionProgress> {
+        // The converter reports progress as "PROGRESS key=value" lines on stdout
+        let event = line.strip_prefix("PROGRESS ")?;
+        let (key, value) = event.split_once('=')?;
+
+        match key {
+            "total_chapters" => {
+                self.total_chapters = value.trim().parse().ok()?;
+                self.current_chapter = "Processing chapters...".to_string();
+            }
+            "chapter_started" => {
+                self.current_chapter = match value.split_once(' ') {
+                    Some((number, title)) => format!("Chapter {}: {}", number, title),
+                    None => format!("Chapter {}", value),
+                };
+            }
+            "chapter_completed" => {
+                self.chapters_completed = (self.chapters_completed + 1).min(self.total_chapters.max(1));
+            }
+            _ => return None,
+        }
+
+        Some(self.snapshot())
+    }
+
+    fn snapshot(&self) -> ConversionProgress {
+        ConversionProgress {
+            current_chapter: self.current_chapter.clone(),
+            chapters_completed: self.chapters_completed,
+            total_chapters: self.total_chapters,
+            estimated_time_remaining: self.estimate_remaining(),
         }
     }
-    None
+
+    fn estimate_remaining(&self) -> Option<std::time::Duration> {
+        if self.chapters_completed == 0 || self.total_chapters <= self.chapters_completed {
+            return None;
+        }
+
+        let per_chapter = self.started.elapsed() / self.chapters_completed as u32;
+        Some(per_chapter * (self.total_chapters - self.chapters_completed) as u32)
+    }
 }
 
 fn main() -> Result<(), eframe::Error> {
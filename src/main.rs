@@ -16,35 +16,151 @@ symphonia = { version = "0.5", features = ["all"] }
 hound = "3.5"
 rodio = { version = "0.17", features = ["vorbis"] }
 
-use clap::{Arg, Command};
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use encoding_rs::Encoding;
 use epub::doc::EpubDoc;
 use html2text::from_read;
-use indicatif::{ParallelProgressIterator, ProgressBar, ProgressStyle};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use log::{debug, error, info, log_enabled, warn, Level, LevelFilter};
 use lru::LruCache;
 use rayon::prelude::*;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 use std::fs::{self, File};
-use std::io::{BufWriter, Write};
+use std::io::{BufReader, BufWriter, IsTerminal, Read, Write};
 use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
 use std::process::{Command as ProcessCommand, Stdio};
-use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 struct Config {
+    // Intermediate WAV format every engine's output is converted to
     sample_rate: u32,
+    channels: u16,
+    bits_per_sample: u16,
+    // TTS backend: auto, espeak-ng, espeak, festival or mock
+    engine: String,
+    // espeak-ng-data directory (or the one holding it) for voices installed
+    // outside espeak's default location
+    espeak_data_path: Option<PathBuf>,
+    voice: String,
+    // Experimental: read quoted dialogue in this second voice
+    dialogue_voice: Option<String>,
     voice_speed: f32,
     voice_pitch: f32,
+    // espeak amplitude, 0-200
+    amplitude: u32,
     output_format: AudioFormat,
     quality: f32,
-    chunk_size: usize,
+    // Fixed/average bitrate; replaces the VBR quality mapping when set
+    bitrate_kbps: Option<u32>,
+    // Bounds on the text sent to the engine at once, in bytes. Chunks under the
+    // minimum are joined to a neighbour when the result stays within the maximum
+    #[serde(alias = "chunk_size")]
+    max_chunk_size: usize,
+    min_chunk_size: usize,
+    filename_template: String,
+    // First chapter's number in folder and file names (0 or 1)
+    chapter_number_base: usize,
     max_workers: usize,
+    // Keep a worker count above the number of CPU cores instead of capping it
+    oversubscribe: bool,
+    // Seconds a TTS or encoder process may run on one chunk before it is
+    // killed and the chunk retried once
+    chunk_timeout_secs: Option<u64>,
     cache_enabled: bool,
+    // Audio cache location; the per-user cache directory when unset
+    cache_dir: Option<PathBuf>,
+    // Where the run's scratch directory is created; the system temp dir when unset
+    tmp_dir: Option<PathBuf>,
     preprocessing_aggressive: bool,
+    include_nonlinear: bool,
+    // Glob patterns over spine hrefs: when includes are given only matching items
+    // are converted (linear="no" ones too); excludes drop items either way
+    include_hrefs: Vec<String>,
+    exclude_hrefs: Vec<String>,
+    output_policy: OutputPolicy,
+    html_wrap_width: usize,
+    speak_lists: bool,
+    announce_titles: bool,
+    // Append heading-less spine items to the previous chapter instead of "Chapter N"
+    merge_untitled_fragments: bool,
+    // Start a new chapter at every <hN> of this level, even inside one spine item
+    split_heading_level: Option<u8>,
+    // Also start chapters at epub:type="chapter" elements and page-break-before
+    // styles; --split-heading takes precedence when both are set
+    split_on_pagebreaks: bool,
+    // Also start a chapter at each line of the flattened text matching this
+    // regex, for books whose only chapter breaks are typed dividers
+    text_chapter_regex: Option<String>,
+    // Drop lines repeated at the top or bottom of most chapters (book title, author)
+    strip_running_headers: bool,
+    // Read em-dashes and parenthetical asides as short pauses
+    dash_to_pause: bool,
+    // Lengthens pauses at commas, sentence ends and paragraph ends, and the
+    // blockquote pause; 1.0 leaves the engine's own pauses alone
+    pause_scale: f32,
+    // Lowercase shouted all-caps words while leaving acronyms to be spelled out
+    normalize_caps: bool,
+    // Set blockquotes and epigraphs apart with a pause before and after
+    mark_blockquotes: bool,
+    // Read "Illustration" for sections that are only a picture instead of dropping them
+    announce_skipped_images: bool,
+    // Drop a leading "Chapter 1:", "IV." or "12" from headings; the number is announced anyway
+    clean_chapter_titles: bool,
+    // Fade-in at the start and fade-out at the end of each chapter
+    fade_ms: u32,
+    // How "#" is read: "number" or "hashtag"
+    hash_word: String,
+    // Trim each chunk's leading/trailing silence to a fixed pad before encoding
+    trim_chunk_silence: bool,
+    table_mode: TableMode,
+    image_alt: ImageMode,
+    ruby_mode: RubyMode,
+    tag_preset: TagPreset,
+    // Used instead of the EPUB's own title, author and series metadata
+    book_title: Option<String>,
+    book_author: Option<String>,
+    book_series: Option<String>,
+    playlist_format: PlaylistFormat,
+    // Also join every chapter into one book file with chapter markers
+    merge_output: bool,
+    // Replace each chapter's chunk files with one file, so players leave no gap
+    // between chunks
+    join_chunks: bool,
+    // Beside the merged file, list chapter start times as .chapters.txt and an
+    // Audiobookshelf metadata.json
+    chapters_sidecar: bool,
+    // Also regroup the audio into parts of about this many seconds
+    split_duration_secs: Option<u64>,
+    // Tag the output with ReplayGain track/album gain instead of touching samples
+    replaygain: bool,
+    // Save the cleaned text sent to TTS as a .txt beside each chapter's audio
+    emit_transcripts: bool,
+    // Record what the cleanup heuristics removed or rewrote in cleaning_report.json
+    audit: bool,
+    // Write per-chapter subtitles timed from the synthesized chunk lengths
+    subtitles: Option<SubtitleFormat>,
+    // Keep the WAV each chunk was encoded from beside the encoded file
+    keep_wav: bool,
+    // Extract and convert one chapter at a time instead of loading the whole book
+    low_memory: bool,
+    // Chapter numbers (as --dry-run shows them) to play first, in this order;
+    // the rest follow in spine order
+    chapter_order: Vec<usize>,
+    // Read before the first and after the last chapter, as chapters of their
+    // own; {title} and {author} are replaced from the EPUB metadata
+    intro_text: Option<String>,
+    outro_text: Option<String>,
+    // Chapter numbers (as --dry-run shows them) to convert; all when empty
+    only_chapters: Vec<usize>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -55,81 +171,895 @@ enum AudioFormat {
     Wav,
 }
 
+impl AudioFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            AudioFormat::Vorbis => "ogg",
+            AudioFormat::Flac => "flac",
+            AudioFormat::Mp3 => "mp3",
+            AudioFormat::Wav => "wav",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum TagPreset {
+    // Leave encoded files untagged
+    None,
+    // Chapters as tracks of one album, marked as an audiobook for Apple Books
+    Audiobook,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum PlaylistFormat {
+    M3u,
+    Pls,
+    Xspf,
+}
+
+impl PlaylistFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            PlaylistFormat::M3u => "m3u",
+            PlaylistFormat::Pls => "pls",
+            PlaylistFormat::Xspf => "xspf",
+        }
+    }
+}
+
+// Metadata written into each encoded file
+#[derive(Debug, Clone)]
+struct AudioTags {
+    title: String,
+    album: String,
+    artist: Option<String>,
+    series: Option<String>,
+    series_part: Option<String>,
+    // "Series NN - Title", so players list a series' books in reading order
+    album_sort: Option<String>,
+    track: usize,
+    total_tracks: usize,
+}
+
+impl AudioTags {
+    // Vorbis comment names, as used by oggenc -c and flac -T
+    fn vorbis_comments(&self) -> Vec<String> {
+        let mut comments = vec![
+            format!("TITLE={}", self.title),
+            format!("ALBUM={}", self.album),
+            format!("TRACKNUMBER={}", self.track),
+            format!("TRACKTOTAL={}", self.total_tracks),
+            "GENRE=Audiobook".to_string(),
+        ];
+        if let Some(artist) = &self.artist {
+            comments.push(format!("ARTIST={}", artist));
+            comments.push(format!("ALBUMARTIST={}", artist));
+        }
+        if let Some(series) = &self.series {
+            comments.push(format!("SERIES={}", series));
+        }
+        if let Some(part) = &self.series_part {
+            comments.push(format!("SERIESPART={}", part));
+        }
+        if let Some(album_sort) = &self.album_sort {
+            comments.push(format!("ALBUMSORT={}", album_sort));
+        }
+        comments
+    }
+
+    // ffmpeg -metadata keys; media_type 2 is the iTunes "stik" value for audiobooks
+    fn ffmpeg_metadata(&self) -> Vec<String> {
+        let mut metadata = vec![
+            format!("title={}", self.title),
+            format!("album={}", self.album),
+            format!("track={}/{}", self.track, self.total_tracks),
+            "genre=Audiobook".to_string(),
+            "media_type=2".to_string(),
+            "gapless_playback=1".to_string(),
+        ];
+        if let Some(artist) = &self.artist {
+            metadata.push(format!("artist={}", artist));
+            metadata.push(format!("album_artist={}", artist));
+        }
+        // Written as a SERIES comment, or a TXXX frame in MP3
+        if let Some(series) = &self.series {
+            metadata.push(format!("series={}", series));
+        }
+        if let Some(part) = &self.series_part {
+            metadata.push(format!("series-part={}", part));
+        }
+        // TSOA in MP3, soal in M4B
+        if let Some(album_sort) = &self.album_sort {
+            metadata.push(format!("album_sort={}", album_sort));
+        }
+        metadata
+    }
+
+    fn lame_args(&self) -> Vec<String> {
+        let mut args = vec![
+            "--add-id3v2".to_string(),
+            "--tt".to_string(),
+            self.title.clone(),
+            "--tl".to_string(),
+            self.album.clone(),
+            "--tn".to_string(),
+            format!("{}/{}", self.track, self.total_tracks),
+            "--tg".to_string(),
+            "Audiobook".to_string(),
+        ];
+        if let Some(artist) = &self.artist {
+            args.extend(["--ta".to_string(), artist.clone()]);
+            args.extend(["--tv".to_string(), format!("TPE2={}", artist)]);
+        }
+        if let Some(series) = &self.series {
+            args.extend(["--tv".to_string(), format!("TXXX=SERIES={}", series)]);
+        }
+        if let Some(part) = &self.series_part {
+            args.extend(["--tv".to_string(), format!("TXXX=SERIES-PART={}", part)]);
+        }
+        if let Some(album_sort) = &self.album_sort {
+            args.extend(["--tv".to_string(), format!("TSOA={}", album_sort)]);
+        }
+        args
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum TableMode {
+    // Drop tables from the narration
+    Skip,
+    // Read each row as "Column: value" sentences
+    Linearize,
+    // Leave html2text's ASCII rendering untouched
+    Keep,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum ImageMode {
+    // Drop images and their captions
+    Skip,
+    // Read each image's alt text as "Figure: ..."
+    Alt,
+    // Read <figcaption> text as "Figure: ..."
+    Caption,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum RubyMode {
+    // Read the annotated text itself, e.g. the kanji
+    Base,
+    // Read the <rt> annotation in its place, e.g. the furigana
+    Reading,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum SubtitleFormat {
+    Srt,
+    Vtt,
+}
+
+impl SubtitleFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            SubtitleFormat::Srt => "srt",
+            SubtitleFormat::Vtt => "vtt",
+        }
+    }
+
+    fn timestamp(&self, secs: f64) -> String {
+        let millis = (secs.max(0.0) * 1000.0).round() as u64;
+        let separator = match self {
+            SubtitleFormat::Srt => ',',
+            SubtitleFormat::Vtt => '.',
+        };
+        format!(
+            "{:02}:{:02}:{:02}{}{:03}",
+            millis / 3_600_000,
+            millis / 60_000 % 60,
+            millis / 1000 % 60,
+            separator,
+            millis % 1000
+        )
+    }
+
+    // Cues are (start, end, text) in seconds from the start of the chapter
+    fn render(&self, cues: &[(f64, f64, String)]) -> String {
+        let mut out = String::new();
+        if *self == SubtitleFormat::Vtt {
+            out.push_str("WEBVTT\n\n");
+        }
+        for (index, (start, end, text)) in cues.iter().enumerate() {
+            if *self == SubtitleFormat::Srt {
+                out.push_str(&format!("{}\n", index + 1));
+            }
+            out.push_str(&format!("{} --> {}\n{}\n\n", self.timestamp(*start), self.timestamp(*end), text));
+        }
+        out
+    }
+}
+
+// What to do when the output directory already has files in it
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum OutputPolicy {
+    Refuse,
+    Overwrite,
+    Resume,
+}
+
+// Per-chapter voice settings from a JSON sidecar keyed by chapter order, e.g.
+// {"2": {"voice_speed": 0.8}, "5": {"voice": "en+f3", "voice_pitch": 1.2}}
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct ChapterOverride {
+    voice: Option<String>,
+    voice_speed: Option<f32>,
+    voice_pitch: Option<f32>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+struct ChapterOverrides(HashMap<usize, ChapterOverride>);
+
+impl ChapterOverrides {
+    fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let content = fs::read_to_string(path)?;
+        serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid overrides file {}: {}", path.display(), e).into())
+    }
+
+    fn apply(&self, base: &Config, order: usize) -> Config {
+        let mut config = base.clone();
+        if let Some(overrides) = self.0.get(&order) {
+            if let Some(voice) = &overrides.voice {
+                config.voice = voice.clone();
+            }
+            if let Some(speed) = overrides.voice_speed {
+                config.voice_speed = speed;
+            }
+            if let Some(pitch) = overrides.voice_pitch {
+                config.voice_pitch = pitch;
+            }
+        }
+        config
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
             sample_rate: 22050,
+            channels: 1,
+            bits_per_sample: 16,
+            engine: "auto".to_string(),
+            espeak_data_path: None,
+            voice: "en".to_string(),
+            dialogue_voice: None,
             voice_speed: 1.0,
             voice_pitch: 1.0,
+            amplitude: 100,
             output_format: AudioFormat::Vorbis,
             quality: 0.7,
-            chunk_size: 1000,
+            bitrate_kbps: None,
+            max_chunk_size: 1000,
+            min_chunk_size: 0,
+            filename_template: DEFAULT_FILENAME_TEMPLATE.to_string(),
+            chapter_number_base: 0,
             max_workers: num_cpus::get(),
+            oversubscribe: false,
+            chunk_timeout_secs: None,
             cache_enabled: true,
+            cache_dir: None,
+            tmp_dir: None,
             preprocessing_aggressive: true,
+            include_nonlinear: false,
+            include_hrefs: Vec::new(),
+            exclude_hrefs: Vec::new(),
+            output_policy: OutputPolicy::Refuse,
+            // Effectively unwrapped: hard line breaks would split sentences
+            html_wrap_width: 10_000,
+            speak_lists: true,
+            announce_titles: true,
+            merge_untitled_fragments: false,
+            split_heading_level: None,
+            split_on_pagebreaks: false,
+            text_chapter_regex: None,
+            strip_running_headers: false,
+            dash_to_pause: false,
+            pause_scale: 1.0,
+            normalize_caps: false,
+            mark_blockquotes: false,
+            announce_skipped_images: false,
+            clean_chapter_titles: false,
+            fade_ms: 50,
+            hash_word: "number".to_string(),
+            trim_chunk_silence: false,
+            table_mode: TableMode::Linearize,
+            image_alt: ImageMode::Skip,
+            ruby_mode: RubyMode::Base,
+            tag_preset: TagPreset::None,
+            book_title: None,
+            book_author: None,
+            book_series: None,
+            playlist_format: PlaylistFormat::M3u,
+            merge_output: false,
+            join_chunks: false,
+            chapters_sidecar: false,
+            replaygain: false,
+            split_duration_secs: None,
+            emit_transcripts: false,
+            audit: false,
+            subtitles: None,
+            keep_wav: false,
+            low_memory: false,
+            chapter_order: Vec::new(),
+            intro_text: None,
+            outro_text: None,
+            only_chapters: Vec::new(),
+        }
+    }
+}
+
+const DEFAULT_FILENAME_TEMPLATE: &str = "{chunk}_{chapter_title}.{ext}";
+const FILENAME_PLACEHOLDERS: [&str; 6] = [
+    "chapter_order",
+    "chapter_title",
+    "chunk",
+    "book_title",
+    "author",
+    "ext",
+];
+
+// espeak's default speaking rate in words per minute at speed 1.0
+const BASE_WPM: f32 = 175.0;
+
+impl Config {
+    // Whether --chapters leaves this chapter in
+    fn converts_chapter(&self, order: usize) -> bool {
+        self.only_chapters.is_empty() || self.only_chapters.contains(&(order + self.chapter_number_base))
+    }
+
+    // Whether chunk files are later joined into bigger ones, which then needs
+    // the chunks' WAVs to encode from
+    fn merges_chunks(&self) -> bool {
+        self.merge_output || self.join_chunks || self.split_duration_secs.is_some()
+    }
+
+    // ffmpeg's PCM codec for the intermediate bit depth
+    fn pcm_codec(&self) -> &'static str {
+        match self.bits_per_sample {
+            24 => "pcm_s24le",
+            32 => "pcm_s32le",
+            _ => "pcm_s16le",
+        }
+    }
+
+    fn wav_spec(&self) -> hound::WavSpec {
+        hound::WavSpec {
+            channels: self.channels,
+            sample_rate: self.sample_rate,
+            bits_per_sample: self.bits_per_sample,
+            sample_format: hound::SampleFormat::Int,
         }
     }
+
+    // How the 0-1 quality maps onto each encoder: Vorbis -q 0-10, MP3 -V 9-0
+    // (lower is better), and for lossless FLAC the compression effort 0-8
+    fn vorbis_quality(&self) -> u32 {
+        (self.quality * 10.0) as u32
+    }
+
+    fn mp3_vbr_level(&self) -> u32 {
+        (9.0 - self.quality * 9.0) as u32
+    }
+
+    fn flac_compression_level(&self) -> u32 {
+        (self.quality * 8.0).round().clamp(0.0, 8.0) as u32
+    }
+
+    fn effective_wpm(&self) -> f32 {
+        self.voice_speed * BASE_WPM
+    }
+
+    // Chapter numbers as written to disk: offset by the base and zero-padded to
+    // at least three digits, wider when the book has more chapters than that
+    fn chapter_number(&self, order: usize, chapter_count: usize) -> String {
+        let last = (chapter_count + self.chapter_number_base).saturating_sub(1);
+        let width = last.to_string().len().max(3);
+        format!("{:0width$}", order + self.chapter_number_base, width = width)
+    }
+
+    // Chapter numbers as read in title announcements: the book's first chapter
+    // is Chapter 1 whatever the base, and an --intro does not count
+    fn spoken_chapter_number(&self, order: usize) -> usize {
+        let intro = self.intro_text.as_deref().is_some_and(|text| !text.trim().is_empty());
+        (order + 1).saturating_sub(intro as usize)
+    }
 }
 
 #[derive(Debug)]
 struct Chapter {
     title: String,
     content: String,
+    // Playback position, used for file names; differs from the spine
+    // position in `source_order` only when --order rearranges the book
+    order: usize,
+    source_order: usize,
+    word_count: usize,
+    // An --intro/--outro pseudo-chapter, read without a title announcement
+    bookend: bool,
+}
+
+impl Chapter {
+    // Spoken header read before the chapter body, e.g. "Chapter 3. The Return."
+    // "Chapter 3. The End."; a title that already carries a chapter number,
+    // such as "Chapter Three" or "Chapter 1: The Beginning", is read alone
+    fn title_announcement(&self, number: usize) -> String {
+        static NUMBERED: OnceLock<Regex> = OnceLock::new();
+        let numbered = NUMBERED.get_or_init(|| {
+            Regex::new(concat!(
+                r"(?i)^(?:chapter|ch\.)\s+(?:\d+|[ivxlcdm]+|one|two|three|four|five|six|seven|eight|nine|ten",
+                r"|eleven|twelve|thirteen|fourteen|fifteen|sixteen|seventeen|eighteen|nineteen",
+                r"|twenty|thirty|forty|fifty|sixty|seventy|eighty|ninety|hundred)\b",
+            ))
+            .unwrap()
+        });
+
+        let title = self.title.trim();
+        if numbered.is_match(title) {
+            with_terminal_punctuation(title)
+        } else {
+            format!("Chapter {}. {}", number, with_terminal_punctuation(title))
+        }
+    }
+
+    fn estimated_duration(&self, wpm: f32) -> Duration {
+        if wpm <= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(self.word_count as f64 * 60.0 / wpm as f64)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+struct BookMetadata {
+    title: String,
+    author: Option<String>,
+    series: Option<String>,
+    // Position in the series; calibre allows fractions such as 1.5
+    series_index: Option<f32>,
+}
+
+impl BookMetadata {
+    // Zero-padded series position, "02" or "01.5"
+    fn series_part(&self) -> Option<String> {
+        let index = self.series_index?;
+        Some(if index.fract() == 0.0 {
+            format!("{:02}", index as u32)
+        } else {
+            format!("{:04.1}", index)
+        })
+    }
+
+    // "Series NN - Title", or just "Series - Title" without a position
+    fn sort_title(&self) -> Option<String> {
+        let series = self.series.as_ref()?;
+        Some(match self.series_part() {
+            Some(part) => format!("{} {} - {}", series, part, self.title),
+            None => format!("{} - {}", series, self.title),
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ManifestChapter {
     order: usize,
+    title: String,
     word_count: usize,
+    characters: usize,
+    estimated_duration_secs: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    audio_duration_secs: Option<f64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    files: Vec<PathBuf>,
+}
+
+// Book-level summary written as manifest.json and used by the dry run
+#[derive(Debug, Serialize)]
+struct BookManifest {
+    title: String,
+    author: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    series: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    series_index: Option<f32>,
+    chapters: Vec<ManifestChapter>,
+    total_words: usize,
+    total_characters: usize,
+    estimated_duration_secs: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<ConversionSummary>,
+}
+
+impl ManifestChapter {
+    fn new(chapter: &Chapter, wpm: f32) -> Self {
+        Self {
+            order: chapter.order,
+            title: chapter.title.clone(),
+            word_count: chapter.word_count,
+            characters: chapter.content.chars().count(),
+            estimated_duration_secs: chapter.estimated_duration(wpm).as_secs_f64(),
+            audio_duration_secs: None,
+            files: Vec::new(),
+        }
+    }
+}
+
+impl BookManifest {
+    fn new(metadata: &BookMetadata, chapters: &[Chapter], wpm: f32) -> Self {
+        let chapters = chapters.iter().map(|chapter| ManifestChapter::new(chapter, wpm)).collect();
+        Self::from_outline(metadata, chapters)
+    }
+
+    fn from_outline(metadata: &BookMetadata, chapters: Vec<ManifestChapter>) -> Self {
+        Self {
+            title: metadata.title.clone(),
+            author: metadata.author.clone(),
+            series: metadata.series.clone(),
+            series_index: metadata.series_index,
+            total_words: chapters.iter().map(|c| c.word_count).sum(),
+            total_characters: chapters.iter().map(|c| c.characters).sum(),
+            estimated_duration_secs: chapters.iter().map(|c| c.estimated_duration_secs).sum(),
+            chapters,
+            summary: None,
+        }
+    }
+
+    fn record_outputs(&mut self, outputs: &[ChapterOutput], output_dir: &Path) {
+        for output in outputs {
+            if let Some(chapter) = self.chapters.iter_mut().find(|c| c.order == output.order) {
+                chapter.audio_duration_secs = Some(output.duration_secs());
+                chapter.files = output
+                    .files
+                    .iter()
+                    .map(|file| file.path.strip_prefix(output_dir).unwrap_or(&file.path).to_path_buf())
+                    .collect();
+            }
+        }
+    }
+
+    fn estimated_duration(&self) -> Duration {
+        Duration::from_secs_f64(self.estimated_duration_secs)
+    }
+
+    fn write(&self, output_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let file = File::create(output_dir.join("manifest.json"))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+        Ok(())
+    }
+}
+
+// What cleanup did to one chapter, as written by --audit. Counts are taken from
+// the text before cleaning, so they describe the source rather than the output
+#[derive(Debug, Default, Serialize)]
+struct ChapterAudit {
+    order: usize,
+    title: String,
+    // Heading before --clean-titles shortened it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    original_title: Option<String>,
+    // Spine items the chapter's text came from, fragments included
+    sources: Vec<String>,
+    running_header_lines: usize,
+    page_numbers: usize,
+    tables_removed: usize,
+    abbreviations_expanded: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct SkippedContent {
+    href: String,
+    reason: String,
+}
+
+// Written as cleaning_report.json, so a missing paragraph can be traced to a
+// heuristic rather than a bug
+#[derive(Debug, Default, Serialize)]
+struct CleaningReport {
+    running_headers: Vec<String>,
+    skipped: Vec<SkippedContent>,
+    chapters: Vec<ChapterAudit>,
+}
+
+impl CleaningReport {
+    fn skip(&mut self, href: &Path, reason: &str) {
+        let href = href.display().to_string();
+        // The running header pass reads every section a second time
+        if !self.skipped.iter().any(|s| s.href == href && s.reason == reason) {
+            self.skipped.push(SkippedContent { href, reason: reason.to_string() });
+        }
+    }
+
+    fn write(&self, output_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let file = File::create(output_dir.join("cleaning_report.json"))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+        Ok(())
+    }
+}
+
+// Zero-width characters, soft hyphens and word joiners are dropped; espeak either
+// voices them oddly or they split words. Non-breaking and typographic spaces
+// become plain spaces so whitespace normalization sees them.
+fn normalize_invisibles(text: &str) -> String {
+    text.chars()
+        .filter_map(|c| match c {
+            '\u{00AD}' | '\u{200B}' | '\u{200C}' | '\u{200D}' | '\u{2060}' | '\u{FEFF}' => None,
+            '\u{00A0}' | '\u{2000}'..='\u{200A}' | '\u{202F}' | '\u{205F}' | '\u{3000}' => Some(' '),
+            c => Some(c),
+        })
+        .collect()
+}
+
+// All-caps words espeak should keep spelling out or reading as acronyms
+const KNOWN_ACRONYMS: &[&str] = &[
+    "AI", "BBC", "CEO", "CIA", "CNN", "DNA", "EU", "FAQ", "FBI", "GPS", "ID", "IQ", "NASA",
+    "NATO", "NHS", "OK", "PC", "RAF", "TV", "UFO", "UK", "UN", "UNESCO", "UNICEF", "US",
+    "USA", "USSR", "WHO",
+];
+
+// A lone short all-caps word is most likely an acronym; longer ones, and every
+// word of a multi-word run, are emphasis and read better in lowercase. Known
+// acronyms and Roman numerals are kept either way.
+fn normalize_caps_run(run: &str, roman_numeral: &Regex) -> String {
+    let words: Vec<&str> = run.split_whitespace().collect();
+    words
+        .iter()
+        .map(|word| {
+            let keep = KNOWN_ACRONYMS.contains(word)
+                || roman_numeral.is_match(word)
+                || (words.len() == 1 && word.chars().count() <= 4);
+            if keep {
+                word.to_string()
+            } else {
+                word.to_lowercase()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// First halves that form hyphenated compounds ("self-aware", "well-known"),
+// and second halves that do ("sugar-free", "year-old"); a line break at such
+// a hyphen keeps it
+const COMPOUND_PREFIXES: &[&str] = &["all", "ex", "great", "half", "ill", "quasi", "self", "well"];
+const COMPOUND_SUFFIXES: &[&str] = &[
+    "based", "born", "free", "friendly", "like", "looking", "made", "minded", "old", "related", "style", "wide",
+    "year",
+];
+
+// Whether "first-\nsecond" is a real hyphen rather than one added by line
+// wrapping: compound halves, capitals ("anti-\nAmerican"), digits and single
+// letters ("e-\nmail") keep it, anything else is joined into one word
+fn keeps_line_break_hyphen(first: &str, second: &str) -> bool {
+    let first_lower = first.to_lowercase();
+    let second_lower = second.to_lowercase();
+    COMPOUND_PREFIXES.contains(&first_lower.as_str())
+        || COMPOUND_SUFFIXES.contains(&second_lower.as_str())
+        || second.starts_with(char::is_uppercase)
+        || first.chars().chain(second.chars()).any(|c| c.is_ascii_digit())
+        || first.chars().count() == 1
 }
 
 struct TextProcessor {
+    source_fix_regex: Vec<(Regex, &'static str)>,
     cleanup_regex: Vec<(Regex, &'static str)>,
     sentence_splitter: Regex,
+    page_artifact_regex: Regex,
+    hyphen_regex: Regex,
+    abbreviation_regex: Vec<(Regex, &'static str)>,
+    sentence_boundary_regex: Regex,
+    dash_pause_regex: Regex,
+    parenthetical_regex: Regex,
+    pause_collapse_regex: Regex,
+    period_run_regex: Regex,
+    dash_to_pause: bool,
+    pause_scale: f32,
+    paragraph_regex: Regex,
+    pause_point_regex: Regex,
+    caps_run_regex: Option<Regex>,
+    roman_numeral_regex: Regex,
+    symbol_regex: Vec<(Regex, String)>,
+    money_regex: Regex,
+    number_range_regex: Regex,
     word_cache: Arc<Mutex<LruCache<String, String>>>,
 }
 
 impl TextProcessor {
-    fn new() -> Self {
-        let cleanup_patterns = vec![
+    fn new(config: &Config) -> Self {
+        // Fixes to the source text itself, made before any later step reads it:
+        // a zero restored here would otherwise only become a number range or
+        // an amount on a second cleaning
+        let source_fix_patterns = vec![
             // Remove HTML entities and special characters
             (Regex::new(r"&[a-zA-Z0-9#]+;").unwrap(), " "),
-            // Normalize whitespace
-            (Regex::new(r"\s+").unwrap(), " "),
             // Fix common OCR errors
             (Regex::new(r"\bl\b").unwrap(), "I"), // lowercase L to I
             (Regex::new(r"\bO\b").unwrap(), "0"), // O to zero in numbers
-            // Remove page numbers and references
-            (Regex::new(r"\b[Pp]age\s+\d+\b").unwrap(), ""),
-            (Regex::new(r"\b\d+\s*[-–—]\s*\d+\b").unwrap(), ""),
-            // Fix quotation marks
-            (Regex::new(r"[""''`]").unwrap(), "\""),
-            // Normalize dashes
-            (Regex::new(r"[–—]").unwrap(), "-"),
-            // Remove multiple periods
-            (Regex::new(r"\.{3,}").unwrap(), "..."),
+        ];
+
+        let cleanup_patterns = vec![
+            // Normalize whitespace
+            (Regex::new(r"\s+").unwrap(), " "),
+            // Fix quotation marks: curly and low-9 double quotes, guillemets
+            (Regex::new("[\u{201C}\u{201D}\u{201E}\u{201F}\u{00AB}\u{00BB}]").unwrap(), "\""),
+            // Curly and low-9 single quotes, single angle quotes, backticks
+            (Regex::new("[\u{2018}\u{2019}\u{201A}\u{201B}\u{2039}\u{203A}`]").unwrap(), "'"),
+            // Normalize dashes: hyphen, non-breaking hyphen, figure dash, en/em dash, horizontal bar
+            (Regex::new("[\u{2010}\u{2011}\u{2012}\u{2013}\u{2014}\u{2015}]").unwrap(), "-"),
+            // Ellipsis character, collapsed with other periods below
+            (Regex::new("\u{2026}").unwrap(), "..."),
             // Fix spacing around punctuation
             (Regex::new(r"\s+([,.!?;:])").unwrap(), "$1"),
             (Regex::new(r"([,.!?;:])\s+").unwrap(), "$1 "),
         ];
 
+        // Common abbreviations that should be expanded for better TTS
+        let abbreviations = [
+            ("Mr.", "Mister"),
+            ("Mrs.", "Missus"),
+            ("Dr.", "Doctor"),
+            ("Prof.", "Professor"),
+            ("St.", "Saint"),
+            ("vs.", "versus"),
+            ("etc.", "etcetera"),
+            ("i.e.", "that is"),
+            ("e.g.", "for example"),
+        ];
+        let abbreviation_patterns = abbreviations
+            .iter()
+            .map(|(abbrev, expansion)| {
+                // A \b after the final "." would demand a letter right after it
+                let pattern = format!(r"\b{}", regex::escape(abbrev));
+                (Regex::new(&pattern).unwrap(), *expansion)
+            })
+            .collect();
+
         Self {
+            source_fix_regex: source_fix_patterns,
             cleanup_regex: cleanup_patterns,
             sentence_splitter: Regex::new(r"[.!?]+\s+").unwrap(),
+            // Lines holding nothing but a page number or page range ("12", "Page 12",
+            // "12-13", "Page 12 of 300"); ranges inside prose are left alone
+            page_artifact_regex: Regex::new(
+                "(?m)^[ \t]*(?:[Pp]age[ \t]+)?\\d{1,4}(?:[ \t]*[-\u{2013}\u{2014}][ \t]*\\d{1,4}|[ \t]+of[ \t]+\\d{1,4})?[ \t]*$",
+            )
+            .unwrap(),
+            // html2text renders <ul> items as "* item" and <ol> items as "1. item"
+            // Words split across lines
+            hyphen_regex: Regex::new(r"(\w+)-\s*\n\s*(\w+)").unwrap(),
+            abbreviation_regex: abbreviation_patterns,
+            sentence_boundary_regex: Regex::new(r"([.!?])\s*([A-Z])").unwrap(),
+            // Em-dash, horizontal bar, spaced en-dash or a double hyphen (of any
+            // hyphen-like characters, which the cleanup turns into "--") used as an aside
+            dash_pause_regex: Regex::new("\\s*[\u{2014}\u{2015}]\\s*|\\s+\u{2013}\\s+|\\s*[-\u{2010}-\u{2013}]{2,}\\s*").unwrap(),
+            // Mid-sentence parentheticals; ones holding whole sentences are left alone
+            parenthetical_regex: Regex::new(r"(\w)\s*\(([^().!?]+)\)").unwrap(),
+            // A pause running into other punctuation yields to it
+            pause_collapse_regex: Regex::new(r"(?:,\s*)+([,.!?;:])").unwrap(),
+            period_run_regex: Regex::new(r"\.{3,}").unwrap(),
+            dash_to_pause: config.dash_to_pause,
+            pause_scale: config.pause_scale,
+            // html2text separates paragraphs with a blank line
+            paragraph_regex: Regex::new(r"\n[ \t]*\n\s*").unwrap(),
+            // Clause punctuation, or a sentence end with any closing quote or bracket
+            pause_point_regex: Regex::new(r#"([,;:]|[.!?]+["')\]]*)(\s+)"#).unwrap(),
+            // Runs of all-caps words, e.g. "NASA", "THE END", "USA TODAY"
+            caps_run_regex: config
+                .normalize_caps
+                .then(|| Regex::new(r"\b[A-Z][A-Z']+\b(?:[ \t]+[A-Z][A-Z']+\b)*").unwrap()),
+            roman_numeral_regex: Regex::new(r"^M{0,3}(CM|CD|D?C{0,3})(XC|XL|L?X{0,3})(IX|IV|V?I{0,3})$").unwrap(),
+            // Symbols espeak reads by name or skips
+            symbol_regex: vec![
+                (Regex::new(r"\s*%\s*").unwrap(), " percent ".to_string()),
+                (Regex::new(r"\s*&\s*").unwrap(), " and ".to_string()),
+                (Regex::new(r"\s*@\s*").unwrap(), " at ".to_string()),
+                (Regex::new(r"#\s*").unwrap(), format!("{} ", config.hash_word)),
+            ],
+            money_regex: Regex::new(r"\$\s?(\d{1,3}(?:,\d{3})+|\d+)(?:\.(\d{2}))?").unwrap(),
+            // Whole hyphenated digit runs, so dates like 2024-05-01 can be told apart.
+            // Any dash and any spacing count, as the cleanup makes hyphens and
+            // spaces of them afterwards.
+            number_range_regex: Regex::new(r"\b\d+(?:\s*[-\u{2010}-\u{2015}]\s*\d+)+\b").unwrap(),
             word_cache: Arc::new(Mutex::new(LruCache::new(
                 NonZeroUsize::new(10000).unwrap(),
             ))),
         }
     }
 
+    // Rules can bring text together into something another rule matches ("&x ;"
+    // into an entity once the space goes), so cleaning repeats until the text
+    // settles; the pass limit is a guard against rules undoing each other
     fn clean_text(&self, text: &str, aggressive: bool) -> String {
-        let mut cleaned = text.to_string();
+        let mut cleaned = self.clean_pass(text, aggressive);
+        for _ in 0..4 {
+            let again = self.clean_pass(&cleaned, aggressive);
+            if again == cleaned {
+                break;
+            }
+            cleaned = again;
+        }
+        cleaned
+    }
+
+    fn clean_pass(&self, text: &str, aggressive: bool) -> String {
+        let mut cleaned = normalize_invisibles(text);
+
+        // Page furniture only shows as whole lines; whitespace normalization below
+        // joins the lines, so this goes first. Text without line breaks, such as
+        // the output of an earlier cleaning, has none left to find.
+        if cleaned.contains('\n') {
+            cleaned = self.page_artifact_regex.replace_all(&cleaned, "").to_string();
+        }
+
+        for (regex, replacement) in &self.source_fix_regex {
+            cleaned = regex.replace_all(&cleaned, *replacement).to_string();
+        }
+
+        // Words split across lines are only recognizable before the lines are joined.
+        // Abbreviations go before pauses, as their periods keep a parenthetical
+        // from becoming one.
+        if aggressive {
+            cleaned = self.fix_hyphenation(&cleaned);
+            cleaned = self.normalize_abbreviations(&cleaned);
+        }
+
+        // Paragraph breaks are mere whitespace, so they are marked to survive it
+        if self.pause_scale > 1.0 {
+            let mark = format!(" {} ", PARAGRAPH_MARK);
+            cleaned = self.paragraph_regex.replace_all(&cleaned, mark.as_str()).to_string();
+        }
+
+        // Turn dashes into commas before dashes are normalized away, and before
+        // the symbols, which would take a number either side for a range
+        if self.dash_to_pause {
+            cleaned = self.dash_pause_regex.replace_all(&cleaned, ", ").to_string();
+        }
+
+        // Symbols go before the cleanup below, which would drop number ranges entirely
+        if aggressive {
+            cleaned = self.normalize_symbols(&cleaned);
+        }
+
+        // After the symbols, so one after "%" follows the word "percent"; nested
+        // parentheses open up one level per pass
+        if self.dash_to_pause {
+            while self.parenthetical_regex.is_match(&cleaned) {
+                cleaned = self.parenthetical_regex.replace_all(&cleaned, "$1, $2,").to_string();
+            }
+        }
 
         // Apply basic cleanup patterns
         for (regex, replacement) in &self.cleanup_regex {
             cleaned = regex.replace_all(&cleaned, *replacement).to_string();
         }
 
+        // After the spacing fix, which can bring a pause up against punctuation
+        if self.dash_to_pause {
+            cleaned = self.pause_collapse_regex.replace_all(&cleaned, "$1").to_string();
+        }
+
+        // Remove multiple periods, including ones the spacing fix or a collapsed
+        // pause just joined
+        cleaned = self.period_run_regex.replace_all(&cleaned, "...").to_string();
+
+        if let Some(caps_run_regex) = &self.caps_run_regex {
+            cleaned = caps_run_regex
+                .replace_all(&cleaned, |caps: &regex::Captures| {
+                    normalize_caps_run(&caps[0], &self.roman_numeral_regex)
+                })
+                .to_string();
+        }
+
         if aggressive {
             // Additional aggressive cleaning
-            cleaned = self.fix_hyphenation(&cleaned);
-            cleaned = self.normalize_abbreviations(&cleaned);
             cleaned = self.fix_sentence_boundaries(&cleaned);
         }
 
@@ -137,729 +1067,8311 @@ impl TextProcessor {
         cleaned.trim().to_string()
     }
 
+    // The text handed to the engine. Weighting adds pauses every time it runs,
+    // so it stays out of clean_text, which leaves its own output unchanged.
+    fn speech_text(&self, text: &str, aggressive: bool) -> String {
+        let cleaned = self.clean_text(text, aggressive);
+        if self.pause_scale > 1.0 {
+            self.weight_pauses(&cleaned).trim().to_string()
+        } else {
+            cleaned
+        }
+    }
+
+    // espeak pauses on an ellipsis without reading it, so each extra unit of
+    // pause is one more: per unit of scale above 1.0, one after clause
+    // punctuation, two after a sentence and three at a paragraph end
+    fn weight_pauses(&self, text: &str) -> String {
+        let extra = |weight: f32| PAUSE_ELLIPSIS.repeat(((self.pause_scale - 1.0) * weight).round() as usize);
+        let text = self.pause_point_regex.replace_all(text, |caps: &regex::Captures| {
+            let weight = if caps[1].starts_with([',', ';', ':']) { 1.0 } else { 2.0 };
+            format!("{}{}{}", &caps[1], extra(weight), &caps[2])
+        });
+        // The sentence end before a paragraph mark has had its share already
+        let paragraph = format!("{} ", extra(1.0));
+        Regex::new(&format!(r"\s*{0}[\s{0}]*", PARAGRAPH_MARK))
+            .unwrap()
+            .replace_all(&text, paragraph.as_str())
+            .to_string()
+    }
+
     fn fix_hyphenation(&self, text: &str) -> String {
-        // Fix words split across lines
-        let hyphen_regex = Regex::new(r"(\w+)-\s*\n\s*(\w+)").unwrap();
-        hyphen_regex.replace_all(text, "$1$2").to_string()
+        // Fix words split across lines, keeping the hyphen of real compounds
+        self.hyphen_regex
+            .replace_all(text, |caps: &regex::Captures| {
+                if keeps_line_break_hyphen(&caps[1], &caps[2]) {
+                    format!("{}-{}", &caps[1], &caps[2])
+                } else {
+                    format!("{}{}", &caps[1], &caps[2])
+                }
+            })
+            .to_string()
+    }
+
+    fn count_page_artifacts(&self, text: &str) -> usize {
+        self.page_artifact_regex.find_iter(text).count()
+    }
+
+    fn count_abbreviations(&self, text: &str) -> usize {
+        self.abbreviation_regex.iter().map(|(regex, _)| regex.find_iter(text).count()).sum()
     }
 
     fn normalize_abbreviations(&self, text: &str) -> String {
         let mut result = text.to_string();
 
-        // Common abbreviations that should be expanded for better TTS
-        let abbreviations = vec![
-            ("Mr.", "Mister"),
-            ("Mrs.", "Missus"),
-            ("Dr.", "Doctor"),
-            ("Prof.", "Professor"),
-            ("St.", "Saint"),
-            ("vs.", "versus"),
-            ("etc.", "etcetera"),
-            ("i.e.", "that is"),
-            ("e.g.", "for example"),
-        ];
-
-        for (abbrev, expansion) in abbreviations {
-            let pattern = format!(r"\b{}\b", regex::escape(abbrev));
-            let regex = Regex::new(&pattern).unwrap();
-            result = regex.replace_all(&result, expansion).to_string();
+        for (regex, expansion) in &self.abbreviation_regex {
+            result = regex.replace_all(&result, *expansion).to_string();
         }
 
         result
     }
 
-    fn fix_sentence_boundaries(&self, text: &str) -> String {
-        // Ensure proper spacing after sentence endings
-        let sentence_regex = Regex::new(r"([.!?])\s*([A-Z])").unwrap();
-        sentence_regex.replace_all(text, "$1 $2").to_string()
-    }
-
-    fn split_into_chunks(&self, text: &str, chunk_size: usize) -> Vec<String> {
-        let sentences: Vec<&str> = self.sentence_splitter.split(text).collect();
-        let mut chunks = Vec::new();
+    fn normalize_symbols(&self, text: &str) -> String {
+        // "$1,250.50" -> "one thousand two hundred fifty dollars and fifty cents"
+        let text = self.money_regex.replace_all(text, |caps: &regex::Captures| {
+            let dollars = match caps[1].replace(',', "").parse::<u64>() {
+                Ok(dollars) => dollars,
+                Err(_) => return caps[0].to_string(),
+            };
+            let mut spoken = format!(
+                "{} {}",
+                number_to_words(dollars),
+                if dollars == 1 { "dollar" } else { "dollars" }
+            );
+            if let Some(cents) = caps.get(2).and_then(|cents| cents.as_str().parse::<u64>().ok()) {
+                if cents > 0 {
+                    spoken.push_str(&format!(
+                        " and {} {}",
+                        number_to_words(cents),
+                        if cents == 1 { "cent" } else { "cents" }
+                    ));
+                }
+            }
+            spoken
+        });
+
+        // "1914-1918" -> "1914 to 1918": the digits stay, so espeak still reads
+        // years as years. Longer runs (dates, phone numbers) are left alone.
+        let text = self.number_range_regex.replace_all(&text, |caps: &regex::Captures| {
+            let parts: Vec<&str> = caps[0]
+                .split(|c: char| c == '-' || ('\u{2010}'..='\u{2015}').contains(&c))
+                .map(str::trim)
+                .collect();
+            match parts.as_slice() {
+                [from, to] if from.parse::<u64>().is_ok() && to.parse::<u64>().is_ok() => format!("{} to {}", from, to),
+                _ => caps[0].to_string(),
+            }
+        });
+
+        let mut result = text.to_string();
+        for (regex, replacement) in &self.symbol_regex {
+            result = regex.replace_all(&result, replacement.as_str()).to_string();
+        }
+
+        result
+    }
+
+    fn fix_sentence_boundaries(&self, text: &str) -> String {
+        // Ensure proper spacing after sentence endings
+        self.sentence_boundary_regex.replace_all(text, "$1 $2").to_string()
+    }
+
+    // Sentences with their closing punctuation, for subtitle cues
+    fn sentences<'a>(&self, text: &'a str) -> Vec<&'a str> {
+        let mut sentences = Vec::new();
+        let mut start = 0;
+        for boundary in self.sentence_splitter.find_iter(text) {
+            sentences.push(text[start..boundary.end()].trim());
+            start = boundary.end();
+        }
+        sentences.push(text[start..].trim());
+        sentences.retain(|sentence| !sentence.is_empty());
+        sentences
+    }
+
+    fn split_into_chunks(&self, text: &str, min_size: usize, max_size: usize) -> Vec<String> {
+        // Sentences keep their closing punctuation, which espeak pauses on and
+        // subtitle cues split at
+        let sentences = self.sentences(text);
+        let mut chunks = Vec::new();
         let mut current_chunk = String::new();
         let mut current_length = 0;
 
         for sentence in sentences {
-            let sentence_length = sentence.len();
+            // A "sentence" without terminal punctuation can be arbitrarily long
+            let pieces = if sentence.len() > max_size {
+                self.split_long_segment(sentence, max_size)
+            } else {
+                vec![sentence.to_string()]
+            };
 
-            if current_length + sentence_length > chunk_size && !current_chunk.is_empty() {
-                chunks.push(current_chunk.trim().to_string());
-                current_chunk.clear();
-                current_length = 0;
-            }
+            for piece in pieces {
+                let piece_length = piece.len();
+
+                if current_length + piece_length > max_size && !current_chunk.is_empty() {
+                    chunks.push(current_chunk.trim().to_string());
+                    current_chunk.clear();
+                    current_length = 0;
+                }
 
-            current_chunk.push_str(sentence);
-            current_chunk.push(' ');
-            current_length += sentence_length + 1;
+                current_chunk.push_str(&piece);
+                current_chunk.push(' ');
+                current_length += piece_length + 1;
+            }
         }
 
         if !current_chunk.trim().is_empty() {
             chunks.push(current_chunk.trim().to_string());
         }
 
-        chunks
+        if min_size > 0 {
+            self.merge_small_chunks(chunks, min_size, max_size)
+        } else {
+            chunks
+        }
     }
-}
-
-struct TTSEngine {
-    config: Config,
-    cache_dir: PathBuf,
-}
 
-impl TTSEngine {
-    fn new(config: Config) -> Result<Self, Box<dyn std::error::Error>> {
-        let cache_dir = PathBuf::from("./tts_cache");
-        if config.cache_enabled {
-            fs::create_dir_all(&cache_dir)?;
+    // A chunk under min_size joins the next chunk or the previous one if the
+    // two fit in max_size. A short tail usually fits neither, since the chunk
+    // before it was closed for being full, so sentences move over to it instead.
+    fn merge_small_chunks(&self, chunks: Vec<String>, min_size: usize, max_size: usize) -> Vec<String> {
+        let mut merged: Vec<String> = Vec::with_capacity(chunks.len());
+        let mut chunks = chunks.into_iter().peekable();
+        while let Some(mut chunk) = chunks.next() {
+            if chunk.len() < min_size {
+                if let Some(next) = chunks.peek_mut() {
+                    if chunk.len() + 1 + next.len() <= max_size {
+                        *next = format!("{} {}", chunk, next);
+                        continue;
+                    }
+                }
+                if let Some(previous) = merged.last_mut() {
+                    if previous.len() + 1 + chunk.len() <= max_size {
+                        previous.push(' ');
+                        previous.push_str(&chunk);
+                        continue;
+                    }
+                    chunk = self.move_sentences(previous, chunk, min_size, max_size);
+                }
+            }
+            merged.push(chunk);
         }
+        merged
+    }
 
-        Ok(Self { config, cache_dir })
+    // Moves whole sentences from the end of `previous` to the front of `short`
+    // until it reaches min_size, keeping both chunks within the bounds
+    fn move_sentences(&self, previous: &mut String, short: String, min_size: usize, max_size: usize) -> String {
+        let sentences = self.sentences(previous);
+        let mut split = sentences.len();
+        let mut moved = short.len();
+        let mut kept = previous.len();
+        while split > 1 && moved < min_size {
+            let length = sentences[split - 1].len() + 1;
+            if moved + length > max_size || kept.saturating_sub(length) < min_size {
+                break;
+            }
+            moved += length;
+            kept -= length;
+            split -= 1;
+        }
+        if split == sentences.len() {
+            return short;
+        }
+        let short = format!("{} {}", sentences[split..].join(" "), short);
+        *previous = sentences[..split].join(" ");
+        short
     }
 
-    fn text_to_speech(
-        &self,
-        text: &str,
-        output_path: &Path,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        // Generate cache key
-        let cache_key = if self.config.cache_enabled {
-            let mut hasher = Sha256::new();
-            hasher.update(text.as_bytes());
-            hasher.update(&self.config.voice_speed.to_be_bytes());
-            hasher.update(&self.config.voice_pitch.to_be_bytes());
-            hasher.update(&self.config.sample_rate.to_be_bytes());
-            Some(format!("{:x}", hasher.finalize()))
-        } else {
-            None
-        };
+    fn split_long_segment(&self, segment: &str, limit: usize) -> Vec<String> {
+        let limit = limit.max(1);
+        let mut pieces = Vec::new();
+        let mut current = String::new();
 
-        // Check cache
-        if let Some(ref key) = cache_key {
-            let cache_path = self.cache_dir.join(format!("{}.wav", key));
-            if cache_path.exists() {
-                return self.convert_audio(&cache_path, output_path);
+        for word in segment.split_whitespace() {
+            if !current.is_empty() && current.len() + 1 + word.len() > limit {
+                pieces.push(std::mem::take(&mut current));
+            }
+
+            if word.len() > limit {
+                // No word boundary to use, so cut on character boundaries
+                let mut rest = word;
+                while rest.len() > limit {
+                    let mut cut = limit;
+                    while !rest.is_char_boundary(cut) {
+                        cut -= 1;
+                    }
+                    if cut == 0 {
+                        cut = rest.chars().next().map(char::len_utf8).unwrap_or(rest.len());
+                    }
+                    pieces.push(rest[..cut].to_string());
+                    rest = &rest[cut..];
+                }
+                current = rest.to_string();
+                continue;
+            }
+
+            if !current.is_empty() {
+                current.push(' ');
             }
+            current.push_str(word);
         }
 
-        // Generate speech using espeak-ng (highly optimized CPU-based TTS)
-        let temp_wav = if let Some(ref key) = cache_key {
-            self.cache_dir.join(format!("{}.wav", key))
-        } else {
-            tempfile::NamedTempFile::new()?.into_temp_path().to_path_buf()
-        };
+        if !current.is_empty() {
+            pieces.push(current);
+        }
 
-        // Check for available TTS engines on Arch Linux
-        let tts_command = self.detect_tts_engine()?;
+        pieces
+    }
+}
 
-        let espeak_output = match tts_command.as_str() {
-            "espeak-ng" => self.run_espeak_ng(text)?,
-            "espeak" => self.run_espeak(text)?,
-            "festival" => self.run_festival(text)?,
-            _ => return Err("No suitable TTS engine found".into()),
-        };
+// $XDG_CACHE_HOME/ebuptts or ~/.cache/ebuptts, so the cache does not depend on
+// the directory the tool is run from
+fn default_cache_dir() -> PathBuf {
+    std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_else(std::env::temp_dir)
+        .join("ebuptts")
+}
+const CACHE_INDEX_FILE: &str = "cache_index.json";
 
-        if !espeak_output.status.success() {
-            return Err(format!("TTS generation failed with {}", tts_command).into());
-        }
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct CacheEntry {
+    last_access: u64,
+    books: BTreeSet<String>,
+    // SHA-256 of the WAV as written; entries from older versions have none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
+}
+
+// Records which books use each cached chunk so a single book's cache can be dropped
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheIndex {
+    entries: HashMap<String, CacheEntry>,
+}
 
-        // Write raw audio to temp file
-        fs::write(&temp_wav, &espeak_output.stdout)?;
+impl CacheIndex {
+    fn load(cache_dir: &Path) -> Self {
+        fs::read_to_string(cache_dir.join(CACHE_INDEX_FILE))
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
 
-        // Convert to target format
-        self.convert_audio(&temp_wav, output_path)?;
+    fn save(&self, cache_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let file = File::create(cache_dir.join(CACHE_INDEX_FILE))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+        Ok(())
+    }
 
-        // Clean up temp file if not cached
-        if cache_key.is_none() {
-            let _ = fs::remove_file(&temp_wav);
+    fn add(&mut self, key: &str, book: Option<&str>) {
+        let entry = self.entries.entry(key.to_string()).or_default();
+        entry.last_access = unix_timestamp();
+        if let Some(book) = book {
+            entry.books.insert(book.to_string());
         }
+    }
 
-        Ok(())
+    fn checksum(&self, key: &str) -> Option<String> {
+        self.entries.get(key).and_then(|entry| entry.sha256.clone())
     }
 
-    fn detect_tts_engine(&self) -> Result<String, Box<dyn std::error::Error>> {
-        let engines = ["espeak-ng", "espeak", "festival"];
+    fn set_checksum(&mut self, key: &str, sha256: String) {
+        if let Some(entry) = self.entries.get_mut(key) {
+            entry.sha256 = Some(sha256);
+        }
+    }
 
-        for engine in &engines {
-            if ProcessCommand::new("which")
-                .arg(engine)
-                .output()
-                .map(|output| output.status.success())
-                .unwrap_or(false)
-                {
-                    return Ok(engine.to_string());
+    // Returns the keys whose cache files are no longer referenced. With a book,
+    // only entries used solely by that book are released.
+    fn prune(&mut self, book: Option<&str>) -> Vec<String> {
+        let mut released = Vec::new();
+
+        self.entries.retain(|key, entry| {
+            if let Some(book) = book {
+                if !entry.books.remove(book) || !entry.books.is_empty() {
+                    return true;
                 }
-        }
+            }
+            released.push(key.clone());
+            false
+        });
 
-        Err("No TTS engine found. Please install espeak-ng, espeak, or festival".into())
+        released
     }
+}
 
-    fn run_espeak_ng(&self, text: &str) -> Result<std::process::Output, Box<dyn std::error::Error>> {
-        let mut cmd = ProcessCommand::new("espeak-ng");
-        cmd.arg("-v")
-        .arg("en")
-        .arg("-s")
-        .arg(format!("{}", (self.config.voice_speed * 175.0) as u32))
-        .arg("-p")
-        .arg(format!("{}", (self.config.voice_pitch * 50.0) as u32))
-        .arg("-a")
-        .arg("100")
-        .arg("--stdout")
-        .arg(text)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::null());
+// Output path standing for stdout, as in `--stdout`
+const STDOUT_PATH: &str = "-";
 
-        Ok(cmd.output()?)
-    }
-
-    fn run_espeak(&self, text: &str) -> Result<std::process::Output, Box<dyn std::error::Error>> {
-        let mut cmd = ProcessCommand::new("espeak");
-        cmd.arg("-v")
-        .arg("en")
-        .arg("-s")
-        .arg(format!("{}", (self.config.voice_speed * 175.0) as u32))
-        .arg("-p")
-        .arg(format!("{}", (self.config.voice_pitch * 50.0) as u32))
-        .arg("-a")
-        .arg("100")
-        .arg("--stdout")
-        .arg(text)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::null());
+fn is_stdout(path: &Path) -> bool {
+    path == Path::new(STDOUT_PATH)
+}
 
-        Ok(cmd.output()?)
+// Runs an encoder; when it writes to stdout its output is passed straight through
+// rather than captured. Streaming encodes a whole chapter, so `timeout_secs`
+// only bounds encodes to a file.
+fn run_encoder(cmd: &mut ProcessCommand, output_path: &Path, timeout_secs: Option<u64>) -> Result<bool, Box<dyn std::error::Error>> {
+    if is_stdout(output_path) {
+        Ok(cmd.stdin(Stdio::null()).stdout(Stdio::inherit()).stderr(Stdio::null()).status()?.success())
+    } else {
+        Ok(output_with_timeout(cmd.stderr(Stdio::null()), None, timeout_secs)?.status.success())
     }
+}
 
-    fn run_festival(&self, text: &str) -> Result<std::process::Output, Box<dyn std::error::Error>> {
-        let mut cmd = ProcessCommand::new("festival");
-        cmd.arg("--tts")
-        .arg("--pipe")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::null());
+// A process killed for running past --chunk-timeout
+#[derive(Debug)]
+struct TimedOut {
+    program: String,
+    secs: u64,
+}
 
-        let mut child = cmd.spawn()?;
+impl std::fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} killed after {}s (--chunk-timeout)", self.program, self.secs)
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+// Like `Command::output`, feeding `input` on stdin and killing the process once
+// it runs past `timeout_secs`. Stdin and stdout are serviced on their own
+// threads so a full pipe never stalls the wait. Stderr is left as the caller set it.
+fn output_with_timeout(
+    cmd: &mut ProcessCommand,
+    input: Option<&[u8]>,
+    timeout_secs: Option<u64>,
+) -> Result<std::process::Output, Box<dyn std::error::Error>> {
+    cmd.stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+    .stdout(Stdio::piped());
+    let program = cmd.get_program().to_string_lossy().into_owned();
+    let mut child = cmd.spawn()?;
+    let stdin = child.stdin.take();
+    let stdout = child.stdout.take();
 
-        if let Some(stdin) = child.stdin.as_mut() {
-            stdin.write_all(text.as_bytes())?;
+    std::thread::scope(|scope| {
+        if let (Some(mut stdin), Some(input)) = (stdin, input) {
+            // Dropping stdin once written closes it, as the engine expects
+            scope.spawn(move || {
+                let _ = stdin.write_all(input);
+            });
         }
+        let reader = scope.spawn(move || {
+            let mut buffer = Vec::new();
+            if let Some(mut stdout) = stdout {
+                let _ = stdout.read_to_end(&mut buffer);
+            }
+            buffer
+        });
+
+        let status = match timeout_secs {
+            None => child.wait()?,
+            Some(secs) => {
+                let deadline = Instant::now() + Duration::from_secs(secs);
+                loop {
+                    if let Some(status) = child.try_wait()? {
+                        break status;
+                    }
+                    if Instant::now() >= deadline {
+                        // Killing closes the pipes, which ends both threads
+                        let _ = child.kill();
+                        let _ = child.wait();
+                        return Err(TimedOut { program, secs }.into());
+                    }
+                    std::thread::sleep(Duration::from_millis(50));
+                }
+            }
+        };
+        Ok(std::process::Output {
+            status,
+            stdout: reader.join().unwrap_or_default(),
+            stderr: Vec::new(),
+        })
+    })
+}
 
-        Ok(child.wait_with_output()?)
+// Chunk text shortened for a log line
+fn log_excerpt(text: &str) -> String {
+    let flat = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match flat.char_indices().nth(80) {
+        Some((end, _)) => format!("{}…", &flat[..end]),
+        None => flat,
     }
+}
 
-    fn convert_audio(
-        &self,
-        input_path: &Path,
-        output_path: &Path,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        match self.config.output_format {
-            AudioFormat::Vorbis => self.convert_to_vorbis(input_path, output_path),
-            AudioFormat::Flac => self.convert_to_flac(input_path, output_path),
-            AudioFormat::Mp3 => self.convert_to_mp3(input_path, output_path),
-            AudioFormat::Wav => {
-                fs::copy(input_path, output_path)?;
-                Ok(())
+// ffmpeg cannot guess the container from "pipe:1", so it is named explicitly
+fn ffmpeg_output(cmd: &mut ProcessCommand, output_path: &Path, muxer: &str) {
+    if is_stdout(output_path) {
+        cmd.arg("-f").arg(muxer).arg("pipe:1");
+    } else {
+        cmd.arg(output_path);
+    }
+}
+
+// Canonical in-memory WAVs joined into one
+fn join_wavs(parts: &[Vec<u8>], spec: hound::WavSpec) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut output = Vec::new();
+    {
+        let mut writer = hound::WavWriter::new(std::io::Cursor::new(&mut output), spec)?;
+        for part in parts {
+            let mut reader = hound::WavReader::new(std::io::Cursor::new(part))?;
+            for sample in reader.samples::<i32>().map_while(Result::ok) {
+                writer.write_sample(sample)?;
             }
         }
+        writer.finalize()?;
     }
+    Ok(output)
+}
 
-    fn convert_to_vorbis(
-        &self,
-        input_path: &Path,
-        output_path: &Path,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        // Try oggenc first (preferred), then ffmpeg as fallback
-        let encoders = ["oggenc", "ffmpeg"];
+// Appends WAV files that share one spec (the canonical chunk format) into one
+fn concat_wavs(inputs: &[PathBuf], output: &Path, spec: hound::WavSpec) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = hound::WavWriter::create(output, spec)?;
+    for input in inputs {
+        let mut reader = hound::WavReader::open(input)?;
+        if reader.spec() != spec {
+            return Err(format!("{} does not match the chunk audio format", input.display()).into());
+        }
+        for sample in reader.samples::<i32>().map_while(Result::ok) {
+            writer.write_sample(sample)?;
+        }
+    }
+    writer.finalize()?;
+    Ok(())
+}
 
-        for encoder in &encoders {
-            if ProcessCommand::new("which")
-                .arg(encoder)
-                .output()
-                .map(|output| output.status.success())
-                .unwrap_or(false)
-                {
-                    return match *encoder {
-                        "oggenc" => self.encode_with_oggenc(input_path, output_path),
-                        "ffmpeg" => self.encode_vorbis_with_ffmpeg(input_path, output_path),
-                        _ => continue,
-                    };
+// espeak streams WAV to stdout with a placeholder data length, so the
+// sample count is bounded by what is actually in the file
+fn wav_duration_secs(path: &Path) -> Result<f64, Box<dyn std::error::Error>> {
+    let reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    let block_align = (spec.channels as u64 * spec.bits_per_sample as u64 / 8).max(1);
+    let data_frames = fs::metadata(path)?.len().saturating_sub(44) / block_align;
+    let frames = (reader.duration() as u64).min(data_frames);
+
+    Ok(frames as f64 / spec.sample_rate.max(1) as f64)
+}
+
+// Wraps headerless little-endian samples in a WAV header; 8-bit ones are
+// unsigned, as in WAV
+fn pcm_to_wav(data: &[u8], spec: hound::WavSpec) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let width = (spec.bits_per_sample as usize).div_ceil(8);
+    let supported = match spec.sample_format {
+        hound::SampleFormat::Int => (1..=4).contains(&width),
+        hound::SampleFormat::Float => width == 4,
+    };
+    if !supported || spec.channels == 0 {
+        return Err(format!("unsupported PCM layout: {:?}", spec).into());
+    }
+
+    let mut wav = Vec::new();
+    {
+        let mut writer = hound::WavWriter::new(std::io::Cursor::new(&mut wav), spec)?;
+        for bytes in data.chunks_exact(width) {
+            let mut le = [0u8; 4];
+            le[..width].copy_from_slice(bytes);
+            match spec.sample_format {
+                hound::SampleFormat::Float => writer.write_sample(f32::from_le_bytes(le))?,
+                hound::SampleFormat::Int if width == 1 => writer.write_sample(le[0] as i32 - 128)?,
+                hound::SampleFormat::Int => {
+                    // Sign-extend from the sample's own width
+                    let shift = 32 - 8 * width as u32;
+                    writer.write_sample(i32::from_le_bytes(le) << shift >> shift)?
                 }
+            }
         }
-
-        Err("No Vorbis encoder found. Please install vorbis-tools or ffmpeg".into())
+        writer.finalize()?;
     }
+    Ok(wav)
+}
 
-    fn encode_with_oggenc(
-        &self,
-        input_path: &Path,
-        output_path: &Path,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut cmd = ProcessCommand::new("oggenc");
-        cmd.arg("-q")
-        .arg(format!("{}", (self.config.quality * 10.0) as u32))
-        .arg("-o")
-        .arg(output_path)
-        .arg(input_path);
+// Splits a chunk at straight double quotes (curly ones are normalized to these
+// during cleanup) into narration and dialogue spans, in reading order. A quote
+// left open runs to the end of the chunk, as in multi-paragraph dialogue.
+fn split_dialogue(text: &str) -> Vec<(bool, &str)> {
+    text.split('"')
+        .enumerate()
+        .map(|(index, span)| (index % 2 == 1, span.trim()))
+        .filter(|(_, span)| span.chars().any(char::is_alphanumeric))
+        .collect()
+}
 
-        let output = cmd.output()?;
-        if !output.status.success() {
-            return Err("oggenc encoding failed".into());
+// Converts engine output to the configured intermediate format (channels, bit
+// depth, sample rate) so chunks from any engine can be cached, faded and merged alike
+fn to_canonical_wav(wav: &[u8], target: hound::WavSpec) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut reader = hound::WavReader::new(std::io::Cursor::new(wav))?;
+    let source = reader.spec();
+
+    // Same format: copy the samples anyway so the header's data length is
+    // rewritten to match (espeak leaves a placeholder)
+    if source == target {
+        let mut output = Vec::new();
+        {
+            let mut writer = hound::WavWriter::new(std::io::Cursor::new(&mut output), target)?;
+            for sample in reader.samples::<i32>().map_while(Result::ok) {
+                writer.write_sample(sample)?;
+            }
+            writer.finalize()?;
         }
-        Ok(())
+        return Ok(output);
     }
 
-    fn encode_vorbis_with_ffmpeg(
-        &self,
-        input_path: &Path,
-        output_path: &Path,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut cmd = ProcessCommand::new("ffmpeg");
-        cmd.arg("-i")
-        .arg(input_path)
-        .arg("-c:a")
-        .arg("libvorbis")
-        .arg("-q:a")
-        .arg(format!("{}", (self.config.quality * 10.0) as u32))
-        .arg("-y")
-        .arg(output_path);
+    // Decode to f32 in [-1, 1], stopping at the real end of data
+    let samples: Vec<f32> = match source.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().map_while(Result::ok).collect(),
+        hound::SampleFormat::Int => {
+            let scale = (1i64 << (source.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map_while(Result::ok)
+                .map(|sample| sample as f32 / scale)
+                .collect()
+        }
+    };
 
-        let output = cmd.output()?;
-        if !output.status.success() {
-            return Err("ffmpeg Vorbis encoding failed".into());
+    // Down-mix to mono, then spread to the target channel count
+    let source_channels = source.channels.max(1) as usize;
+    let mono: Vec<f32> = samples
+        .chunks(source_channels)
+        .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+        .collect();
+
+    // Linear-interpolation resampling
+    let mono = if source.sample_rate == target.sample_rate || mono.is_empty() {
+        mono
+    } else {
+        let ratio = source.sample_rate as f64 / target.sample_rate as f64;
+        let frames = (mono.len() as f64 / ratio) as usize;
+        (0..frames)
+            .map(|index| {
+                let position = index as f64 * ratio;
+                let base = position as usize;
+                let next = (base + 1).min(mono.len() - 1);
+                let fraction = (position - base as f64) as f32;
+                mono[base] * (1.0 - fraction) + mono[next] * fraction
+            })
+            .collect()
+    };
+
+    let max = ((1i64 << (target.bits_per_sample - 1)) - 1) as f32;
+    let mut output = Vec::new();
+    {
+        let mut writer = hound::WavWriter::new(std::io::Cursor::new(&mut output), target)?;
+        for sample in mono {
+            let value = (sample.clamp(-1.0, 1.0) * max).round() as i32;
+            for _ in 0..target.channels {
+                writer.write_sample(value)?;
+            }
         }
-        Ok(())
+        writer.finalize()?;
     }
 
-    fn convert_to_flac(
-        &self,
-        input_path: &Path,
-        output_path: &Path,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let encoders = ["flac", "ffmpeg"];
+    Ok(output)
+}
 
-        for encoder in &encoders {
-            if ProcessCommand::new("which")
-                .arg(encoder)
-                .output()
-                .map(|output| output.status.success())
-                .unwrap_or(false)
-                {
-                    return match *encoder {
-                        "flac" => self.encode_with_flac(input_path, output_path),
-                        "ffmpeg" => self.encode_flac_with_ffmpeg(input_path, output_path),
-                        _ => continue,
-                    };
-                }
-        }
+fn sha256_file(path: &Path) -> Result<String, Box<dyn std::error::Error>> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
 
-        Err("No FLAC encoder found. Please install flac or ffmpeg".into())
+// Cached audio must be complete, decodable PCM in the current canonical format;
+// anything else (an interrupted write, a cache from other settings or a
+// nonstandard espeak build) is re-synthesized rather than encoded
+fn check_cached_wav(path: &Path, spec: hound::WavSpec) -> Result<(), Box<dyn std::error::Error>> {
+    let reader = hound::WavReader::open(path)?;
+    if reader.spec() != spec {
+        return Err(format!("format {:?} does not match {:?}", reader.spec(), spec).into());
     }
+    let declared = reader.len() as u64 * (spec.bits_per_sample as u64 / 8);
+    let available = fs::metadata(path)?.len().saturating_sub(44);
+    if available < declared {
+        return Err(format!("truncated ({} of {} data bytes)", available, declared).into());
+    }
+    drop(reader);
 
-    fn encode_with_flac(
-        &self,
-        input_path: &Path,
-        output_path: &Path,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut cmd = ProcessCommand::new("flac");
-        cmd.arg("--compression-level-8")
-        .arg("-o")
-        .arg(output_path)
-        .arg(input_path);
+    decode_audio_stats(path)?;
+    Ok(())
+}
 
-        let output = cmd.output()?;
-        if !output.status.success() {
-            return Err("FLAC encoding failed".into());
-        }
-        Ok(())
+// Samples quieter than this fraction of full scale count as silence
+const SILENCE_THRESHOLD: f32 = 0.01;
+// Silence kept at each end of a trimmed chunk, so joined chunks keep a natural gap
+const SILENCE_KEEP_MS: u64 = 60;
+// Short ramp at the cut points so trimmed edges cannot click
+const TRIM_RAMP_MS: u64 = 5;
+
+// Cuts espeak's variable leading/trailing silence down to a fixed pad so chunks
+// concatenate without uneven gaps or seams
+fn trim_silence(input: &Path, output: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut reader = hound::WavReader::open(input)?;
+    let spec = reader.spec();
+    if spec.sample_format != hound::SampleFormat::Int {
+        fs::copy(input, output)?;
+        return Ok(());
     }
 
-    fn encode_flac_with_ffmpeg(
-        &self,
-        input_path: &Path,
-        output_path: &Path,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut cmd = ProcessCommand::new("ffmpeg");
-        cmd.arg("-i")
-        .arg(input_path)
-        .arg("-c:a")
-        .arg("flac")
-        .arg("-compression_level")
-        .arg("8")
-        .arg("-y")
-        .arg(output_path);
+    let samples: Vec<i32> = reader.samples::<i32>().map_while(Result::ok).collect();
+    let channels = spec.channels.max(1) as usize;
+    let threshold = ((1i64 << (spec.bits_per_sample - 1)) as f32 * SILENCE_THRESHOLD) as i32;
+    let frames: Vec<&[i32]> = samples.chunks(channels).collect();
+    let loud = |frame: &&[i32]| frame.iter().any(|sample| sample.abs() > threshold);
 
-        let output = cmd.output()?;
-        if !output.status.success() {
-            return Err("ffmpeg FLAC encoding failed".into());
+    let (first, last) = match (frames.iter().position(loud), frames.iter().rposition(loud)) {
+        (Some(first), Some(last)) => (first, last),
+        // All silence: keep it as is rather than writing an empty file
+        _ => {
+            fs::copy(input, output)?;
+            return Ok(());
+        }
+    };
+
+    let frames_for = |ms: u64| (spec.sample_rate as u64 * ms / 1000) as usize;
+    let keep = frames_for(SILENCE_KEEP_MS);
+    let start = first.saturating_sub(keep);
+    let end = (last + keep + 1).min(frames.len());
+    let ramp = frames_for(TRIM_RAMP_MS).max(1);
+    let length = end - start;
+
+    let mut writer = hound::WavWriter::create(output, spec)?;
+    for (offset, frame) in frames[start..end].iter().enumerate() {
+        let edge = offset.min(length - 1 - offset);
+        let gain = (edge as f32 / ramp as f32).min(1.0);
+        for sample in frame.iter() {
+            writer.write_sample((*sample as f32 * gain) as i32)?;
         }
-        Ok(())
     }
+    writer.finalize()?;
 
-    fn convert_to_mp3(
-        &self,
-        input_path: &Path,
-        output_path: &Path,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let encoders = ["lame", "ffmpeg"];
+    Ok(())
+}
 
-        for encoder in &encoders {
-            if ProcessCommand::new("which")
-                .arg(encoder)
+// Fade lengths for one chunk, in milliseconds
+#[derive(Debug, Clone, Copy, Default)]
+struct Fade {
+    in_ms: u32,
+    out_ms: u32,
+}
+
+impl Fade {
+    fn is_none(&self) -> bool {
+        self.in_ms == 0 && self.out_ms == 0
+    }
+}
+
+// Linear fade over the leading/trailing frames of a WAV file
+fn apply_fade(input: &Path, output: &Path, fade: Fade) -> Result<(), Box<dyn std::error::Error>> {
+    let mut reader = hound::WavReader::open(input)?;
+    let spec = reader.spec();
+    if spec.sample_format != hound::SampleFormat::Int {
+        fs::copy(input, output)?;
+        return Ok(());
+    }
+
+    // Stop at the real end of data rather than espeak's placeholder length
+    let samples: Vec<i32> = reader.samples::<i32>().map_while(Result::ok).collect();
+    let channels = spec.channels.max(1) as usize;
+    let frames = samples.len() / channels;
+    let frames_for = |ms: u32| ((spec.sample_rate as u64 * ms as u64 / 1000) as usize).min(frames);
+    let fade_in = frames_for(fade.in_ms);
+    let fade_out = frames_for(fade.out_ms);
+
+    let mut writer = hound::WavWriter::create(output, spec)?;
+    for (index, sample) in samples.iter().enumerate() {
+        let frame = index / channels;
+        let mut gain = 1.0f32;
+        if frame < fade_in {
+            gain = gain.min(frame as f32 / fade_in as f32);
+        }
+        if frame + fade_out >= frames {
+            gain = gain.min((frames - frame) as f32 / fade_out.max(1) as f32);
+        }
+        writer.write_sample((*sample as f32 * gain) as i32)?;
+    }
+    writer.finalize()?;
+
+    Ok(())
+}
+
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+// Cached chunks are named by their SHA-256 key; ".tmp" ones are a worker's
+// audio not yet renamed into place
+fn is_cache_file(path: &Path) -> bool {
+    let is_key = |stem: &str| stem.len() == 64 && stem.chars().all(|c| c.is_ascii_hexdigit());
+    path.extension().is_some_and(|ext| ext == "wav")
+        && path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .is_some_and(|stem| is_key(stem) || stem.starts_with(".tmp"))
+}
+
+fn clear_cache(cache_dir: &Path, book: Option<&str>) -> Result<usize, Box<dyn std::error::Error>> {
+    if !cache_dir.exists() {
+        return Ok(0);
+    }
+
+    let mut index = CacheIndex::load(cache_dir);
+
+    // --cache-dir may point at a folder holding other files, so only the
+    // cache's own audio and index go, and the folder only once it is empty
+    if book.is_none() {
+        let mut removed = 0;
+        for entry in fs::read_dir(cache_dir)? {
+            let path = entry?.path();
+            if is_cache_file(&path) {
+                fs::remove_file(&path)?;
+                removed += 1;
+            }
+        }
+        let _ = fs::remove_file(cache_dir.join(CACHE_INDEX_FILE));
+        let _ = fs::remove_dir(cache_dir);
+        return Ok(removed);
+    }
+
+    let released = index.prune(book);
+    for key in &released {
+        let _ = fs::remove_file(cache_dir.join(format!("{}.wav", key)));
+    }
+    index.save(cache_dir)?;
+
+    Ok(released.len())
+}
+
+// A text-to-speech backend producing WAV bytes for one chunk
+trait SpeechSynthesizer: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // Estimated price in USD for this many characters; None when the engine's
+    // pricing is unknown. Local engines are free.
+    fn estimate_cost(&self, _characters: usize) -> Option<f64> {
+        Some(0.0)
+    }
+
+    // Rejects a voice name the engine cannot load, before any chunk is synthesized
+    fn check_voice(&self, _voice: &str) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+
+    // Voices installed for this engine, for --list-voices
+    fn list_voices(&self) -> Result<Vec<VoiceInfo>, Box<dyn std::error::Error>> {
+        Ok(Vec::new())
+    }
+
+    // Modifiers appended to a voice with "+", e.g. "f3" in "en+f3"
+    fn list_variants(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    fn synthesize(&self, text: &str, voice: &Config) -> Result<OutputAudio, Box<dyn std::error::Error>>;
+}
+
+// Audio as an engine wrote it, tagged with its layout so it can be turned
+// into a WAV before to_canonical_wav
+struct OutputAudio {
+    data: Vec<u8>,
+    format: NativeFormat,
+}
+
+enum NativeFormat {
+    // A RIFF WAV file, header included
+    Wav,
+    // Headerless little-endian samples in this spec (8-bit ones unsigned, as in WAV)
+    RawPcm(hound::WavSpec),
+}
+
+impl OutputAudio {
+    fn wav(data: Vec<u8>) -> Self {
+        OutputAudio {
+            data,
+            format: NativeFormat::Wav,
+        }
+    }
+
+    fn into_wav(self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        match self.format {
+            NativeFormat::Wav => Ok(self.data),
+            NativeFormat::RawPcm(spec) => pcm_to_wav(&self.data, spec),
+        }
+    }
+}
+
+// One row of --list-voices; `id` is what --voice takes
+struct VoiceInfo {
+    id: String,
+    gender: String,
+    name: String,
+}
+
+// data_path is what espeak's --path takes: the directory holding espeak-ng-data
+struct EspeakNg {
+    data_path: Option<PathBuf>,
+}
+struct Espeak {
+    data_path: Option<PathBuf>,
+}
+struct Festival;
+
+// Silence of roughly the spoken length; lets the pipeline run without a TTS install
+struct MockSynthesizer;
+
+impl SpeechSynthesizer for EspeakNg {
+    fn name(&self) -> &'static str {
+        "espeak-ng"
+    }
+
+    fn check_voice(&self, voice: &str) -> Result<(), Box<dyn std::error::Error>> {
+        check_espeak_voice("espeak-ng", self.data_path.as_deref(), voice)
+    }
+
+    fn list_voices(&self) -> Result<Vec<VoiceInfo>, Box<dyn std::error::Error>> {
+        list_espeak_voices("espeak-ng", self.data_path.as_deref())
+    }
+
+    fn list_variants(&self) -> Vec<String> {
+        list_espeak_variants("espeak-ng", self.data_path.as_deref())
+    }
+
+    fn synthesize(&self, text: &str, voice: &Config) -> Result<OutputAudio, Box<dyn std::error::Error>> {
+        run_espeak("espeak-ng", self.data_path.as_deref(), text, voice)
+    }
+}
+
+impl SpeechSynthesizer for Espeak {
+    fn name(&self) -> &'static str {
+        "espeak"
+    }
+
+    fn check_voice(&self, voice: &str) -> Result<(), Box<dyn std::error::Error>> {
+        check_espeak_voice("espeak", self.data_path.as_deref(), voice)
+    }
+
+    fn list_voices(&self) -> Result<Vec<VoiceInfo>, Box<dyn std::error::Error>> {
+        list_espeak_voices("espeak", self.data_path.as_deref())
+    }
+
+    fn list_variants(&self) -> Vec<String> {
+        list_espeak_variants("espeak", self.data_path.as_deref())
+    }
+
+    fn synthesize(&self, text: &str, voice: &Config) -> Result<OutputAudio, Box<dyn std::error::Error>> {
+        run_espeak("espeak", self.data_path.as_deref(), text, voice)
+    }
+}
+
+impl SpeechSynthesizer for Festival {
+    fn name(&self) -> &'static str {
+        "festival"
+    }
+
+    fn list_voices(&self) -> Result<Vec<VoiceInfo>, Box<dyn std::error::Error>> {
+        let output = ProcessCommand::new("festival").arg("-b").arg("(print (voice.list))").output()?;
+        if !output.status.success() {
+            return Err("festival could not list its voices".into());
+        }
+        // Printed as a Lisp list, e.g. "(kal_diphone rab_diphone)"
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .split(|c: char| c == '(' || c == ')' || c.is_whitespace())
+            .filter(|name| !name.is_empty())
+            .map(|name| VoiceInfo {
+                id: name.to_string(),
+                gender: "-".to_string(),
+                name: name.to_string(),
+            })
+            .collect())
+    }
+
+    fn synthesize(&self, text: &str, voice: &Config) -> Result<OutputAudio, Box<dyn std::error::Error>> {
+        let mut cmd = ProcessCommand::new("festival");
+        cmd.arg("--tts")
+        .arg("--pipe")
+        .stderr(Stdio::null());
+
+        let output = output_with_timeout(&mut cmd, Some(text.as_bytes()), voice.chunk_timeout_secs)?;
+        if !output.status.success() {
+            return Err("TTS generation failed with festival".into());
+        }
+        Ok(OutputAudio::wav(output.stdout))
+    }
+}
+
+impl SpeechSynthesizer for MockSynthesizer {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    // Raw PCM, like an engine that streams bare samples
+    fn synthesize(&self, text: &str, voice: &Config) -> Result<OutputAudio, Box<dyn std::error::Error>> {
+        let words = text.split_whitespace().count().max(1) as f32;
+        let samples = (words / voice.effective_wpm() * 60.0 * voice.sample_rate as f32) as usize;
+
+        Ok(OutputAudio {
+            data: vec![0; samples * 2],
+            format: NativeFormat::RawPcm(hound::WavSpec {
+                channels: 1,
+                sample_rate: voice.sample_rate,
+                bits_per_sample: 16,
+                sample_format: hound::SampleFormat::Int,
+            }),
+        })
+    }
+}
+
+fn silent_wav(spec: hound::WavSpec, samples: u32) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut wav = Vec::new();
+    {
+        let mut writer = hound::WavWriter::new(std::io::Cursor::new(&mut wav), spec)?;
+        for _ in 0..samples * spec.channels as u32 {
+            writer.write_sample(0i16)?;
+        }
+        writer.finalize()?;
+    }
+    Ok(wav)
+}
+
+fn espeak_command(program: &str, data_path: Option<&Path>) -> ProcessCommand {
+    let mut cmd = ProcessCommand::new(program);
+    if let Some(path) = data_path {
+        cmd.arg(format!("--path={}", path.display()));
+    }
+    cmd
+}
+
+// espeak's --path names the directory that holds espeak-ng-data (espeak-data for
+// espeak), so either that or the data directory itself is accepted here
+fn resolve_espeak_data(dir: &Path) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    if !dir.is_dir() {
+        return Err(format!("--espeak-data directory {} does not exist", dir.display()).into());
+    }
+    let is_data_dir = dir
+        .file_name()
+        .is_some_and(|name| name == "espeak-ng-data" || name == "espeak-data");
+    if is_data_dir && dir.join("voices").is_dir() {
+        return Ok(dir.parent().unwrap_or(Path::new(".")).to_path_buf());
+    }
+    if ["espeak-ng-data", "espeak-data"].iter().any(|data| dir.join(data).join("voices").is_dir()) {
+        return Ok(dir.to_path_buf());
+    }
+    Err(format!(
+        "--espeak-data {} has no voices folder; point it at an espeak-ng-data directory or the one holding it",
+        dir.display()
+    )
+    .into())
+}
+
+// An engine killed by --chunk-timeout gets one more try before the chunk fails
+fn synthesize_retrying(
+    synthesizer: &dyn SpeechSynthesizer,
+    text: &str,
+    voice: &Config,
+) -> Result<OutputAudio, Box<dyn std::error::Error>> {
+    match synthesizer.synthesize(text, voice) {
+        Err(e) if e.is::<TimedOut>() => {
+            warn!("⚠️  {} on \"{}\"; retrying once", e, log_excerpt(text));
+            synthesizer.synthesize(text, voice)
+        }
+        result => result,
+    }
+}
+
+fn run_espeak(program: &str, data_path: Option<&Path>, text: &str, voice: &Config) -> Result<OutputAudio, Box<dyn std::error::Error>> {
+    let mut cmd = espeak_command(program, data_path);
+    cmd.arg("-v")
+    .arg(&voice.voice)
+    .arg("-s")
+    .arg(format!("{}", voice.effective_wpm() as u32))
+    .arg("-p")
+    .arg(format!("{}", (voice.voice_pitch * 50.0) as u32))
+    .arg("-a")
+    .arg(voice.amplitude.to_string())
+    .arg("--stdout")
+    .arg(text)
+    .stderr(Stdio::null());
+
+    let output = output_with_timeout(&mut cmd, None, voice.chunk_timeout_secs)?;
+    if !output.status.success() {
+        return Err(format!("TTS generation failed with {}", program).into());
+    }
+    Ok(OutputAudio::wav(output.stdout))
+}
+
+// `espeak-ng --voices` prints "Pty Language Age/Gender VoiceName File Other
+// Languages"; the language code is what -v takes. espeak-ng names use
+// underscores, but espeak's may hold spaces, so the name runs up to the file
+// column (the first later token with a "/").
+fn list_espeak_voices(program: &str, data_path: Option<&Path>) -> Result<Vec<VoiceInfo>, Box<dyn std::error::Error>> {
+    let output = espeak_command(program, data_path).arg("--voices").output()?;
+    if !output.status.success() {
+        return Err(format!("{} could not list its voices", program).into());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            if tokens.len() < 4 {
+                return None;
+            }
+            let name_end = tokens[4..].iter().position(|token| token.contains('/')).map_or(4, |i| i + 4);
+            Some(VoiceInfo {
+                id: tokens[1].to_string(),
+                gender: tokens[2].rsplit('/').next().unwrap_or("-").to_string(),
+                name: tokens[3..name_end].join(" ").replace('_', " "),
+            })
+        })
+        .collect())
+}
+
+// Variant names from `espeak-ng --voices=variant`, whose File column reads e.g. "!v/f3"
+fn list_espeak_variants(program: &str, data_path: Option<&Path>) -> Vec<String> {
+    let Ok(output) = espeak_command(program, data_path).arg("--voices=variant").output() else {
+        return Vec::new();
+    };
+
+    let mut variants: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().skip(3).find(|token| token.contains('/')))
+        .map(|file| file.rsplit('/').next().unwrap_or(file).to_string())
+        .collect();
+    variants.sort();
+    variants.dedup();
+    variants
+}
+
+// Prints the selected engine's voices as a table, then any variants
+fn print_voices(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let synthesizer = select_synthesizer(config)?
+        .ok_or_else(ConversionError::no_tts_engine)?;
+    let voices = synthesizer.list_voices()?;
+    if voices.is_empty() {
+        println!("The {} engine has no voices to choose from", synthesizer.name());
+        return Ok(());
+    }
+
+    let id_width = voices.iter().map(|voice| voice.id.chars().count()).max().unwrap_or(0).max("VOICE".len());
+    println!("Voices for {} (pass one to --voice):", synthesizer.name());
+    println!("  {:<id_width$}  {:<6}  NAME", "VOICE", "GENDER");
+    for voice in &voices {
+        println!("  {:<id_width$}  {:<6}  {}", voice.id, voice.gender, voice.name);
+    }
+    if synthesizer.name() == "festival" {
+        println!("festival reads with its default voice; choose one in ~/.festivalrc");
+    }
+
+    let variants = synthesizer.list_variants();
+    if !variants.is_empty() {
+        println!();
+        println!("Variants (append with +, e.g. --voice en+{}):", variants[0]);
+        println!("  {}", variants.join(" "));
+    }
+    Ok(())
+}
+
+// Voices may carry a variant, e.g. "en+f3" or "en-us+whisper"; espeak reports
+// an unknown language or variant on stderr when asked to load it
+fn check_espeak_voice(program: &str, data_path: Option<&Path>, voice: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let output = espeak_command(program, data_path)
+        .arg("-v")
+        .arg(voice)
+        .arg("-q")
+        .arg(".")
+        .stdin(Stdio::null())
+        .output()?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() || stderr.contains("Failed to read voice") {
+        return Err(format!(
+            "{} does not know the voice \"{}\"; list languages with `{} --voices` and variants with `{} --voices=variant`",
+            program, voice, program, program
+        )
+        .into());
+    }
+    Ok(())
+}
+
+const TTS_ENGINES: [&str; 5] = ["auto", "espeak-ng", "espeak", "festival", "mock"];
+
+// "auto" picks the first engine installed on this system, if any
+fn select_synthesizer(config: &Config) -> Result<Option<Box<dyn SpeechSynthesizer>>, Box<dyn std::error::Error>> {
+    let engine = config.engine.as_str();
+    let engine = if engine == "auto" {
+        match ["espeak-ng", "espeak", "festival"].into_iter().find(|engine| {
+            ProcessCommand::new("which")
+                .arg(engine)
                 .output()
                 .map(|output| output.status.success())
                 .unwrap_or(false)
-                {
-                    return match *encoder {
-                        "lame" => self.encode_with_lame(input_path, output_path),
-                        "ffmpeg" => self.encode_mp3_with_ffmpeg(input_path, output_path),
-                        _ => continue,
+        }) {
+            Some(engine) => engine,
+            None => return Ok(None),
+        }
+    } else {
+        engine
+    };
+
+    let data_path = match (&config.espeak_data_path, engine) {
+        (Some(dir), "espeak-ng" | "espeak") => Some(resolve_espeak_data(dir)?),
+        _ => None,
+    };
+    let synthesizer: Box<dyn SpeechSynthesizer> = match engine {
+        "espeak-ng" => Box::new(EspeakNg { data_path }),
+        "espeak" => Box::new(Espeak { data_path }),
+        "festival" => Box::new(Festival),
+        "mock" => Box::new(MockSynthesizer),
+        other => {
+            return Err(format!("Unknown TTS engine '{}'; expected one of {}", other, TTS_ENGINES.join(", ")).into())
+        }
+    };
+    debug!("Using TTS engine {}", synthesizer.name());
+
+    Ok(Some(synthesizer))
+}
+
+struct TTSEngine {
+    config: Config,
+    cache_dir: PathBuf,
+    cache_index: Mutex<CacheIndex>,
+    book: Option<String>,
+    synthesizer: Option<Box<dyn SpeechSynthesizer>>,
+    cache_hits: AtomicUsize,
+    cache_misses: AtomicUsize,
+    // Chunk texts seen more than once in this run, and the WAV each repeat shares
+    repeated_texts: Mutex<HashSet<String>>,
+    shared_wavs: Mutex<HashMap<String, Arc<SharedWav>>>,
+    // Holds shared WAVs for the run when the cache is off
+    run_dir: tempfile::TempDir,
+    duplicates_reused: AtomicUsize,
+}
+
+// Set once by whichever chapter synthesizes a repeated chunk first: the WAV
+// path and whether it came from the cache
+type SharedWav = OnceLock<Result<(PathBuf, bool), String>>;
+
+// Names the failing chunk, unless the error already says what to install
+fn chunk_error(chunk_idx: usize, error: Box<dyn std::error::Error + Send + Sync>) -> Box<dyn std::error::Error + Send + Sync> {
+    if error.is::<ConversionError>() {
+        error
+    } else {
+        format!("TTS failed for chunk {}: {}", chunk_idx, error).into()
+    }
+}
+
+// A chunk's synthesized WAV; a temp file is removed once this is dropped
+struct RenderedWav {
+    path: PathBuf,
+    _temp_guard: Option<tempfile::TempPath>,
+    cache_hit: bool,
+}
+
+// Outcome of synthesizing one chunk
+struct SpeechResult {
+    duration_secs: f64,
+    cache_hit: bool,
+}
+
+impl TTSEngine {
+    fn new(config: Config) -> Result<Self, Box<dyn std::error::Error>> {
+        let cache_dir = config.cache_dir.clone().unwrap_or_else(default_cache_dir);
+        if config.cache_enabled {
+            fs::create_dir_all(&cache_dir)?;
+        }
+        let cache_index = Mutex::new(CacheIndex::load(&cache_dir));
+        let synthesizer = select_synthesizer(&config)?;
+        if let Some(synthesizer) = &synthesizer {
+            synthesizer.check_voice(&config.voice)?;
+            if let Some(dialogue_voice) = &config.dialogue_voice {
+                synthesizer.check_voice(dialogue_voice)?;
+            }
+        }
+
+        Ok(Self {
+            config,
+            cache_dir,
+            cache_index,
+            book: None,
+            synthesizer,
+            cache_hits: AtomicUsize::new(0),
+            cache_misses: AtomicUsize::new(0),
+            repeated_texts: Mutex::new(HashSet::new()),
+            shared_wavs: Mutex::new(HashMap::new()),
+            run_dir: tempfile::tempdir()?,
+            duplicates_reused: AtomicUsize::new(0),
+        })
+    }
+
+    fn set_book(&mut self, book: &str) {
+        self.book = Some(book.to_string());
+    }
+
+    fn save_cache_index(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.config.cache_enabled {
+            return Ok(());
+        }
+        self.cache_index.lock().unwrap().save(&self.cache_dir)
+    }
+
+    fn text_to_speech(
+        &self,
+        text: &str,
+        output_path: &Path,
+    ) -> Result<SpeechResult, Box<dyn std::error::Error>> {
+        self.text_to_speech_with(text, output_path, &self.config, None, Fade::default())
+    }
+
+    // Synthesizes with voice settings from `voice` (e.g. a per-chapter override)
+    // while encoding with the engine's own output settings
+    fn text_to_speech_with(
+        &self,
+        text: &str,
+        output_path: &Path,
+        voice: &Config,
+        tags: Option<&AudioTags>,
+        fade: Fade,
+    ) -> Result<SpeechResult, Box<dyn std::error::Error>> {
+        let rendered = self.render(text, voice)?;
+        Ok(SpeechResult {
+            duration_secs: self.encode_chunk(&rendered.path, output_path, tags, fade)?,
+            cache_hit: rendered.cache_hit,
+        })
+    }
+
+    // Synthesizes (or finds) the canonical WAV for a chunk, ready to encode
+    fn render(&self, text: &str, voice: &Config) -> Result<RenderedWav, Box<dyn std::error::Error>> {
+        let key = self.chunk_key(text, voice);
+
+        // Text that repeats in this run is synthesized once; other chapters
+        // wait for that WAV and encode their own copy from it
+        let repeated = self.repeated_texts.lock().unwrap().contains(text);
+        let (path, temp_guard, cache_hit) = if repeated {
+            let slot = Arc::clone(self.shared_wavs.lock().unwrap().entry(key.clone()).or_default());
+            let mut synthesized_here = false;
+            let shared = slot.get_or_init(|| {
+                synthesized_here = true;
+                self.chunk_wav(text, voice, &key, true)
+                    .map(|(path, _, cache_hit)| (path, cache_hit))
+                    .map_err(|e| e.to_string())
+            });
+            let (path, cache_hit) = shared.clone()?;
+            if !synthesized_here {
+                self.duplicates_reused.fetch_add(1, Ordering::Relaxed);
+            }
+            (path, None, cache_hit && synthesized_here)
+        } else {
+            self.chunk_wav(text, voice, &key, false)?
+        };
+
+        Ok(RenderedWav {
+            path,
+            _temp_guard: temp_guard,
+            cache_hit,
+        })
+    }
+
+    // `ms` of silence in place of speech
+    fn render_pause(&self, ms: u64) -> Result<RenderedWav, Box<dyn std::error::Error>> {
+        let spec = self.config.wav_spec();
+        let samples = spec.sample_rate as u64 * ms / 1000;
+        let mut temp_wav = tempfile::Builder::new().suffix(".wav").tempfile()?;
+        temp_wav.write_all(&silent_wav(spec, samples as u32)?)?;
+        let temp_path = temp_wav.into_temp_path();
+
+        Ok(RenderedWav {
+            path: temp_path.to_path_buf(),
+            _temp_guard: Some(temp_path),
+            cache_hit: false,
+        })
+    }
+
+    fn chunk_key(&self, text: &str, voice: &Config) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(text.as_bytes());
+        if let Some(synthesizer) = &self.synthesizer {
+            hasher.update(synthesizer.name().as_bytes());
+        }
+        hasher.update(voice.voice.as_bytes());
+        hasher.update(voice.voice_speed.to_be_bytes());
+        hasher.update(voice.voice_pitch.to_be_bytes());
+        hasher.update(voice.amplitude.to_be_bytes());
+        if let Some(dialogue_voice) = &self.config.dialogue_voice {
+            hasher.update(dialogue_voice.as_bytes());
+        }
+        hasher.update(self.config.sample_rate.to_be_bytes());
+        hasher.update(self.config.channels.to_be_bytes());
+        hasher.update(self.config.bits_per_sample.to_be_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+
+    // Finds or synthesizes the canonical WAV for a chunk. Uncached audio lives in
+    // a temp file removed with the returned guard, or in the run directory when
+    // `retain` is set so repeats of the chunk can reuse it.
+    fn chunk_wav(
+        &self,
+        text: &str,
+        voice: &Config,
+        key: &str,
+        retain: bool,
+    ) -> Result<(PathBuf, Option<tempfile::TempPath>, bool), Box<dyn std::error::Error>> {
+        let cache_path = self.cache_dir.join(format!("{}.wav", key));
+
+        // Check cache
+        if self.config.cache_enabled && cache_path.exists() {
+            // A recorded checksum catches any change to the file cheaply; entries
+            // without one get the full decode check and are checksummed once they pass
+            let expected = self.cache_index.lock().unwrap().checksum(key);
+            let verdict = match &expected {
+                Some(expected) => sha256_file(&cache_path).and_then(|actual| {
+                    if &actual == expected {
+                        Ok(())
+                    } else {
+                        Err("checksum mismatch".into())
+                    }
+                }),
+                None => check_cached_wav(&cache_path, self.config.wav_spec()),
+            };
+            match verdict {
+                Ok(()) => {
+                    let recorded = match expected {
+                        Some(_) => None,
+                        None => sha256_file(&cache_path).ok(),
                     };
+                    {
+                        let mut index = self.cache_index.lock().unwrap();
+                        index.add(key, self.book.as_deref());
+                        if let Some(sha256) = recorded {
+                            index.set_checksum(key, sha256);
+                        }
+                    }
+                    self.cache_hits.fetch_add(1, Ordering::Relaxed);
+                    return Ok((cache_path, None, true));
+                }
+                Err(e) => {
+                    // Another worker may already have removed it; the fresh audio
+                    // replaces it below either way
+                    warn!("⚠️  Discarding unusable cached audio {}: {}", cache_path.display(), e);
+                    let _ = fs::remove_file(&cache_path);
+                }
+            }
+        }
+
+        let synthesizer = self
+            .synthesizer
+            .as_deref()
+            .ok_or_else(ConversionError::no_tts_engine)?;
+        let audio = match &self.config.dialogue_voice {
+            Some(dialogue_voice) if text.contains('"') => {
+                // Narration and dialogue spans are synthesized separately and joined in order
+                let mut dialogue = voice.clone();
+                dialogue.voice = dialogue_voice.clone();
+                let mut spans = Vec::new();
+                for (is_dialogue, span) in split_dialogue(text) {
+                    let span_voice = if is_dialogue { &dialogue } else { voice };
+                    spans.push(to_canonical_wav(&synthesize_retrying(synthesizer, span, span_voice)?.into_wav()?, self.config.wav_spec())?);
                 }
+                join_wavs(&spans, self.config.wav_spec())?
+            }
+            _ => to_canonical_wav(&synthesize_retrying(synthesizer, text, voice)?.into_wav()?, self.config.wav_spec())?,
+        };
+
+        // Write raw audio to a temp file first; cached audio is then renamed into
+        // place so chunks with identical text never read a half-written file
+        let mut temp_wav = if self.config.cache_enabled {
+            tempfile::Builder::new().suffix(".wav").tempfile_in(&self.cache_dir)?
+        } else {
+            tempfile::Builder::new().suffix(".wav").tempfile()?
+        };
+        temp_wav.write_all(&audio)?;
+        temp_wav.as_file().sync_all()?;
+        let temp_path = temp_wav.into_temp_path();
+
+        if self.config.cache_enabled {
+            temp_path.persist(&cache_path)?;
+            {
+                let mut index = self.cache_index.lock().unwrap();
+                index.add(key, self.book.as_deref());
+                index.set_checksum(key, format!("{:x}", Sha256::digest(audio)));
+            }
+            self.cache_misses.fetch_add(1, Ordering::Relaxed);
+            Ok((cache_path, None, false))
+        } else if retain {
+            let run_path = self.run_dir.path().join(format!("{}.wav", key));
+            temp_path.persist(&run_path)?;
+            Ok((run_path, None, false))
+        } else {
+            Ok((temp_path.to_path_buf(), Some(temp_path), false))
+        }
+    }
+
+    // Called before a run with every chunk text that occurs more than once
+    fn expect_repeats(&self, texts: HashSet<String>) {
+        *self.repeated_texts.lock().unwrap() = texts;
+    }
+
+    // Cached audio stays untouched; trimming and fades are applied to scratch
+    // copies. Returns the duration of the audio actually encoded.
+    fn encode_chunk(
+        &self,
+        wav_path: &Path,
+        output_path: &Path,
+        tags: Option<&AudioTags>,
+        fade: Fade,
+    ) -> Result<f64, Box<dyn std::error::Error>> {
+        let trimmed = tempfile::Builder::new().suffix(".wav").tempfile()?.into_temp_path();
+        let source = if self.config.trim_chunk_silence {
+            trim_silence(wav_path, &trimmed)?;
+            trimmed.to_path_buf()
+        } else {
+            wav_path.to_path_buf()
+        };
+
+        let faded = tempfile::Builder::new().suffix(".wav").tempfile()?.into_temp_path();
+        let source = if fade.is_none() {
+            source
+        } else {
+            apply_fade(&source, &faded, fade)?;
+            faded.to_path_buf()
+        };
+
+        // Encode next to the destination and rename into place, so an interrupted
+        // run never leaves a truncated file that --resume would keep
+        let parent = output_path.parent().unwrap_or(Path::new("."));
+        let partial_dir = tempfile::Builder::new().prefix(".partial").tempdir_in(parent)?;
+        let partial = partial_dir.path().join(output_path.file_name().ok_or("Invalid output path")?);
+        match self.convert_audio(&source, &partial, tags) {
+            Err(e) if e.is::<TimedOut>() => {
+                warn!("⚠️  {} encoding {}; retrying once", e, output_path.display());
+                self.convert_audio(&source, &partial, tags)?;
+            }
+            result => result?,
+        }
+        fs::rename(&partial, output_path)?;
+
+        // The exact samples handed to the encoder, for telling espeak artifacts
+        // from encoder ones
+        if self.config.keep_wav && !matches!(self.config.output_format, AudioFormat::Wav) {
+            fs::copy(&source, output_path.with_extension("wav"))?;
+        }
+        if self.config.merges_chunks() {
+            fs::copy(&source, self.merge_wav_path(output_path))?;
+        }
+        wav_duration_secs(&source)
+    }
+
+    // Where the WAV an encoded file was made from is kept for the run, so
+    // merging encodes the audio once instead of re-encoding lossy files
+    fn merge_wav_path(&self, encoded_path: &Path) -> PathBuf {
+        let digest = Sha256::digest(encoded_path.to_string_lossy().as_bytes());
+        self.run_dir.path().join(format!("merge_{:x}.wav", digest))
+    }
+
+    fn convert_audio(
+        &self,
+        input_path: &Path,
+        output_path: &Path,
+        tags: Option<&AudioTags>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.convert_audio_as(&self.config.output_format, input_path, output_path, tags)
+    }
+
+    // An output path of "-" streams the encoded audio to stdout
+    fn convert_audio_as(
+        &self,
+        format: &AudioFormat,
+        input_path: &Path,
+        output_path: &Path,
+        tags: Option<&AudioTags>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match format {
+            AudioFormat::Vorbis => self.convert_to_vorbis(input_path, output_path, tags),
+            AudioFormat::Flac => self.convert_to_flac(input_path, output_path, tags),
+            AudioFormat::Mp3 => self.convert_to_mp3(input_path, output_path, tags),
+            AudioFormat::Wav if is_stdout(output_path) => {
+                let mut stdout = std::io::stdout().lock();
+                std::io::copy(&mut File::open(input_path)?, &mut stdout)?;
+                stdout.flush()?;
+                Ok(())
+            }
+            AudioFormat::Wav => {
+                fs::copy(input_path, output_path)?;
+                Ok(())
+            }
+        }
+    }
+
+    fn convert_to_vorbis(
+        &self,
+        input_path: &Path,
+        output_path: &Path,
+        tags: Option<&AudioTags>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // Try oggenc first (preferred), then ffmpeg as fallback
+        let encoders = ["oggenc", "ffmpeg"];
+
+        for encoder in &encoders {
+            if ProcessCommand::new("which")
+                .arg(encoder)
+                .output()
+                .map(|output| output.status.success())
+                .unwrap_or(false)
+                {
+                    return match *encoder {
+                        "oggenc" => self.encode_with_oggenc(input_path, output_path, tags),
+                        "ffmpeg" => self.encode_vorbis_with_ffmpeg(input_path, output_path, tags),
+                        _ => continue,
+                    };
+                }
+        }
+
+        Err(ConversionError::EncoderMissing("No Vorbis encoder found. Please install vorbis-tools or ffmpeg".to_string()).into())
+    }
+
+    fn encode_with_oggenc(
+        &self,
+        input_path: &Path,
+        output_path: &Path,
+        tags: Option<&AudioTags>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = ProcessCommand::new("oggenc");
+        match self.config.bitrate_kbps {
+            Some(kbps) => cmd.arg("-b").arg(kbps.to_string()),
+            None => cmd.arg("-q").arg(self.config.vorbis_quality().to_string()),
+        };
+        for comment in tags.map(AudioTags::vorbis_comments).unwrap_or_default() {
+            cmd.arg("-c").arg(comment);
+        }
+        cmd.arg("-o")
+        .arg(output_path)
+        .arg(input_path);
+
+        if !run_encoder(&mut cmd, output_path, self.config.chunk_timeout_secs)? {
+            return Err("oggenc encoding failed".into());
+        }
+        Ok(())
+    }
+
+    fn encode_vorbis_with_ffmpeg(
+        &self,
+        input_path: &Path,
+        output_path: &Path,
+        tags: Option<&AudioTags>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = ProcessCommand::new("ffmpeg");
+        cmd.arg("-i")
+        .arg(input_path)
+        .arg("-c:a")
+        .arg("libvorbis");
+        match self.config.bitrate_kbps {
+            Some(kbps) => cmd.arg("-b:a").arg(format!("{}k", kbps)),
+            None => cmd.arg("-q:a").arg(self.config.vorbis_quality().to_string()),
+        };
+        for metadata in tags.map(AudioTags::ffmpeg_metadata).unwrap_or_default() {
+            cmd.arg("-metadata").arg(metadata);
+        }
+        cmd.arg("-y");
+        ffmpeg_output(&mut cmd, output_path, "ogg");
+
+        if !run_encoder(&mut cmd, output_path, self.config.chunk_timeout_secs)? {
+            return Err("ffmpeg Vorbis encoding failed".into());
+        }
+        Ok(())
+    }
+
+    fn convert_to_flac(
+        &self,
+        input_path: &Path,
+        output_path: &Path,
+        tags: Option<&AudioTags>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let encoders = ["flac", "ffmpeg"];
+
+        for encoder in &encoders {
+            if ProcessCommand::new("which")
+                .arg(encoder)
+                .output()
+                .map(|output| output.status.success())
+                .unwrap_or(false)
+                {
+                    return match *encoder {
+                        "flac" => self.encode_with_flac(input_path, output_path, tags),
+                        "ffmpeg" => self.encode_flac_with_ffmpeg(input_path, output_path, tags),
+                        _ => continue,
+                    };
+                }
+        }
+
+        Err(ConversionError::EncoderMissing("No FLAC encoder found. Please install flac or ffmpeg".to_string()).into())
+    }
+
+    fn encode_with_flac(
+        &self,
+        input_path: &Path,
+        output_path: &Path,
+        tags: Option<&AudioTags>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = ProcessCommand::new("flac");
+        cmd.arg(format!("--compression-level-{}", self.config.flac_compression_level()));
+        for comment in tags.map(AudioTags::vorbis_comments).unwrap_or_default() {
+            cmd.arg("-T").arg(comment);
+        }
+        if is_stdout(output_path) {
+            cmd.arg("--stdout");
+        } else {
+            cmd.arg("-o").arg(output_path);
+        }
+        cmd.arg(input_path);
+
+        if !run_encoder(&mut cmd, output_path, self.config.chunk_timeout_secs)? {
+            return Err("FLAC encoding failed".into());
+        }
+        Ok(())
+    }
+
+    fn encode_flac_with_ffmpeg(
+        &self,
+        input_path: &Path,
+        output_path: &Path,
+        tags: Option<&AudioTags>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = ProcessCommand::new("ffmpeg");
+        cmd.arg("-i")
+        .arg(input_path)
+        .arg("-c:a")
+        .arg("flac")
+        .arg("-compression_level")
+        .arg(self.config.flac_compression_level().to_string());
+        for metadata in tags.map(AudioTags::ffmpeg_metadata).unwrap_or_default() {
+            cmd.arg("-metadata").arg(metadata);
+        }
+        cmd.arg("-y");
+        ffmpeg_output(&mut cmd, output_path, "flac");
+
+        if !run_encoder(&mut cmd, output_path, self.config.chunk_timeout_secs)? {
+            return Err("ffmpeg FLAC encoding failed".into());
+        }
+        Ok(())
+    }
+
+    fn convert_to_mp3(
+        &self,
+        input_path: &Path,
+        output_path: &Path,
+        tags: Option<&AudioTags>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let encoders = ["lame", "ffmpeg"];
+
+        for encoder in &encoders {
+            if ProcessCommand::new("which")
+                .arg(encoder)
+                .output()
+                .map(|output| output.status.success())
+                .unwrap_or(false)
+                {
+                    return match *encoder {
+                        "lame" => self.encode_with_lame(input_path, output_path, tags),
+                        "ffmpeg" => self.encode_mp3_with_ffmpeg(input_path, output_path, tags),
+                        _ => continue,
+                    };
+                }
+        }
+
+        Err(ConversionError::EncoderMissing("No MP3 encoder found. Please install lame or ffmpeg".to_string()).into())
+    }
+
+    fn encode_with_lame(
+        &self,
+        input_path: &Path,
+        output_path: &Path,
+        tags: Option<&AudioTags>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = ProcessCommand::new("lame");
+        match self.config.bitrate_kbps {
+            Some(kbps) => cmd.arg("--abr").arg(kbps.to_string()),
+            None => cmd.arg("-V").arg(self.config.mp3_vbr_level().to_string()),
+        };
+        cmd.args(tags.map(AudioTags::lame_args).unwrap_or_default());
+        // lame reads "-" as stdout itself
+        cmd.arg(input_path)
+        .arg(output_path);
+
+        if !run_encoder(&mut cmd, output_path, self.config.chunk_timeout_secs)? {
+            return Err("LAME encoding failed".into());
+        }
+        Ok(())
+    }
+
+    fn encode_mp3_with_ffmpeg(
+        &self,
+        input_path: &Path,
+        output_path: &Path,
+        tags: Option<&AudioTags>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = ProcessCommand::new("ffmpeg");
+        cmd.arg("-i")
+        .arg(input_path)
+        .arg("-c:a")
+        .arg("libmp3lame");
+        match self.config.bitrate_kbps {
+            Some(kbps) => cmd.arg("-b:a").arg(format!("{}k", kbps)),
+            None => cmd.arg("-q:a").arg(self.config.mp3_vbr_level().to_string()),
+        };
+        for metadata in tags.map(AudioTags::ffmpeg_metadata).unwrap_or_default() {
+            cmd.arg("-metadata").arg(metadata);
+        }
+        cmd.arg("-y");
+        ffmpeg_output(&mut cmd, output_path, "mp3");
+
+        if !run_encoder(&mut cmd, output_path, self.config.chunk_timeout_secs)? {
+            return Err("ffmpeg MP3 encoding failed".into());
+        }
+        Ok(())
+    }
+
+    // Joins chunk files into one by concatenating the WAVs they were encoded
+    // from and encoding the result once. The joined WAV is kept under the
+    // output's merge_wav_path, so a joined chapter can itself be merged later.
+    fn merge_audio(
+        &self,
+        inputs: &[PathBuf],
+        output_path: &Path,
+        metadata: &[String],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !ProcessCommand::new("which")
+            .arg("ffmpeg")
+            .output()
+            .map(|output| output.status.success())
+            .unwrap_or(false)
+        {
+            return Err(ConversionError::EncoderMissing("Merging into a single file requires ffmpeg".to_string()).into());
+        }
+
+        let mut wavs = Vec::new();
+        let mut decoded = Vec::new();
+        for input in inputs {
+            let wav = self.merge_wav_path(input);
+            if wav.exists() {
+                wavs.push(wav);
+            } else {
+                // Files kept by --resume were encoded by an earlier run, possibly
+                // with other channel settings
+                let temp = tempfile::Builder::new().suffix(".wav").tempfile_in(self.run_dir.path())?.into_temp_path();
+                self.decode_to_wav(input, &temp)?;
+                wavs.push(temp.to_path_buf());
+                decoded.push(temp);
+            }
+        }
+        let joined = self.merge_wav_path(output_path);
+        concat_wavs(&wavs, &joined, self.config.wav_spec())?;
+
+        let mut cmd = ProcessCommand::new("ffmpeg");
+        cmd.arg("-i").arg(&joined);
+        match self.config.output_format {
+            AudioFormat::Vorbis => {
+                cmd.arg("-c:a").arg("libvorbis");
+                match self.config.bitrate_kbps {
+                    Some(kbps) => cmd.arg("-b:a").arg(format!("{}k", kbps)),
+                    None => cmd.arg("-q:a").arg(self.config.vorbis_quality().to_string()),
+                };
+            }
+            AudioFormat::Flac => {
+                cmd.arg("-c:a").arg("flac").arg("-compression_level").arg(self.config.flac_compression_level().to_string());
+            }
+            AudioFormat::Mp3 => {
+                cmd.arg("-c:a").arg("libmp3lame");
+                match self.config.bitrate_kbps {
+                    Some(kbps) => cmd.arg("-b:a").arg(format!("{}k", kbps)),
+                    None => cmd.arg("-q:a").arg(self.config.mp3_vbr_level().to_string()),
+                };
+            }
+            AudioFormat::Wav => {
+                cmd.arg("-c:a").arg(self.config.pcm_codec());
+            }
+        }
+        for entry in metadata {
+            cmd.arg("-metadata").arg(entry);
+        }
+        cmd.arg("-y")
+        .arg(output_path);
+
+        let output = cmd.output()?;
+        if !output.status.success() {
+            return Err("ffmpeg failed to merge the chapter files".into());
+        }
+        Ok(())
+    }
+
+    // Decodes an encoded file to the intermediate WAV format
+    fn decode_to_wav(&self, input_path: &Path, output_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let output = ProcessCommand::new("ffmpeg")
+            .arg("-i")
+            .arg(input_path)
+            .arg("-ac")
+            .arg(self.config.channels.to_string())
+            .arg("-ar")
+            .arg(self.config.sample_rate.to_string())
+            .arg("-c:a")
+            .arg(self.config.pcm_codec())
+            .arg("-y")
+            .arg(output_path)
+            .output()?;
+        if !output.status.success() {
+            return Err(format!("ffmpeg failed to decode {}", input_path.display()).into());
+        }
+        Ok(())
+    }
+}
+
+// Structured progress reported while chapters are converted; chapters run in
+// parallel, so events from different chapters interleave
+#[derive(Debug, Clone)]
+enum ProgressEvent {
+    ChapterStarted { order: usize, title: String },
+    ChunkCompleted { order: usize, chunk: usize, total_chunks: usize },
+    CacheHit { order: usize, chunk: usize },
+    ChapterCompleted { order: usize },
+}
+
+// How conversion progress is shown on the command line
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ProgressMode {
+    // An indicatif bar on stderr
+    Bar,
+    // A log line per finished chapter, for CI logs, dumb terminals and redirects
+    Plain,
+    // The PROGRESS lines the GUI reads from stdout
+    Machine,
+    None,
+}
+
+impl ProgressMode {
+    // "auto" draws the bar only when stderr is an interactive terminal
+    fn from_arg(mode: &str) -> Self {
+        match mode {
+            "bar" => ProgressMode::Bar,
+            "plain" => ProgressMode::Plain,
+            "machine" => ProgressMode::Machine,
+            "none" => ProgressMode::None,
+            _ => {
+                let dumb = std::env::var("TERM").map(|term| term == "dumb").unwrap_or(false);
+                if std::io::stderr().is_terminal() && !dumb {
+                    ProgressMode::Bar
+                } else {
+                    ProgressMode::Plain
+                }
+            }
+        }
+    }
+}
+
+// Command-line adapter for ProgressEvent. Outside bar mode the bar stays hidden
+// but still tracks position and ETA for the plain lines.
+struct CliProgress {
+    bar: ProgressBar,
+    mode: ProgressMode,
+    // Estimated seconds per chapter, so long chapters count for more
+    weights: HashMap<usize, u64>,
+    chapters_completed: AtomicUsize,
+}
+
+impl CliProgress {
+    fn new(
+        chapters: &[ManifestChapter],
+        mode: ProgressMode,
+        multi: Option<&MultiProgress>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let weights: HashMap<usize, u64> = chapters
+            .iter()
+            .map(|chapter| (chapter.order, (chapter.estimated_duration_secs as u64).max(1)))
+            .collect();
+
+        let total = weights.values().sum();
+        let bar = if mode == ProgressMode::Bar && log_enabled!(Level::Info) {
+            ProgressBar::new(total)
+        } else {
+            ProgressBar::with_draw_target(Some(total), ProgressDrawTarget::hidden())
+        };
+        bar.set_style(
+            ProgressStyle::default_bar()
+            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {percent:>3}% ETA {eta} {msg}")?
+            .progress_chars("█▉▊▋▌▍▎▏  ")
+        );
+        let bar = match multi {
+            Some(multi) if mode == ProgressMode::Bar => multi.add(bar),
+            _ => bar,
+        };
+
+        if mode == ProgressMode::Machine {
+            emit_progress(&format!("total_chapters={}", chapters.len()));
+        }
+
+        Ok(Self {
+            bar,
+            mode,
+            weights,
+            chapters_completed: AtomicUsize::new(0),
+        })
+    }
+
+    fn handle(&self, event: ProgressEvent) {
+        match event {
+            ProgressEvent::ChapterStarted { order, title } => {
+                if self.mode == ProgressMode::Machine {
+                    emit_progress(&format!("chapter_started={} {}", order + 1, title));
+                }
+            }
+            ProgressEvent::ChunkCompleted { order, chunk, total_chunks } => {
+                debug!("Chapter {}: chunk {}/{} done", order + 1, chunk + 1, total_chunks);
+            }
+            ProgressEvent::CacheHit { order, chunk } => {
+                debug!("Chapter {}: chunk {} served from cache", order + 1, chunk + 1);
+            }
+            ProgressEvent::ChapterCompleted { order } => {
+                self.bar.inc(self.weights.get(&order).copied().unwrap_or(1));
+                let completed = self.chapters_completed.fetch_add(1, Ordering::Relaxed) + 1;
+                match self.mode {
+                    ProgressMode::Machine => emit_progress(&format!("chapter_completed={}", order + 1)),
+                    ProgressMode::Plain => info!(
+                        "⏳ {}/{} chapters done ({:.0}%), ETA {}",
+                        completed,
+                        self.weights.len(),
+                        self.bar.position() as f64 * 100.0 / self.bar.length().unwrap_or(1).max(1) as f64,
+                        format_duration(self.bar.eta())
+                    ),
+                    ProgressMode::Bar | ProgressMode::None => {}
+                }
+            }
+        }
+    }
+
+    fn finish(&self) {
+        self.bar.finish_and_clear();
+    }
+}
+
+// Plain text of one section before cleaning, as extracted from a spine item
+struct RawSection {
+    href: PathBuf,
+    heading: Option<String>,
+    leading_fragment: bool,
+    // The section's HTML shows an image, so empty text means a picture page
+    has_images: bool,
+    // Tables dropped under --tables skip
+    tables_removed: usize,
+    text: String,
+}
+
+// Walks the spine lazily. A chapter is held back until the next one starts,
+// since untitled fragments that follow may still be appended to it.
+// Titles of the --intro/--outro chapters in folder names and the manifest
+const INTRO_TITLE: &str = "Intro";
+const OUTRO_TITLE: &str = "Outro";
+
+struct ChapterIter<'a> {
+    processor: &'a EpubProcessor,
+    doc: EpubDoc<BufReader<File>>,
+    hrefs: std::vec::IntoIter<PathBuf>,
+    sections: VecDeque<RawSection>,
+    running_headers: BTreeSet<String>,
+    pending: Option<Chapter>,
+    image_only_pages: usize,
+    // The --intro chapter, yielded before the book's own, and the --outro text
+    intro: Option<Chapter>,
+    outro: Option<String>,
+    // Order of the book's first chapter: 1 after an intro
+    first_order: usize,
+}
+
+impl<'a> ChapterIter<'a> {
+    fn new(processor: &'a EpubProcessor, epub_path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let config = &processor.config;
+        let mut doc = open_epub(epub_path)?;
+
+        // Encrypted content would only come out as gibberish for espeak to read
+        if is_drm_protected(&mut doc) {
+            return Err(ConversionError::InvalidEpub(format!(
+                "{} appears to be DRM-protected; it must be decrypted first",
+                epub_path.display()
+            ))
+            .into());
+        }
+
+        // Warn when the book already carries recorded narration
+        let narrated = processor.find_media_overlays(&mut doc);
+        if !narrated.is_empty() {
+            warn!(
+                "⚠️  This EPUB ships media overlays (pre-recorded narration) for {} documents; synthetic speech will be generated anyway",
+                narrated.len()
+            );
+        }
+
+        // Auxiliary spine items (linear="no") are outside the main reading flow
+        let nonlinear = read_package_document(&mut doc)
+            .map(|opf| nonlinear_spine_hrefs(&opf))
+            .unwrap_or_default();
+
+        let include = compile_href_patterns(&config.include_hrefs)?;
+        let exclude = compile_href_patterns(&config.exclude_hrefs)?;
+
+        // Get spine (reading order), walked exactly as authored
+        let hrefs: Vec<PathBuf> = doc
+            .spine
+            .iter()
+            .map(|spine_item| PathBuf::from(&spine_item.0))
+            .filter(|href| {
+                let explicitly_included = href_matches(&include, href);
+                if !include.is_empty() && !explicitly_included {
+                    debug!("Skipping {}: not matched by --include-href", href.display());
+                    processor.audit(|report| report.skip(href, "not matched by --include-href"));
+                    return false;
+                }
+                if href_matches(&exclude, href) {
+                    info!("⏭️  Excluding {}", href.display());
+                    processor.audit(|report| report.skip(href, "matched by --exclude-href"));
+                    return false;
+                }
+                if explicitly_included {
+                    info!("📄 Including {}", href.display());
+                } else if !config.include_nonlinear && nonlinear.iter().any(|item| href.ends_with(item)) {
+                    debug!("Skipping non-linear spine item {}", href.display());
+                    processor.audit(|report| report.skip(href, "non-linear spine item (notes, pop-ups)"));
+                    return false;
+                }
+                true
+            })
+            .collect();
+
+        // Running headers are detected across the whole book while line structure
+        // is still intact; only each section's edge lines are kept for that
+        let mut running_headers = BTreeSet::new();
+        if config.strip_running_headers {
+            let mut edges = Vec::new();
+            for href in &hrefs {
+                for section in processor.read_sections(&mut doc, href) {
+                    edges.push(edge_lines(&section.text).join("\n"));
+                }
+            }
+            let texts: Vec<&str> = edges.iter().map(String::as_str).collect();
+            running_headers = find_running_headers(&texts);
+            for header in &running_headers {
+                info!("✂️  Removing running header/footer \"{}\"", header);
+            }
+            processor.audit(|report| report.running_headers = running_headers.iter().cloned().collect());
+        }
+
+        let intro = config
+            .intro_text
+            .as_deref()
+            .map(|text| processor.bookend(INTRO_TITLE, text, 0))
+            .filter(|intro| !intro.content.trim().is_empty());
+
+        Ok(Self {
+            processor,
+            doc,
+            hrefs: hrefs.into_iter(),
+            sections: VecDeque::new(),
+            running_headers,
+            pending: None,
+            image_only_pages: 0,
+            first_order: intro.is_some() as usize,
+            intro,
+            outro: config.outro_text.clone(),
+        })
+    }
+}
+
+impl Iterator for ChapterIter<'_> {
+    type Item = Chapter;
+
+    fn next(&mut self) -> Option<Chapter> {
+        let processor = self.processor;
+        let config = &processor.config;
+        if let Some(intro) = self.intro.take() {
+            return Some(intro);
+        }
+        loop {
+            let RawSection { href, heading, leading_fragment, has_images, tables_removed, text } = match self.sections.pop_front() {
+                Some(section) => section,
+                None => match self.hrefs.next() {
+                    Some(href) => {
+                        self.sections.extend(processor.read_sections(&mut self.doc, &href));
+                        continue;
+                    }
+                    None => {
+                        if self.image_only_pages > 0 {
+                            info!(
+                                "🖼️  {} image-only page(s){}",
+                                self.image_only_pages,
+                                if config.announce_skipped_images { ", announced as illustrations" } else { " skipped" }
+                            );
+                            self.image_only_pages = 0;
+                        }
+                        // The outro follows the last chapter, numbered after it
+                        if let Some(outro) = self.outro.take() {
+                            let order = self.pending.as_ref().map_or(self.first_order, |chapter| chapter.order + 1);
+                            let outro = processor.bookend(OUTRO_TITLE, &outro, order);
+                            if outro.content.trim().is_empty() {
+                                continue;
+                            }
+                            match self.pending.replace(outro) {
+                                Some(last) => return Some(last),
+                                None => continue,
+                            }
+                        }
+                        return self.pending.take();
+                    }
+                },
+            };
+
+            let content_lines = |text: &str| text.lines().filter(|line| !line.trim().is_empty()).count();
+            let lines_before = content_lines(&text);
+            let text = if self.running_headers.is_empty() {
+                text
+            } else {
+                strip_running_headers(&text, &self.running_headers)
+            };
+            let section_audit = config.audit.then(|| ChapterAudit {
+                sources: vec![href.display().to_string()],
+                running_header_lines: lines_before - content_lines(&text),
+                page_numbers: processor.text_processor.count_page_artifacts(&text),
+                tables_removed,
+                abbreviations_expanded: if config.preprocessing_aggressive {
+                    processor.text_processor.count_abbreviations(&text)
+                } else {
+                    0
+                },
+                ..ChapterAudit::default()
+            });
+
+            // Clean the text
+            let mut cleaned_text = processor.text_processor.speech_text(&text, config.preprocessing_aggressive);
+
+            if cleaned_text.trim().is_empty() && has_images {
+                self.image_only_pages += 1;
+                if !config.announce_skipped_images {
+                    debug!("Skipping image-only section of {}", href.display());
+                    processor.audit(|report| report.skip(&href, "image-only section"));
+                    continue;
+                }
+                // A short placeholder, so the listener knows a picture page went by;
+                // the title is left out when it is announced anyway
+                cleaned_text = match &heading {
+                    Some(heading) if !config.announce_titles => {
+                        format!("{} Illustration.", with_terminal_punctuation(heading.trim()))
+                    }
+                    _ => "Illustration.".to_string(),
+                };
+            }
+
+            if cleaned_text.trim().is_empty() {
+                debug!("Skipping section of {}: no speakable text", href.display());
+                processor.audit(|report| report.skip(&href, "no speakable text"));
+                continue;
+            }
+
+            let word_count = cleaned_text.split_whitespace().count();
+
+            // Chapters split across several files only carry a heading in the first one
+            if leading_fragment || (heading.is_none() && config.merge_untitled_fragments) {
+                if let Some(previous) = self.pending.as_mut() {
+                    debug!("Appending {} to \"{}\" ({} words)", href.display(), previous.title, word_count);
+                    previous.content.push_str("\n\n");
+                    previous.content.push_str(&cleaned_text);
+                    previous.word_count += word_count;
+                    if let Some(section) = section_audit {
+                        processor.audit(|report| {
+                            if let Some(chapter) = report.chapters.last_mut() {
+                                chapter.sources.extend(section.sources);
+                                chapter.running_header_lines += section.running_header_lines;
+                                chapter.page_numbers += section.page_numbers;
+                                chapter.tables_removed += section.tables_removed;
+                                chapter.abbreviations_expanded += section.abbreviations_expanded;
+                            }
+                        });
+                    }
+                    continue;
+                }
+            }
+
+            let order = self.pending.as_ref().map_or(self.first_order, |chapter| chapter.order + 1);
+            let title = match &heading {
+                Some(heading) if config.clean_chapter_titles => clean_chapter_title(heading),
+                Some(heading) => heading.clone(),
+                None => format!("Chapter {}", order + 1),
+            };
+            if let Some(section) = section_audit {
+                processor.audit(|report| {
+                    report.chapters.push(ChapterAudit {
+                        order,
+                        title: title.clone(),
+                        original_title: heading.filter(|heading| *heading != title),
+                        ..section
+                    })
+                });
+            }
+            debug!("Chapter {} \"{}\" from {} ({} words)", order, title, href.display(), word_count);
+            let chapter = Chapter {
+                title,
+                content: cleaned_text,
+                order,
+                source_order: order,
+                word_count,
+                bookend: false,
+            };
+            if let Some(finished) = self.pending.replace(chapter) {
+                return Some(finished);
+            }
+        }
+    }
+}
+
+struct EpubProcessor {
+    text_processor: TextProcessor,
+    tts_engine: TTSEngine,
+    config: Config,
+    book: BookMetadata,
+    overrides: ChapterOverrides,
+    text_chapter_regex: Option<Regex>,
+    chunks_processed: AtomicUsize,
+    // Filled while chapters are extracted when --audit is on
+    cleaning_report: Mutex<Option<CleaningReport>>,
+}
+
+// Audio files written for one chapter, in playback order
+#[derive(Debug, Clone, Serialize)]
+struct ChapterOutput {
+    order: usize,
+    title: String,
+    files: Vec<ChunkOutput>,
+    // False when the run was interrupted before every chunk was written
+    complete: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ChunkOutput {
+    path: PathBuf,
+    duration_secs: f64,
+}
+
+impl ChapterOutput {
+    fn duration_secs(&self) -> f64 {
+        self.files.iter().map(|file| file.duration_secs).sum()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ConversionSummary {
+    chapters: usize,
+    chunks: usize,
+    cache_hits: usize,
+    cache_misses: usize,
+    cache_hit_rate: f64,
+    // Repeated chunks encoded from audio synthesized earlier in the same run
+    duplicate_chunks: usize,
+    audio_duration_secs: f64,
+    wall_time_secs: f64,
+    real_time_factor: f64,
+}
+
+impl ConversionSummary {
+    fn log(&self) {
+        info!("📈 Conversion summary:");
+        info!("   Chapters: {}, chunks: {}", self.chapters, self.chunks);
+        info!(
+            "   Cache: {} hits, {} misses ({:.0}% hit rate)",
+            self.cache_hits,
+            self.cache_misses,
+            self.cache_hit_rate * 100.0
+        );
+        if self.duplicate_chunks > 0 {
+            info!("   Repeated text: {} syntheses saved", self.duplicate_chunks);
+        }
+        info!(
+            "   Audio: {} produced in {} ({:.1}x real time)",
+            format_duration(Duration::from_secs_f64(self.audio_duration_secs)),
+            format_duration(Duration::from_secs_f64(self.wall_time_secs)),
+            self.real_time_factor
+        );
+    }
+}
+
+impl EpubProcessor {
+    fn new(config: Config) -> Result<Self, Box<dyn std::error::Error>> {
+        let tts_engine = TTSEngine::new(config.clone())?;
+        let text_chapter_regex = match &config.text_chapter_regex {
+            Some(pattern) => Some(Regex::new(pattern).map_err(|e| format!("Invalid --split-regex: {}", e))?),
+            None => None,
+        };
+        Ok(Self {
+            text_processor: TextProcessor::new(&config),
+           tts_engine,
+           cleaning_report: Mutex::new(config.audit.then(CleaningReport::default)),
+           config,
+           book: BookMetadata::default(),
+           overrides: ChapterOverrides::default(),
+           text_chapter_regex,
+           chunks_processed: AtomicUsize::new(0),
+        })
+    }
+
+    fn set_book(&mut self, book_id: &str, metadata: BookMetadata) {
+        self.tts_engine.set_book(book_id);
+        self.book = metadata;
+    }
+
+    fn set_overrides(&mut self, overrides: ChapterOverrides) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(synthesizer) = &self.tts_engine.synthesizer {
+            for voice in overrides.0.values().filter_map(|o| o.voice.as_deref()) {
+                synthesizer.check_voice(voice)?;
+            }
+        }
+        self.overrides = overrides;
+        Ok(())
+    }
+
+    // Records into the --audit report, if one is being kept
+    fn audit(&self, record: impl FnOnce(&mut CleaningReport)) {
+        if let Some(report) = self.cleaning_report.lock().unwrap().as_mut() {
+            record(report);
+        }
+    }
+
+    // Writes the --audit report once; taking it also stops the low-memory second
+    // pass from recording again
+    fn write_cleaning_report(&self, output_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(report) = self.cleaning_report.lock().unwrap().take() {
+            report.write(output_dir)?;
+            info!("🧾 Wrote cleaning report to {}", output_dir.join("cleaning_report.json").display());
+        }
+        Ok(())
+    }
+
+    fn extract_chapters(&self, epub_path: &Path) -> Result<Vec<Chapter>, Box<dyn std::error::Error>> {
+        let chapters = self.iter_chapters(epub_path).collect::<Result<Vec<_>, _>>()?;
+        if self.config.chapter_order.is_empty() {
+            Ok(chapters)
+        } else {
+            reorder_chapters(chapters, &self.config.chapter_order, self.config.chapter_number_base)
+        }
+    }
+
+    // An --intro/--outro pseudo-chapter; {title} and {author} in the text are
+    // filled in from the EPUB metadata
+    fn bookend(&self, title: &str, template: &str, order: usize) -> Chapter {
+        let text = template
+            .replace("{title}", &self.book.title)
+            .replace("{author}", self.book.author.as_deref().unwrap_or("an unknown author"));
+        let content = self.text_processor.speech_text(&text, self.config.preprocessing_aggressive);
+        Chapter {
+            title: title.to_string(),
+            word_count: content.split_whitespace().count(),
+            content,
+            order,
+            source_order: order,
+            bookend: true,
+        }
+    }
+
+    // Yields chapters one at a time, so only the chapter being converted has its
+    // text in memory
+    fn iter_chapters<'a>(
+        &'a self,
+        epub_path: &Path,
+    ) -> impl Iterator<Item = Result<Chapter, Box<dyn std::error::Error>>> + 'a {
+        let (chapters, error) = match ChapterIter::new(self, epub_path) {
+            Ok(chapters) => (Some(chapters), None),
+            Err(e) => (None, Some(e)),
+        };
+        error.map(Err).into_iter().chain(chapters.into_iter().flatten().map(Ok))
+    }
+
+    // Plain text of each section of one spine item, before cleaning
+    fn read_sections(&self, doc: &mut EpubDoc<BufReader<File>>, href: &Path) -> Vec<RawSection> {
+        let content = match find_spine_resource(doc, href) {
+            Some(content) => content,
+            None => {
+                warn!("⚠️  Spine item {} is missing from the EPUB; skipping it", href.display());
+                self.audit(|report| report.skip(href, "missing from the EPUB"));
+                return Vec::new();
+            }
+        };
+
+        let html_content = decode_resource(&content, href);
+
+        // Either one section per spine item, or one per heading of the split
+        // level or chapter landmark
+        let sections = match (self.config.split_heading_level, self.config.split_on_pagebreaks) {
+            (Some(level), _) => split_at_headings(&html_content, level),
+            (None, true) => split_at_landmarks(&html_content),
+            (None, false) => vec![(None, html_content.as_str())],
+        };
+        let split = self.config.split_heading_level.is_some() || sections.len() > 1;
+
+        let image_regex = Regex::new(r"(?i)<(?:img|image|svg)\b").unwrap();
+        let table_regex = Regex::new(r"(?i)<table\b").unwrap();
+        let mut raw_sections = Vec::new();
+        for (index, (split_heading, section)) in sections.into_iter().enumerate() {
+            // Extract title from HTML
+            let heading = split_heading.clone().or_else(|| self.extract_title(section));
+
+            let has_images = image_regex.is_match(section);
+            let tables_removed = match self.config.table_mode {
+                TableMode::Skip => table_regex.find_iter(section).count(),
+                _ => 0,
+            };
+            let section_html = self.preprocess_html(section);
+
+            // Convert HTML to plain text
+            let plain_text = from_read(section_html.as_bytes(), self.config.html_wrap_width);
+
+            let pieces = match &self.text_chapter_regex {
+                Some(marker) => split_at_text_markers(&plain_text, marker),
+                None => vec![(None, plain_text)],
+            };
+            let mut pieces = pieces.into_iter();
+            if let Some((_, text)) = pieces.next() {
+                raw_sections.push(RawSection {
+                    href: href.to_path_buf(),
+                    heading,
+                    // Text before the first split heading continues the previous chapter
+                    leading_fragment: split && index == 0 && split_heading.is_none(),
+                    has_images,
+                    tables_removed,
+                    text,
+                });
+            }
+            // Every text marker starts a chapter, titled or not
+            for (heading, text) in pieces {
+                raw_sections.push(RawSection {
+                    href: href.to_path_buf(),
+                    heading,
+                    leading_fragment: false,
+                    has_images: false,
+                    tables_removed: 0,
+                    text,
+                });
+            }
+        }
+        raw_sections
+    }
+
+    fn preprocess_html(&self, html: &str) -> String {
+        let table_regex = Regex::new(r"(?is)<table\b.*?</table>").unwrap();
+
+        // Ruby can sit inside table cells and captions, so it goes first
+        let html = rewrite_ruby(html, self.config.ruby_mode);
+        let html = match self.config.table_mode {
+            TableMode::Keep => html,
+            TableMode::Skip => table_regex.replace_all(&html, "").to_string(),
+            TableMode::Linearize => table_regex
+                .replace_all(&html, |caps: &regex::Captures| linearize_table(&caps[0]))
+                .to_string(),
+        };
+
+        let html = rewrite_images(&html, self.config.image_alt);
+        let html = if self.config.speak_lists { rewrite_lists(&html) } else { html };
+
+        if self.config.mark_blockquotes {
+            mark_blockquotes(&html)
+        } else {
+            html
+        }
+    }
+
+    fn read_metadata(&self, epub_path: &Path) -> Result<BookMetadata, Box<dyn std::error::Error>> {
+        let doc = open_epub(epub_path)?;
+        let config = &self.config;
+        let mdata = |name: &str| doc.mdata(name).filter(|value| !value.trim().is_empty());
+
+        // --title and friends first, then the EPUB's own metadata; the file name
+        // stands in for a missing title
+        Ok(BookMetadata {
+            title: config
+                .book_title
+                .clone()
+                .or_else(|| mdata("title"))
+                .unwrap_or_else(|| book_id(epub_path)),
+            author: config.book_author.clone().or_else(|| mdata("creator")),
+            // EPUB 3 collections, else the calibre:series meta most stores add
+            series: config
+                .book_series
+                .clone()
+                .or_else(|| mdata("belongs-to-collection"))
+                .or_else(|| mdata("calibre:series")),
+            // An unreadable position is dropped rather than failing the book
+            series_index: mdata("calibre:series_index")
+                .or_else(|| mdata("group-position"))
+                .and_then(|value| value.trim().parse::<f32>().ok())
+                .filter(|index| index.is_finite() && *index >= 0.0),
+        })
+    }
+
+    // Copies the EPUB cover image into the output directory
+    fn extract_cover(&self, epub_path: &Path, output_dir: &Path) -> Result<Option<PathBuf>, Box<dyn std::error::Error>> {
+        let mut doc = open_epub(epub_path)?;
+        let (data, mime) = match doc.get_cover() {
+            Some(cover) => cover,
+            None => return Ok(None),
+        };
+
+        let ext = match mime.as_str() {
+            "image/png" => "png",
+            "image/gif" => "gif",
+            "image/webp" => "webp",
+            _ => "jpg",
+        };
+        let cover_path = output_dir.join(format!("cover.{}", ext));
+        fs::write(&cover_path, data)?;
+
+        Ok(Some(cover_path))
+    }
+
+    fn detect_media_overlays(&self, epub_path: &Path) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let mut doc = open_epub(epub_path)?;
+        Ok(self.find_media_overlays(&mut doc))
+    }
+
+    fn find_media_overlays(&self, doc: &mut EpubDoc<BufReader<File>>) -> Vec<String> {
+        let opf = match read_package_document(doc) {
+            Some(opf) => opf,
+            None => return Vec::new(),
+        };
+
+        // EPUB3 links a content document to its SMIL narration through the
+        // manifest item's media-overlay attribute
+        let item_regex = Regex::new(r"(?is)<item\b[^>]*>").unwrap();
+        let overlay_regex = Regex::new(r#"media-overlay\s*=\s*["']([^"']+)["']"#).unwrap();
+        let href_regex = Regex::new(r#"\bhref\s*=\s*["']([^"']+)["']"#).unwrap();
+
+        item_regex
+            .find_iter(&opf)
+            .filter(|item| overlay_regex.is_match(item.as_str()))
+            .filter_map(|item| href_regex.captures(item.as_str()))
+            .map(|captures| captures[1].to_string())
+            .collect()
+    }
+
+    fn extract_title(&self, html: &str) -> Option<String> {
+        // Try to extract title from h1, h2, h3 tags
+        let title_regex = Regex::new(r"<h[1-3][^>]*>([^<]+)</h[1-3]>").unwrap();
+
+        if let Some(captures) = title_regex.captures(html) {
+            let title = captures.get(1).unwrap().as_str();
+            return Some(from_read(title.as_bytes(), self.config.html_wrap_width).trim().to_string());
+        }
+
+        None
+    }
+
+    fn process_chapters(
+        &self,
+        chapters: Vec<Chapter>,
+        output_dir: &Path,
+        on_progress: impl Fn(ProgressEvent) + Sync,
+    ) -> Result<Vec<ChapterOutput>, Box<dyn std::error::Error>> {
+        fs::create_dir_all(output_dir)?;
+
+        let total_tracks = chapters.len();
+        let chapter_count = chapters.iter().map(|c| c.order + 1).max().unwrap_or(0);
+
+        // Epigraphs, section dividers and sample notices recur; find them up front
+        // so each is synthesized once rather than once per chapter
+        let mut occurrences: HashMap<String, usize> = HashMap::new();
+        for chapter in &chapters {
+            for chunk in self.chapter_chunks(chapter) {
+                *occurrences.entry(chunk).or_default() += 1;
+            }
+        }
+        self.tts_engine.expect_repeats(
+            occurrences
+                .into_iter()
+                .filter(|(_, count)| *count > 1)
+                .map(|(chunk, _)| chunk)
+                .collect(),
+        );
+
+        let result = chapters
+        .into_par_iter()
+        .enumerate()
+        .map(|(index, chapter)| {
+            self.process_tracked_chapter(&chapter, output_dir, (index + 1, total_tracks), chapter_count, &on_progress)
+        })
+        .collect::<Result<Vec<_>, _>>();
+
+        // Keep the cache index in step with whatever was synthesized, even on failure
+        self.tts_engine.save_cache_index()?;
+        let mut outputs = result.map_err(|e| e as Box<dyn std::error::Error>)?;
+        outputs.sort_by_key(|output| output.order);
+
+        Ok(outputs)
+    }
+
+    // Converts chapters one after another as they are extracted, so each
+    // chapter's text is dropped before the next is read. Repeated text is not
+    // shared between chapters, since that needs the whole book up front.
+    fn process_chapters_lazily(
+        &self,
+        chapters: impl Iterator<Item = Result<Chapter, Box<dyn std::error::Error>>>,
+        chapter_count: usize,
+        output_dir: &Path,
+        on_progress: impl Fn(ProgressEvent) + Sync,
+    ) -> Result<Vec<ChapterOutput>, Box<dyn std::error::Error>> {
+        fs::create_dir_all(output_dir)?;
+
+        let mut outputs = Vec::new();
+        let mut result = Ok(());
+        for (index, chapter) in chapters.enumerate() {
+            result = chapter.and_then(|chapter| {
+                let output = self.process_tracked_chapter(
+                    &chapter,
+                    output_dir,
+                    (index + 1, chapter_count),
+                    chapter_count,
+                    &on_progress,
+                )
+                .map_err(|e| e as Box<dyn std::error::Error>)?;
+                outputs.push(output);
+                Ok(())
+            });
+            if result.is_err() {
+                break;
+            }
+        }
+
+        // Keep the cache index in step with whatever was synthesized, even on failure
+        self.tts_engine.save_cache_index()?;
+        result?;
+
+        Ok(outputs)
+    }
+
+    // One chapter with progress events around it; once interrupted, remaining
+    // chapters are reported incomplete without being converted
+    fn process_tracked_chapter(
+        &self,
+        chapter: &Chapter,
+        output_dir: &Path,
+        tracks: (usize, usize),
+        chapter_count: usize,
+        on_progress: &(dyn Fn(ProgressEvent) + Sync),
+    ) -> Result<ChapterOutput, Box<dyn std::error::Error + Send + Sync>> {
+        if interrupted() {
+            return Ok(ChapterOutput {
+                order: chapter.order,
+                title: chapter.title.clone(),
+                files: Vec::new(),
+                complete: false,
+            });
+        }
+        on_progress(ProgressEvent::ChapterStarted {
+            order: chapter.order,
+            title: chapter.title.clone(),
+        });
+        let output = self.process_single_chapter(chapter, output_dir, tracks, chapter_count, on_progress)?;
+        if output.complete {
+            on_progress(ProgressEvent::ChapterCompleted { order: chapter.order });
+        }
+        Ok(output)
+    }
+
+    fn summary(&self, outputs: &[ChapterOutput], wall_time: Duration) -> ConversionSummary {
+        let cache_hits = self.tts_engine.cache_hits.load(Ordering::Relaxed);
+        let cache_misses = self.tts_engine.cache_misses.load(Ordering::Relaxed);
+        let audio_duration_secs: f64 = outputs.iter().map(ChapterOutput::duration_secs).sum();
+        let wall_time_secs = wall_time.as_secs_f64();
+
+        ConversionSummary {
+            chapters: outputs.len(),
+            chunks: self.chunks_processed.load(Ordering::Relaxed),
+            cache_hits,
+            cache_misses,
+            cache_hit_rate: if cache_hits + cache_misses > 0 {
+                cache_hits as f64 / (cache_hits + cache_misses) as f64
+            } else {
+                0.0
+            },
+            duplicate_chunks: self.tts_engine.duplicates_reused.load(Ordering::Relaxed),
+            audio_duration_secs,
+            wall_time_secs,
+            real_time_factor: if wall_time_secs > 0.0 {
+                audio_duration_secs / wall_time_secs
+            } else {
+                0.0
+            },
+        }
+    }
+
+    fn process_single_chapter(
+        &self,
+        chapter: &Chapter,
+        output_dir: &Path,
+        (track, total_tracks): (usize, usize),
+        chapter_count: usize,
+        on_progress: &(dyn Fn(ProgressEvent) + Sync),
+    ) -> Result<ChapterOutput, Box<dyn std::error::Error + Send + Sync>> {
+        let safe_title = sanitize_filename(&chapter.title);
+        let chapter_number = self.config.chapter_number(chapter.order, chapter_count);
+        let chapter_dir = output_dir.join(format!("{}_{}", chapter_number, safe_title));
+
+        // --join-chunks removed the chunk files of a chapter it joined in an
+        // earlier run, so its joined file is what --resume keeps
+        if self.config.output_policy == OutputPolicy::Resume && self.config.join_chunks {
+            let joined = self.joined_chapter_path(output_dir, chapter.order, &chapter.title, chapter_count);
+            if fs::metadata(&joined).map(|m| m.len() > 0).unwrap_or(false) {
+                debug!("Keeping existing {}", joined.display());
+                return Ok(ChapterOutput {
+                    order: chapter.order,
+                    title: chapter.title.clone(),
+                    files: vec![ChunkOutput {
+                        path: joined,
+                        duration_secs: 0.0,
+                    }],
+                    complete: true,
+                });
+            }
+        }
+        fs::create_dir_all(&chapter_dir)?;
+
+        let chunks = self.chapter_chunks(chapter);
+
+        if self.config.emit_transcripts {
+            let transcript_path = chapter_dir.join(format!("{}_{}.txt", chapter_number, safe_title));
+            let text: Vec<&str> = chunks.iter().map(String::as_str).filter(|chunk| *chunk != PAUSE_MARKER).collect();
+            fs::write(transcript_path, text.join("\n\n") + "\n")?;
+        }
+
+        let voice = self.overrides.apply(&self.config, chapter.order);
+
+        // Titles are read a little slower to set them apart from the body
+        let mut title_voice = voice.clone();
+        title_voice.voice_speed *= 0.9;
+
+        let mut files = Vec::new();
+        let mut spoken = Vec::new();
+        let first_chunk = chunks.iter().position(|chunk| !chunk.trim().is_empty());
+        let last_chunk = chunks.iter().rposition(|chunk| !chunk.trim().is_empty());
+
+        let chunk_path = |chunk_idx: usize| {
+            chapter_dir.join(render_filename_template(
+                &self.config.filename_template,
+                &[
+                    ("chapter_order", chapter_number.clone()),
+                    ("chapter_title", chapter.title.clone()),
+                    ("chunk", format!("{:03}", chunk_idx)),
+                    ("book_title", self.book.title.clone()),
+                    ("author", self.book.author.clone().unwrap_or_default()),
+                    ("ext", self.get_file_extension().to_string()),
+                ],
+            ))
+        };
+
+        // Without {chunk}, a template can name every chunk of a chapter alike,
+        // and each would overwrite the one before
+        let mut named = HashMap::new();
+        for chunk_idx in (0..chunks.len()).filter(|&idx| !chunks[idx].trim().is_empty()) {
+            if let Some(earlier) = named.insert(chunk_path(chunk_idx), chunk_idx) {
+                return Err(format!(
+                    "Name template \"{}\" gives chunks {} and {} of \"{}\" the same file name; add {{chunk}} to it",
+                    self.config.filename_template, earlier, chunk_idx, chapter.title
+                )
+                .into());
+            }
+        }
+
+        // Synthesis runs one chunk ahead of encoding; the channel holds a
+        // single rendered chunk, and chunks still arrive in order
+        let complete = std::thread::scope(|scope| -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+            let (sender, receiver) = mpsc::sync_channel(1);
+            scope.spawn(|| {
+                let sender = sender;
+                for (chunk_idx, chunk) in chunks.iter().enumerate() {
+                    if chunk.trim().is_empty() {
+                        continue;
+                    }
+                    if interrupted() {
+                        break;
+                    }
+
+                    let output_path = chunk_path(chunk_idx);
+                    let rendered = if self.config.output_policy == OutputPolicy::Resume
+                        && fs::metadata(&output_path).map(|m| m.len() > 0).unwrap_or(false)
+                    {
+                        None
+                    } else {
+                        let chunk_voice = if self.config.announce_titles && !chapter.bookend && chunk_idx == 0 {
+                            &title_voice
+                        } else {
+                            &voice
+                        };
+                        let result = if chunk == PAUSE_MARKER {
+                            let scale = self.config.pause_scale.max(1.0);
+                            self.tts_engine.render_pause((BLOCKQUOTE_PAUSE_MS as f32 * scale) as u64)
+                        } else {
+                            self.tts_engine.render(chunk, chunk_voice)
+                        };
+                        Some(result.map_err(sendable_error))
+                    };
+
+                    let failed = matches!(rendered, Some(Err(_)));
+                    // A closed channel means the encoding side gave up
+                    if sender.send((chunk_idx, output_path, rendered)).is_err() || failed {
+                        break;
+                    }
+                }
+            });
+
+            let mut last_done = None;
+            for (chunk_idx, output_path, rendered) in receiver {
+                let rendered = match rendered {
+                    Some(Ok(rendered)) => rendered,
+                    None => {
+                        debug!("Keeping existing {}", output_path.display());
+                        spoken.push((chunk_idx, probe_duration_secs(&output_path).unwrap_or(0.0)));
+                        files.push(ChunkOutput {
+                            path: output_path,
+                            duration_secs: 0.0,
+                        });
+                        last_done = Some(chunk_idx);
+                        continue;
+                    }
+                    // A terminal Ctrl-C also reaches espeak and the encoders; the
+                    // chunk never landed, so --resume redoes it
+                    Some(Err(_)) if interrupted() => break,
+                    Some(Err(e)) => return Err(chunk_error(chunk_idx, e)),
+                };
+                if let Some(parent) = output_path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+
+                let tags = match self.config.tag_preset {
+                    TagPreset::None => None,
+                    TagPreset::Audiobook => Some(AudioTags {
+                        title: if chunks.len() > 1 {
+                            format!("{} (part {})", chapter.title, chunk_idx + 1)
+                        } else {
+                            chapter.title.clone()
+                        },
+                        album: self.book.title.clone(),
+                        artist: self.book.author.clone(),
+                        series: self.book.series.clone(),
+                        series_part: self.book.series_part(),
+                        album_sort: self.book.sort_title(),
+                        track,
+                        total_tracks,
+                    }),
+                };
+
+                // Fade in at the start of the chapter and out at its end
+                let fade = Fade {
+                    in_ms: if Some(chunk_idx) == first_chunk { self.config.fade_ms } else { 0 },
+                    out_ms: if Some(chunk_idx) == last_chunk { self.config.fade_ms } else { 0 },
+                };
+
+                let duration_secs = match self.tts_engine.encode_chunk(&rendered.path, &output_path, tags.as_ref(), fade) {
+                    Ok(duration_secs) => duration_secs,
+                    Err(_) if interrupted() => break,
+                    Err(e) => return Err(chunk_error(chunk_idx, sendable_error(e))),
+                };
+                self.chunks_processed.fetch_add(1, Ordering::Relaxed);
+
+                if rendered.cache_hit {
+                    on_progress(ProgressEvent::CacheHit {
+                        order: chapter.order,
+                        chunk: chunk_idx,
+                    });
+                }
+                on_progress(ProgressEvent::ChunkCompleted {
+                    order: chapter.order,
+                    chunk: chunk_idx,
+                    total_chunks: chunks.len(),
+                });
+
+                spoken.push((chunk_idx, duration_secs));
+                files.push(ChunkOutput {
+                    path: output_path,
+                    duration_secs,
+                });
+                last_done = Some(chunk_idx);
+            }
+
+            // Stopping short of the last chunk means a Ctrl-C cut the chapter off
+            Ok(last_done == last_chunk)
+        })?;
+
+        if let (Some(format), true) = (self.config.subtitles, complete) {
+            let cues = self.subtitle_cues(&chunks, &spoken);
+            let subtitle_path = chapter_dir.join(format!("{}_{}.{}", chapter_number, safe_title, format.extension()));
+            fs::write(subtitle_path, format.render(&cues))?;
+        }
+
+        // Create metadata file
+        let metadata = serde_json::json!({
+            "title": chapter.title,
+            "order": chapter.order,
+            "source_order": chapter.source_order,
+            "word_count": chapter.word_count,
+            "chunks": chunks.len(),
+                                         "config": self.config
+        });
+
+        let metadata_path = chapter_dir.join("metadata.json");
+        let metadata_file = File::create(metadata_path)?;
+        serde_json::to_writer_pretty(metadata_file, &metadata)?;
+
+        Ok(ChapterOutput {
+            order: chapter.order,
+            title: chapter.title.clone(),
+            files,
+            complete,
+        })
+    }
+
+    // Split chapter into chunks for better TTS processing, led by the
+    // spoken title when announcements are on
+    fn chapter_chunks(&self, chapter: &Chapter) -> Vec<String> {
+        let mut chunks = Vec::new();
+        if self.config.announce_titles && !chapter.bookend {
+            chunks.push(chapter.title_announcement(self.config.spoken_chapter_number(chapter.order)));
+        }
+        // Blockquote markers become pause chunks of their own; back-to-back
+        // markers (nested or adjacent quotes) make a single pause
+        for (i, segment) in chapter.content.split(PAUSE_MARKER).enumerate() {
+            if i > 0 && chunks.last().map(String::as_str) != Some(PAUSE_MARKER) {
+                chunks.push(PAUSE_MARKER.to_string());
+            }
+            chunks.extend(self.text_processor.split_into_chunks(
+                segment,
+                self.config.min_chunk_size,
+                self.config.max_chunk_size,
+            ));
+        }
+        chunks
+    }
+
+    // One cue per sentence. espeak gives no word timings, so each chunk's length
+    // is shared among its sentences by character count.
+    fn subtitle_cues(&self, chunks: &[String], spoken: &[(usize, f64)]) -> Vec<(f64, f64, String)> {
+        let mut cues = Vec::new();
+        let mut chunk_start = 0.0;
+        for &(chunk_idx, duration_secs) in spoken {
+            if chunks[chunk_idx] == PAUSE_MARKER {
+                chunk_start += duration_secs;
+                continue;
+            }
+            let sentences = self.text_processor.sentences(&chunks[chunk_idx]);
+            let total_chars: usize = sentences.iter().map(|s| s.chars().count()).sum();
+            let mut cue_start = chunk_start;
+            for sentence in sentences {
+                let share = sentence.chars().count() as f64 / total_chars.max(1) as f64;
+                let cue_end = cue_start + duration_secs * share;
+                cues.push((cue_start, cue_end, sentence.to_string()));
+                cue_start = cue_end;
+            }
+            chunk_start += duration_secs;
+        }
+        cues
+    }
+
+    fn get_file_extension(&self) -> &'static str {
+        self.config.output_format.extension()
+    }
+
+    // Where --join-chunks writes a chapter: beside the chapter folders, where
+    // it cannot take the name of one of the chunks it replaces
+    fn joined_chapter_path(&self, output_dir: &Path, order: usize, title: &str, chapter_count: usize) -> PathBuf {
+        output_dir.join(format!(
+            "{}_{}.{}",
+            self.config.chapter_number(order, chapter_count),
+            sanitize_filename(title),
+            self.get_file_extension()
+        ))
+    }
+}
+
+fn compile_href_patterns(patterns: &[String]) -> Result<Vec<glob::Pattern>, Box<dyn std::error::Error>> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            glob::Pattern::new(pattern).map_err(|e| format!("Invalid href pattern \"{}\": {}", pattern, e).into())
+        })
+        .collect()
+}
+
+// A pattern matches the full href ("OEBPS/Text/ch*.xhtml") or just its file name ("ch*.xhtml")
+fn href_matches(patterns: &[glob::Pattern], href: &Path) -> bool {
+    let full = href.to_string_lossy();
+    let name = href.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    patterns.iter().any(|pattern| pattern.matches(&full) || pattern.matches(&name))
+}
+
+// Spine hrefs may carry %-escapes or a #fragment that the archive path does not,
+// and some packagers get the case wrong; try progressively looser matches
+fn find_spine_resource(doc: &mut EpubDoc<BufReader<File>>, href: &Path) -> Option<Vec<u8>> {
+    if let Some(content) = doc.get_resource_by_path(href) {
+        return Some(content.0);
+    }
+
+    let raw = href.to_string_lossy();
+    let without_fragment = raw.split('#').next().unwrap_or(&raw);
+    let decoded = percent_decode(without_fragment);
+    if let Some(content) = doc.get_resource_by_path(&decoded) {
+        debug!("Resolved spine item {} as {}", raw, decoded);
+        return Some(content.0);
+    }
+
+    let wanted = normalize_resource_path(&decoded);
+    let candidate = doc
+        .resources
+        .values()
+        .map(|resource| resource.0.clone())
+        .find(|path| {
+            let path = normalize_resource_path(&path.to_string_lossy());
+            path == wanted || path.ends_with(&format!("/{}", wanted))
+        })?;
+    debug!("Resolved spine item {} as {}", raw, candidate.display());
+    doc.get_resource_by_path(&candidate).map(|content| content.0)
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn normalize_resource_path(path: &str) -> String {
+    path.replace('\\', "/")
+        .split('/')
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect::<Vec<_>>()
+        .join("/")
+        .to_lowercase()
+}
+
+// Decodes a spine document, honouring its declared charset when it is not UTF-8
+fn decode_resource(bytes: &[u8], href: &Path) -> String {
+    let bytes = bytes.strip_prefix(b"\xef\xbb\xbf").unwrap_or(bytes);
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return text.to_string();
+    }
+
+    let head = String::from_utf8_lossy(&bytes[..bytes.len().min(1024)]);
+    let charset_regex = Regex::new(
+        r#"(?i)(?:<\?xml[^>]*\bencoding\s*=\s*["']|<meta[^>]*\bcharset\s*=\s*["']?)([\w.:-]+)"#,
+    )
+    .unwrap();
+
+    if let Some(caps) = charset_regex.captures(&head) {
+        let label = &caps[1];
+        match Encoding::for_label(label.as_bytes()) {
+            Some(encoding) => {
+                let (text, had_errors) = encoding.decode_without_bom_handling(bytes);
+                if !had_errors {
+                    debug!("Decoded {} as {}", href.display(), encoding.name());
+                    return text.to_string();
+                }
+                warn!("⚠️  {} is not valid {}; some characters will be replaced", href.display(), label);
+            }
+            None => warn!("⚠️  {} declares unknown charset {}", href.display(), label),
+        }
+    } else {
+        warn!("⚠️  {} is not valid UTF-8; some characters will be replaced", href.display());
+    }
+
+    String::from_utf8_lossy(bytes).to_string()
+}
+
+// Lines checked at each end of a section when looking for running headers
+const RUNNING_HEADER_LINES: usize = 3;
+
+// A line is a running header or footer when it sits at the top or bottom of more
+// than 60% of sections; short lines only, so real prose is never matched
+fn find_running_headers(texts: &[&str]) -> BTreeSet<String> {
+    if texts.len() < 3 {
+        return BTreeSet::new();
+    }
+
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for text in texts {
+        let edges: BTreeSet<String> = edge_lines(text).into_iter().map(str::to_string).collect();
+        for line in edges {
+            *counts.entry(line).or_insert(0) += 1;
+        }
+    }
+
+    counts
+        .into_iter()
+        .filter(|(line, count)| line.chars().count() <= 100 && *count * 10 > texts.len() * 6)
+        .map(|(line, _)| line)
+        .collect()
+}
+
+fn edge_lines(text: &str) -> Vec<&str> {
+    let lines: Vec<&str> = text.lines().map(str::trim).filter(|line| !line.is_empty()).collect();
+    let head = lines.iter().take(RUNNING_HEADER_LINES);
+    let tail = lines.iter().rev().take(RUNNING_HEADER_LINES);
+    head.chain(tail).copied().collect()
+}
+
+fn strip_running_headers(text: &str, headers: &BTreeSet<String>) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let content: Vec<usize> = (0..lines.len()).filter(|&i| !lines[i].trim().is_empty()).collect();
+
+    // Only the lines at either end are candidates, never repeats mid-text
+    let edges: BTreeSet<usize> = content
+        .iter()
+        .take(RUNNING_HEADER_LINES)
+        .chain(content.iter().rev().take(RUNNING_HEADER_LINES))
+        .copied()
+        .filter(|&i| headers.contains(lines[i].trim()))
+        .collect();
+
+    lines
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !edges.contains(i))
+        .map(|(_, line)| *line)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Splits a document at each <hN> of the given level. The leading fragment has no
+// heading; every other section starts at its heading and runs to the next one
+fn split_at_headings(html: &str, level: u8) -> Vec<(Option<String>, &str)> {
+    let heading_regex = Regex::new(&format!(r"(?is)<h{0}\b[^>]*>(.*?)</h{0}>", level)).unwrap();
+    let tag_regex = Regex::new(r"(?s)<[^>]+>").unwrap();
+
+    let starts: Vec<(usize, String)> = heading_regex
+        .captures_iter(html)
+        .map(|caps| {
+            let text = tag_regex.replace_all(&caps[1], " ");
+            let title = text.split_whitespace().collect::<Vec<_>>().join(" ");
+            (caps.get(0).unwrap().start(), title)
+        })
+        .collect();
+
+    let first_start = starts.first().map(|(start, _)| *start).unwrap_or(html.len());
+    let mut sections = vec![(None, &html[..first_start])];
+    for (index, (start, title)) in starts.iter().enumerate() {
+        let end = starts.get(index + 1).map(|(next, _)| *next).unwrap_or(html.len());
+        let title = Some(title.clone()).filter(|title| !title.is_empty());
+        sections.push((title, &html[*start..end]));
+    }
+
+    sections
+}
+
+// Splits a document where chapter starts are marked up rather than headed:
+// elements with epub:type "chapter", and inline page-break-before: always or
+// break-before: page styles. Print page markers (epub:type "pagebreak") are
+// not chapter starts and are left alone. A document with no landmarks stays
+// whole; sections take their title from their own heading, if any.
+fn split_at_landmarks(html: &str) -> Vec<(Option<String>, &str)> {
+    let landmark_regex = Regex::new(concat!(
+        r#"(?is)<\w+\b[^>]*?"#,
+        r#"(?:epub:type\s*=\s*["'][^"']*\bchapter\b[^"']*["']"#,
+        r#"|style\s*=\s*["'][^"']*\b(?:page-break-before\s*:\s*always|break-before\s*:\s*page)[^"']*["'])"#,
+    ))
+    .unwrap();
+
+    let starts: Vec<usize> = landmark_regex.find_iter(html).map(|landmark| landmark.start()).collect();
+    let Some(&first_start) = starts.first() else {
+        return vec![(None, html)];
+    };
+
+    let mut sections = vec![(None, &html[..first_start])];
+    for (index, start) in starts.iter().enumerate() {
+        let end = starts.get(index + 1).copied().unwrap_or(html.len());
+        sections.push((None, &html[*start..end]));
+    }
+    sections
+}
+
+// Splits flattened text at lines matching --split-regex. A matched line with
+// words in it ("CHAPTER TWO") titles the chapter it starts and is kept, as
+// headings are; a bare divider ("* * *") is dropped and lends the title to a
+// short line right after it, if there is one
+fn split_at_text_markers(text: &str, marker: &Regex) -> Vec<(Option<String>, String)> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut sections = vec![(None, String::new())];
+    for (i, line) in lines.iter().enumerate() {
+        if !marker.is_match(line) {
+            let body = &mut sections.last_mut().unwrap().1;
+            body.push_str(line);
+            body.push('\n');
+            continue;
+        }
+        let title = line.trim();
+        if title.chars().any(char::is_alphanumeric) {
+            sections.push((Some(title.to_string()), format!("{}\n", line)));
+        } else {
+            let next = lines[i + 1..].iter().map(|line| line.trim()).find(|line| !line.is_empty());
+            let heading = next.filter(|line| line.chars().count() <= 80 && !marker.is_match(line));
+            sections.push((heading.map(str::to_string), String::new()));
+        }
+    }
+    sections
+}
+
+fn linearize_table(table_html: &str) -> String {
+    let row_regex = Regex::new(r"(?is)<tr\b[^>]*>(.*?)</tr>").unwrap();
+    let cell_regex = Regex::new(r"(?is)<(t[hd])\b[^>]*>(.*?)</t[hd]>").unwrap();
+    let tag_regex = Regex::new(r"(?s)<[^>]+>").unwrap();
+
+    let mut rows: Vec<Vec<(bool, String)>> = row_regex
+        .captures_iter(table_html)
+        .map(|row| {
+            cell_regex
+                .captures_iter(&row[1])
+                .map(|cell| {
+                    let text = tag_regex.replace_all(&cell[2], " ");
+                    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                    (cell[1].eq_ignore_ascii_case("th"), text)
+                })
+                .collect::<Vec<_>>()
+        })
+        .filter(|cells| cells.iter().any(|(_, text)| !text.is_empty()))
+        .collect();
+
+    // A leading row of header cells names the columns for every later row
+    let headers: Vec<String> = match rows.first() {
+        Some(first) if first.iter().all(|(is_header, _)| *is_header) => {
+            rows.remove(0).into_iter().map(|(_, text)| text).collect()
+        }
+        _ => Vec::new(),
+    };
+
+    let mut sentences = String::new();
+    for row in rows {
+        let parts: Vec<String> = row
+            .into_iter()
+            .enumerate()
+            .filter(|(_, (_, text))| !text.is_empty())
+            .map(|(column, (_, text))| match headers.get(column) {
+                Some(header) if !header.is_empty() => format!("{}: {}", header, text),
+                _ => text,
+            })
+            .collect();
+
+        sentences.push_str(&format!("<p>{}</p>", with_terminal_punctuation(&parts.join(", "))));
+    }
+
+    sentences
+}
+
+// html2text would read an image as "[alt text]" and a caption as plain text;
+// each mode keeps at most one of the two, as its own "Figure:" sentence
+fn rewrite_images(html: &str, mode: ImageMode) -> String {
+    let image_regex = Regex::new(r"(?is)<(?:img|image)\b[^>]*>").unwrap();
+    let alt_regex = Regex::new(r#"(?is)\balt\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap();
+    let caption_regex = Regex::new(r"(?is)<figcaption\b[^>]*>(.*?)</figcaption\s*>").unwrap();
+    let tag_regex = Regex::new(r"(?s)<[^>]+>").unwrap();
+
+    let figure = |text: &str| {
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        if text.is_empty() {
+            String::new()
+        } else {
+            format!("<p>Figure: {}</p>", with_terminal_punctuation(&text))
+        }
+    };
+
+    match mode {
+        ImageMode::Skip => {
+            let html = image_regex.replace_all(html, "");
+            caption_regex.replace_all(&html, "").to_string()
+        }
+        ImageMode::Alt => {
+            let html = image_regex.replace_all(html, |caps: &regex::Captures| {
+                alt_regex
+                    .captures(&caps[0])
+                    .and_then(|alt| alt.get(1).or_else(|| alt.get(2)))
+                    .map(|alt| figure(alt.as_str()))
+                    .unwrap_or_default()
+            });
+            caption_regex.replace_all(&html, "").to_string()
+        }
+        ImageMode::Caption => {
+            let html = image_regex.replace_all(html, "");
+            caption_regex
+                .replace_all(&html, |caps: &regex::Captures| figure(&tag_regex.replace_all(&caps[1], " ")))
+                .to_string()
+        }
+    }
+}
+
+// Lists become paragraphs before html2text can draw its "*" and "1." markers,
+// which the text alone cannot tell from dialogue dashes, "* * *" dividers or
+// a paragraph starting with a year. Each item ends in punctuation for a pause,
+// and numbered items are read as "One. First item."
+fn rewrite_lists(html: &str) -> String {
+    let list_regex = Regex::new(r"(?is)<(/?)(ol|ul|li)\b([^>]*)>").unwrap();
+    let start_regex = Regex::new(r#"(?i)\bstart\s*=\s*["']?(\d+)"#).unwrap();
+    let tag_regex = Regex::new(r"(?s)<[^>]+>").unwrap();
+
+    // Ends the open item, if any, whose text starts at `item` in `out`
+    let close_item = |out: &mut String, item: &mut Option<usize>| {
+        if let Some(start) = item.take() {
+            out.truncate(out.trim_end().len().max(start));
+            let text = tag_regex.replace_all(&out[start..], "");
+            let text = text.trim_end();
+            if !text.trim().is_empty() {
+                let punctuated = with_terminal_punctuation(text);
+                out.push_str(&punctuated[text.len()..]);
+            }
+            out.push_str("</p>");
+        }
+    };
+
+    let mut out = String::with_capacity(html.len());
+    // The next number of each open <ol>, None for a <ul>
+    let mut lists: Vec<Option<u64>> = Vec::new();
+    let mut item = None;
+    let mut last = 0;
+    for caps in list_regex.captures_iter(html) {
+        let tag = caps.get(0).unwrap();
+        out.push_str(&html[last..tag.start()]);
+        last = tag.end();
+
+        // A nested list ends the text of the item holding it
+        close_item(&mut out, &mut item);
+        let closing = !caps[1].is_empty();
+        match (caps[2].to_ascii_lowercase().as_str(), closing) {
+            ("ol", false) => lists.push(Some(
+                start_regex.captures(&caps[3]).and_then(|start| start[1].parse().ok()).unwrap_or(1),
+            )),
+            ("ul", false) => lists.push(None),
+            ("li", false) => {
+                out.push_str("<p>");
+                if let Some(Some(number)) = lists.last_mut() {
+                    out.push_str(&format!("{}. ", capitalize(&number_to_words(*number))));
+                    *number += 1;
+                }
+                item = Some(out.len());
+            }
+            ("ol" | "ul", true) => {
+                lists.pop();
+            }
+            _ => {}
+        }
+    }
+    close_item(&mut out, &mut item);
+    out.push_str(&html[last..]);
+    out
+}
+
+// html2text runs a <ruby> element's base text and its <rt> annotation together
+// ("漢字かんじ"), so each one is replaced by just one of the two. <rp>
+// fallback parentheses and <rtc> glosses go either way; ruby without an
+// annotation keeps its base text.
+fn rewrite_ruby(html: &str, mode: RubyMode) -> String {
+    let ruby_regex = Regex::new(r"(?is)<ruby\b[^>]*>(.*?)</ruby\s*>").unwrap();
+    let extra_regex = Regex::new(r"(?is)<(?:rp|rtc)\b[^>]*>.*?</(?:rp|rtc)\s*>").unwrap();
+    let reading_regex = Regex::new(r"(?is)<rt\b[^>]*>(.*?)</rt\s*>").unwrap();
+    let tag_regex = Regex::new(r"(?s)<[^>]+>").unwrap();
+
+    ruby_regex
+        .replace_all(html, |caps: &regex::Captures| {
+            let ruby = extra_regex.replace_all(&caps[1], "");
+            let base = tag_regex.replace_all(&reading_regex.replace_all(&ruby, ""), "").to_string();
+            let reading: String = reading_regex
+                .captures_iter(&ruby)
+                .map(|rt| tag_regex.replace_all(&rt[1], "").to_string())
+                .collect();
+            match mode {
+                RubyMode::Reading if !reading.trim().is_empty() => reading,
+                _ => base,
+            }
+        })
+        .to_string()
+}
+
+// Stands alone as a paragraph through html2text and cleanup, and is spoken as
+// silence
+const PAUSE_MARKER: &str = "\u{E000}";
+const BLOCKQUOTE_PAUSE_MS: u64 = 700;
+
+// Holds a paragraph break's place through whitespace normalization when
+// --pause-scale needs to find it again
+const PARAGRAPH_MARK: char = '\u{E001}';
+const PAUSE_ELLIPSIS: &str = " ...";
+
+fn mark_blockquotes(html: &str) -> String {
+    let open_regex = Regex::new(r"(?i)<blockquote\b[^>]*>").unwrap();
+    let close_regex = Regex::new(r"(?i)</blockquote\s*>").unwrap();
+    let marker = format!("<p>{}</p>", PAUSE_MARKER);
+
+    let html = open_regex.replace_all(html, |caps: &regex::Captures| format!("{}{}", marker, &caps[0]));
+    close_regex
+        .replace_all(&html, |caps: &regex::Captures| format!("{}{}", &caps[0], marker))
+        .to_string()
+}
+
+fn number_to_words(number: u64) -> String {
+    const ONES: [&str; 20] = [
+        "zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten",
+        "eleven", "twelve", "thirteen", "fourteen", "fifteen", "sixteen", "seventeen",
+        "eighteen", "nineteen",
+    ];
+    const TENS: [&str; 10] = [
+        "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+    ];
+    const SCALES: [(u64, &str); 4] = [
+        (1_000_000_000_000, "trillion"),
+        (1_000_000_000, "billion"),
+        (1_000_000, "million"),
+        (1_000, "thousand"),
+    ];
+
+    if number < 20 {
+        return ONES[number as usize].to_string();
+    }
+    if number < 100 {
+        let tens = TENS[(number / 10) as usize];
+        return match number % 10 {
+            0 => tens.to_string(),
+            ones => format!("{}-{}", tens, ONES[ones as usize]),
+        };
+    }
+    if number < 1000 {
+        let hundreds = format!("{} hundred", ONES[(number / 100) as usize]);
+        return match number % 100 {
+            0 => hundreds,
+            rest => format!("{} {}", hundreds, number_to_words(rest)),
+        };
+    }
+
+    for (scale, name) in SCALES {
+        if number >= scale {
+            let head = format!("{} {}", number_to_words(number / scale), name);
+            return match number % scale {
+                0 => head,
+                rest => format!("{} {}", head, number_to_words(rest)),
+            };
+        }
+    }
+
+    unreachable!()
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn with_terminal_punctuation(text: &str) -> String {
+    let text = text.trim_end();
+    if text.ends_with(['.', '!', '?', ':', ';']) {
+        text.to_string()
+    } else {
+        format!("{}.", text)
+    }
+}
+
+// "Chapter 1: The Beginning", "I. Dawn" and "12 Home" lose their numbering.
+// A title that is nothing but a number ("1984", "Chapter 3") is kept as is,
+// and so is one whose first word only starts like one ("D-Day", "3-D").
+fn clean_chapter_title(title: &str) -> String {
+    static NUMBERING: OnceLock<Regex> = OnceLock::new();
+    let numbering = NUMBERING.get_or_init(|| {
+        Regex::new(concat!(
+            r"^(?:(?i:chapter|ch\.)\s+(?:\d+|[IVXLCDM]+)\b[\s.:\-–—]*",
+            r"|(?i:chapter)\s+[A-Za-z]+(?:-[A-Za-z]+)?\s*[.:\-–—]+\s*",
+            r"|\d+(?:\s*[.:)\-–—]\s+|\s+)",
+            r"|[IVXLCDM]+\s*[.:)\-–—]\s+)",
+        ))
+        .unwrap()
+    });
+
+    let title = title.trim();
+    let rest = numbering.replace(title, "");
+    if rest.trim().is_empty() {
+        title.to_string()
+    } else {
+        rest.trim().to_string()
+    }
+}
+
+// Machine-readable progress for the GUI; kept on stdout while human-facing
+// logging goes to stderr
+fn emit_progress(event: &str) {
+    println!("PROGRESS {}", event);
+}
+
+fn init_logging(verbosity: u8, quiet: bool) {
+    let level = if quiet {
+        LevelFilter::Error
+    } else {
+        match verbosity {
+            0 => LevelFilter::Info,
+            1 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        }
+    };
+
+    let mut builder = env_logger::Builder::new();
+    builder
+    .filter_level(level)
+    .format_timestamp(None)
+    .format_target(false);
+
+    // RUST_LOG still works when no explicit verbosity flag was given
+    if verbosity == 0 && !quiet {
+        if let Ok(filters) = std::env::var("RUST_LOG") {
+            builder.parse_filters(&filters);
+        }
+    }
+
+    builder.init();
+}
+
+// Font obfuscation (IDPF and Adobe) also uses encryption.xml but leaves the text readable
+const FONT_OBFUSCATION_ALGORITHMS: [&str; 2] = ["http://www.idpf.org/2008/embedding", "http://ns.adobe.com/pdf/enc#RC"];
+
+// Adobe ADEPT ships META-INF/rights.xml; any encryption.xml entry beyond font
+// obfuscation means the content documents themselves are encrypted
+fn is_drm_protected(doc: &mut EpubDoc<BufReader<File>>) -> bool {
+    if doc.get_resource_by_path("META-INF/rights.xml").is_some() {
+        return true;
+    }
+    let encryption = match doc.get_resource_by_path("META-INF/encryption.xml") {
+        Some(content) => String::from_utf8_lossy(&content.0).to_string(),
+        None => return false,
+    };
+
+    let algorithm_regex = Regex::new(r#"(?is)<(?:\w+:)?EncryptionMethod\b[^>]*\bAlgorithm\s*=\s*["']([^"']+)["']"#).unwrap();
+    let protected = algorithm_regex
+        .captures_iter(&encryption)
+        .any(|method| !FONT_OBFUSCATION_ALGORITHMS.contains(&method[1].trim()));
+    protected
+}
+
+fn read_package_document(doc: &mut EpubDoc<BufReader<File>>) -> Option<String> {
+    let root_file = doc.root_file.clone();
+    doc.get_resource_by_path(&root_file)
+        .map(|content| String::from_utf8_lossy(&content.0).to_string())
+}
+
+fn nonlinear_spine_hrefs(opf: &str) -> Vec<String> {
+    let item_regex = Regex::new(r"(?is)<item\b[^>]*>").unwrap();
+    let itemref_regex = Regex::new(r"(?is)<itemref\b[^>]*>").unwrap();
+    let id_regex = Regex::new(r#"\bid\s*=\s*["']([^"']+)["']"#).unwrap();
+    let idref_regex = Regex::new(r#"\bidref\s*=\s*["']([^"']+)["']"#).unwrap();
+    let href_regex = Regex::new(r#"\bhref\s*=\s*["']([^"']+)["']"#).unwrap();
+    let linear_no_regex = Regex::new(r#"\blinear\s*=\s*["']no["']"#).unwrap();
+
+    let manifest: HashMap<String, String> = item_regex
+        .find_iter(opf)
+        .filter_map(|item| {
+            let id = id_regex.captures(item.as_str())?[1].to_string();
+            let href = href_regex.captures(item.as_str())?[1].to_string();
+            Some((id, href))
+        })
+        .collect();
+
+    itemref_regex
+        .find_iter(opf)
+        .filter(|itemref| linear_no_regex.is_match(itemref.as_str()))
+        .filter_map(|itemref| idref_regex.captures(itemref.as_str()))
+        .filter_map(|captures| manifest.get(&captures[1]).cloned())
+        .collect()
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+// Sizes the job without synthesizing: characters (what paid engines bill on),
+// words, expected audio length and, where the engine has a cost model, a price
+fn print_estimate(processor: &EpubProcessor, manifest: &BookManifest) {
+    // Spoken chapter titles are sent to the engine too
+    let announced: usize = if processor.config.announce_titles {
+        manifest
+            .chapters
+            .iter()
+            .map(|chapter| {
+                Chapter {
+                    title: chapter.title.clone(),
+                    content: String::new(),
+                    order: chapter.order,
+                    source_order: chapter.order,
+                    word_count: 0,
+                    bookend: false,
+                }
+                .title_announcement(processor.config.spoken_chapter_number(chapter.order))
+                .chars()
+                .count()
+            })
+            .sum()
+    } else {
+        0
+    };
+    let characters = manifest.total_characters + announced;
+
+    println!("🧮 Estimate: {}", manifest.title);
+    println!("   Chapters:   {}", manifest.chapters.len());
+    println!("   Words:      {}", manifest.total_words);
+    println!("   Characters: {}", characters);
+    println!("   Audio:      ~{}", format_duration(manifest.estimated_duration()));
+
+    match processor.tts_engine.synthesizer.as_deref() {
+        Some(synthesizer) => match synthesizer.estimate_cost(characters) {
+            Some(cost) => println!("   Cost:       ~${:.2} with {}", cost, synthesizer.name()),
+            None => println!("   Cost:       unknown for {}", synthesizer.name()),
+        },
+        None => println!("   Cost:       no TTS engine found"),
+    }
+}
+
+fn print_dry_run(
+    processor: &EpubProcessor,
+    input_path: &Path,
+    manifest: &BookManifest,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("📋 Dry run: {}", input_path.display());
+    println!("📖 {}{}", manifest.title, manifest.author.as_ref().map(|a| format!(" by {}", a)).unwrap_or_default());
+
+    for chapter in &manifest.chapters {
+        println!(
+            "  {}  {}  ({} words, ~{})",
+            processor.config.chapter_number(chapter.order, manifest.chapters.len()),
+            chapter.title,
+            chapter.word_count,
+            format_duration(Duration::from_secs_f64(chapter.estimated_duration_secs))
+        );
+    }
+
+    println!(
+        "📊 {} chapters, {} words, ~{} of audio",
+        manifest.chapters.len(),
+        manifest.total_words,
+        format_duration(manifest.estimated_duration())
+    );
+
+    let narrated = processor.detect_media_overlays(input_path)?;
+    if narrated.is_empty() {
+        println!("🎧 No media overlays found");
+    } else {
+        println!("🎧 Media overlays (existing narration) found for {} documents:", narrated.len());
+        for href in narrated {
+            println!("  {}", href);
+        }
+    }
+
+    Ok(())
+}
+
+// --resource: one spine item run through extraction and cleaning on its own, to
+// triage how a single file is read. Running headers are found across the whole
+// book, so they are left in here
+fn print_resource(
+    processor: &EpubProcessor,
+    input_path: &Path,
+    href: &str,
+    show_cleaning: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut doc = open_epub(input_path)?;
+    let href = Path::new(href);
+    let content = find_spine_resource(&mut doc, href).ok_or_else(|| {
+        let spine: Vec<String> = doc.spine.iter().map(|item| PathBuf::from(&item.0).display().to_string()).collect();
+        format!("{} not found in the EPUB; its spine items are: {}", href.display(), spine.join(", "))
+    })?;
+    if show_cleaning {
+        println!("📄 {}: {} bytes of HTML", href.display(), content.len());
+    }
+
+    let aggressive = processor.config.preprocessing_aggressive;
+    for (index, section) in processor.read_sections(&mut doc, href).into_iter().enumerate() {
+        let cleaned = processor.text_processor.speech_text(&section.text, aggressive);
+        if !show_cleaning {
+            println!("{}", cleaned);
+            continue;
+        }
+        let heading = section.heading.map(|heading| format!(" \"{}\"", heading)).unwrap_or_default();
+        println!("\n── Section {}{} ──", index + 1, heading);
+        println!("── html2text: {} characters ──", section.text.chars().count());
+        println!("{}", section.text.trim_end());
+        println!("── clean_text: {} characters ──", cleaned.chars().count());
+        println!("{}", cleaned);
+    }
+    Ok(())
+}
+
+const BENCHMARK_CHUNK_SIZES: [usize; 5] = [250, 500, 1000, 2000, 4000];
+
+// Fixed corpus so runs are comparable across machines and settings
+const BENCHMARK_TEXT: &str = "It was the best of times, it was the worst of times, it was the age of \
+wisdom, it was the age of foolishness, it was the epoch of belief, it was the epoch of incredulity, \
+it was the season of Light, it was the season of Darkness, it was the spring of hope, it was the \
+winter of despair. We had everything before us, we had nothing before us. We were all going direct \
+to Heaven, we were all going direct the other way. In short, the period was so far like the present \
+period, that some of its noisiest authorities insisted on its being received, for good or for evil, \
+in the superlative degree of comparison only. ";
+
+// Times the real chunking + synthesis + encoding path at each chunk size. Small
+// chunks pay process start-up per chunk; large ones leave workers idle.
+fn run_benchmark(mut config: Config) -> Result<(), Box<dyn std::error::Error>> {
+    config.cache_enabled = false;
+
+    let text_processor = TextProcessor::new(&config);
+    let tts_engine = TTSEngine::new(config.clone())?;
+    let corpus = text_processor.speech_text(&BENCHMARK_TEXT.repeat(8), config.preprocessing_aggressive);
+    let work_dir = tempfile::tempdir()?;
+    let ext = config.output_format.extension();
+
+    info!(
+        "⏱️  Benchmarking {} characters with {} workers, {} output",
+        corpus.len(),
+        config.max_workers,
+        ext
+    );
+    println!(
+        "{:>10} {:>8} {:>10} {:>12} {:>10} {:>8}",
+        "chunk_size", "chunks", "wall_secs", "chars/sec", "audio_secs", "rtf"
+    );
+
+    for chunk_size in BENCHMARK_CHUNK_SIZES {
+        let chunks = text_processor.split_into_chunks(&corpus, config.min_chunk_size, chunk_size);
+        let start = Instant::now();
+
+        let audio_secs = chunks
+            .par_iter()
+            .enumerate()
+            .map(|(index, chunk)| -> Result<f64, String> {
+                let output_path = work_dir.path().join(format!("{}_{:04}.{}", chunk_size, index, ext));
+                tts_engine
+                    .text_to_speech(chunk, &output_path)
+                    .map(|speech| speech.duration_secs)
+                    .map_err(|e| e.to_string())
+            })
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .sum::<f64>();
+
+        let wall_secs = start.elapsed().as_secs_f64().max(f64::EPSILON);
+        println!(
+            "{:>10} {:>8} {:>10.2} {:>12.0} {:>10.1} {:>8.1}",
+            chunk_size,
+            chunks.len(),
+            wall_secs,
+            corpus.len() as f64 / wall_secs,
+            audio_secs,
+            audio_secs / wall_secs
+        );
+    }
+
+    Ok(())
+}
+
+const SELFTEST_PHRASE: &str = "The quick brown fox jumps over the lazy dog. This is a self test.";
+const SELFTEST_FORMATS: [AudioFormat; 4] = [AudioFormat::Vorbis, AudioFormat::Flac, AudioFormat::Mp3, AudioFormat::Wav];
+// Decoded peak below which a file counts as silent
+const SELFTEST_MIN_PEAK: f32 = 0.01;
+
+// Runs a fixed phrase through synthesis and every encoder, then decodes each
+// result to check it is audible and about as long as the phrase should take.
+// Finally checks that a cached chunk is re-encoded in another format without
+// calling the synthesizer again.
+fn run_selftest(mut config: Config) -> Result<(), Box<dyn std::error::Error>> {
+    config.cache_enabled = false;
+    let work_dir = tempfile::tempdir()?;
+    let words = SELFTEST_PHRASE.split_whitespace().count() as f64;
+    let expected_secs = words * 60.0 / config.effective_wpm() as f64;
+
+    info!("🩺 Self-test with the {} engine, expecting ~{:.1}s of audio", config.engine, expected_secs);
+    let mut failed = Vec::new();
+    let mut passed = Vec::new();
+    for format in SELFTEST_FORMATS {
+        let output_path = work_dir.path().join(format!("selftest.{}", format.extension()));
+        let engine_config = Config {
+            output_format: format.clone(),
+            ..config.clone()
+        };
+        let result = TTSEngine::new(engine_config)
+            .and_then(|engine| engine.text_to_speech(SELFTEST_PHRASE, &output_path))
+            .and_then(|_| decode_audio_stats(&output_path))
+            .and_then(|(secs, peak)| {
+                if peak < SELFTEST_MIN_PEAK {
+                    Err(format!("output is silent (peak {:.4})", peak).into())
+                } else if secs < expected_secs * 0.3 || secs > expected_secs * 3.0 {
+                    Err(format!("decoded {:.1}s, expected ~{:.1}s", secs, expected_secs).into())
+                } else {
+                    Ok(secs)
+                }
+            });
+
+        match result {
+            Ok(secs) => {
+                println!("PASS  {:<5} {:.1}s", format.extension(), secs);
+                passed.push(format);
+            }
+            Err(e) => {
+                println!("FAIL  {:<5} {}", format.extension(), e);
+                failed.push(format.extension());
+            }
+        }
+    }
+
+    let cache_config = Config {
+        cache_enabled: true,
+        cache_dir: Some(work_dir.path().join("cache")),
+        output_format: AudioFormat::Wav,
+        ..config.clone()
+    };
+    let reencode: Vec<AudioFormat> =
+        passed.into_iter().filter(|format| !matches!(format, AudioFormat::Wav)).collect();
+    if reencode.is_empty() {
+        println!("SKIP  cache no format besides wav to re-encode into");
+    } else {
+        match check_cache_reuse(cache_config, &reencode, work_dir.path()) {
+            Ok(()) => println!(
+                "PASS  cache re-encoded as {} without synthesis",
+                reencode.iter().map(AudioFormat::extension).collect::<Vec<_>>().join(", ")
+            ),
+            Err(e) => {
+                println!("FAIL  cache {}", e);
+                failed.push("cache");
+            }
+        }
+    }
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("Self-test failed for: {}", failed.join(", ")).into())
+    }
+}
+
+// Synthesizes the self-test phrase into an empty cache as WAV, then encodes
+// it again in each of `formats`; those runs must be served from the cache
+fn check_cache_reuse(config: Config, formats: &[AudioFormat], work_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    TTSEngine::new(config.clone())?.text_to_speech(SELFTEST_PHRASE, &work_dir.join("cache-seed.wav"))?;
+    for format in formats {
+        let engine = TTSEngine::new(Config {
+            output_format: format.clone(),
+            ..config.clone()
+        })?;
+        let speech = engine.text_to_speech(SELFTEST_PHRASE, &work_dir.join(format!("cache-reuse.{}", format.extension())))?;
+        if !speech.cache_hit {
+            return Err(format!(
+                "switching to {} synthesized the phrase again instead of reusing the cached audio",
+                format.extension()
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
+
+// Decodes a whole audio file, returning its length in seconds and peak level (0-1)
+fn decode_audio_stats(path: &Path) -> Result<(f64, f32), Box<dyn std::error::Error>> {
+    use symphonia::core::audio::SampleBuffer;
+    use symphonia::core::codecs::DecoderOptions;
+    use symphonia::core::errors::Error as SymphoniaError;
+    use symphonia::core::formats::FormatOptions;
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::meta::MetadataOptions;
+    use symphonia::core::probe::Hint;
+
+    let source = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
+        hint.with_extension(ext);
+    }
+    let probed = symphonia::default::get_probe().format(
+        &hint,
+        source,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    let mut format = probed.format;
+    let track = format.default_track().ok_or("No audio track")?;
+    let track_id = track.id;
+    let sample_rate = track.codec_params.sample_rate.ok_or("Unknown sample rate")?;
+    let mut decoder = symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut frames = 0u64;
+    let mut peak = 0.0f32;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = decoder.decode(&packet)?;
+        let spec = *decoded.spec();
+        let mut samples = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        samples.copy_interleaved_ref(decoded);
+        frames += (samples.samples().len() / spec.channels.count().max(1)) as u64;
+        peak = samples.samples().iter().fold(peak, |peak, sample| peak.max(sample.abs()));
+    }
+
+    Ok((frames as f64 / sample_rate as f64, peak))
+}
+
+// Failures a driving script can act on, each with its own exit code; any
+// other error exits with 1
+#[derive(Debug)]
+enum ConversionError {
+    NoTtsEngine(String),
+    EncoderMissing(String),
+    InvalidEpub(String),
+    PartialFailure(String),
+}
+
+const EXIT_CODES_HELP: &str = "Exit codes:
+  0  Success
+  1  Any other error
+  2  No TTS engine installed
+  3  Audio encoder (oggenc, flac, lame or ffmpeg) missing
+  4  Input is not a readable EPUB (missing, corrupt or DRM-protected)
+  5  Some books of a batch failed";
+
+impl ConversionError {
+    fn no_tts_engine() -> Self {
+        ConversionError::NoTtsEngine("No TTS engine found. Please install espeak-ng, espeak, or festival".to_string())
+    }
+
+    fn exit_code(&self) -> i32 {
+        match self {
+            ConversionError::NoTtsEngine(_) => 2,
+            ConversionError::EncoderMissing(_) => 3,
+            ConversionError::InvalidEpub(_) => 4,
+            ConversionError::PartialFailure(_) => 5,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            ConversionError::NoTtsEngine(_) => "no_tts_engine",
+            ConversionError::EncoderMissing(_) => "encoder_missing",
+            ConversionError::InvalidEpub(_) => "invalid_epub",
+            ConversionError::PartialFailure(_) => "partial_failure",
+        }
+    }
+}
+
+impl std::fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ConversionError::NoTtsEngine(message)
+            | ConversionError::EncoderMissing(message)
+            | ConversionError::InvalidEpub(message)
+            | ConversionError::PartialFailure(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+// Exit code for an error that ends the run
+fn exit_code(error: &(dyn std::error::Error + 'static)) -> i32 {
+    error.downcast_ref::<ConversionError>().map_or(1, ConversionError::exit_code)
+}
+
+// The error that ended the run, on stderr; --json-errors adds a single JSON
+// line after it for scripts
+fn report_error(error: &(dyn std::error::Error + 'static), json: bool) {
+    eprintln!("Error: {}", error);
+    if json {
+        let kind = error.downcast_ref::<ConversionError>().map_or("error", ConversionError::kind);
+        eprintln!(
+            "{}",
+            serde_json::json!({
+                "error": kind,
+                "exit_code": exit_code(error),
+                "message": error.to_string(),
+            })
+        );
+    }
+}
+
+// Keeps a ConversionError intact across threads so its exit code survives;
+// anything else travels as its message
+fn sendable_error(error: Box<dyn std::error::Error>) -> Box<dyn std::error::Error + Send + Sync> {
+    match error.downcast::<ConversionError>() {
+        Ok(error) => error,
+        Err(error) => error.to_string().into(),
+    }
+}
+
+fn open_epub(path: &Path) -> Result<EpubDoc<BufReader<File>>, Box<dyn std::error::Error>> {
+    EpubDoc::new(path)
+        .map_err(|e| ConversionError::InvalidEpub(format!("{} could not be read as an EPUB: {}", path.display(), e)).into())
+}
+
+// Catches the common "wrong file" mistakes before EpubDoc fails deep inside extraction
+fn validate_epub(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    if !path.exists() {
+        return Err(ConversionError::InvalidEpub(format!("Input file not found: {}", path.display())).into());
+    }
+    if !path.is_file() {
+        return Err(ConversionError::InvalidEpub(format!("Input is not a file: {}", path.display())).into());
+    }
+
+    let mut header = [0u8; 256];
+    let read = File::open(path)?.read(&mut header)?;
+    let header = &header[..read];
+
+    if !header.starts_with(b"PK\x03\x04") {
+        return Err(ConversionError::InvalidEpub(format!(
+            "{} is not a valid EPUB (detected: {})",
+            path.display(),
+            describe_file_type(header)
+        ))
+        .into());
+    }
+
+    // An EPUB container starts with an uncompressed "mimetype" entry
+    let field = |offset: usize| {
+        header
+            .get(offset..offset + 2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
+    };
+    let is_epub = match (field(26), field(28)) {
+        (Some(name_len), Some(extra_len)) => {
+            let content_start = 30 + name_len + extra_len;
+            header.get(30..30 + name_len) == Some(&b"mimetype"[..])
+                && header
+                    .get(content_start..)
+                    .map(|content| content.starts_with(b"application/epub+zip"))
+                    .unwrap_or(false)
+        }
+        _ => false,
+    };
+
+    if !is_epub {
+        return Err(ConversionError::InvalidEpub(format!(
+            "{} is not a valid EPUB (detected: ZIP archive without an EPUB mimetype entry)",
+            path.display()
+        ))
+        .into());
+    }
+
+    Ok(())
+}
+
+fn describe_file_type(header: &[u8]) -> &'static str {
+    if header.is_empty() {
+        "empty file"
+    } else if header.starts_with(b"%PDF") {
+        "PDF document"
+    } else if header.get(60..68) == Some(&b"BOOKMOBI"[..]) {
+        "MOBI/AZW e-book"
+    } else if header.starts_with(b"Rar!") {
+        "RAR archive"
+    } else if header.starts_with(b"\x1f\x8b") {
+        "gzip archive"
+    } else if header.starts_with(b"\xef\xbb\xbf<") || header.starts_with(b"<") {
+        "HTML/XML text"
+    } else if std::str::from_utf8(header).is_ok() {
+        "plain text"
+    } else {
+        "unknown binary data"
+    }
+}
+
+// Applies the output policy so two conversion runs never silently mix their files
+fn prepare_output_dir(output_dir: &Path, policy: OutputPolicy) -> Result<(), Box<dyn std::error::Error>> {
+    if !output_dir.exists() {
+        fs::create_dir_all(output_dir)?;
+        return Ok(());
+    }
+    if !output_dir.is_dir() {
+        return Err(format!("Output path {} is not a directory", output_dir.display()).into());
+    }
+
+    let is_empty = fs::read_dir(output_dir)?.next().is_none();
+    match policy {
+        _ if is_empty => {}
+        OutputPolicy::Refuse => {
+            return Err(format!(
+                "Output directory {} is not empty; use --overwrite to replace its contents or --resume to continue a previous run",
+                output_dir.display()
+            )
+            .into());
+        }
+        OutputPolicy::Overwrite => {
+            info!("🗑️  Clearing existing output in {}", output_dir.display());
+            for entry in fs::read_dir(output_dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    fs::remove_dir_all(&path)?;
+                } else {
+                    fs::remove_file(&path)?;
+                }
+            }
+        }
+        OutputPolicy::Resume => info!("⏯️  Resuming into {}", output_dir.display()),
+    }
+
+    Ok(())
+}
+
+// Identifies a book in the cache index
+fn book_id(input_path: &Path) -> String {
+    input_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| input_path.display().to_string())
+}
+
+fn validate_filename_template(template: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let placeholder_regex = Regex::new(r"\{([^{}]*)\}").unwrap();
+
+    let unknown: Vec<&str> = placeholder_regex
+        .captures_iter(template)
+        .map(|caps| caps.get(1).unwrap().as_str())
+        .filter(|name| !FILENAME_PLACEHOLDERS.contains(name))
+        .collect();
+
+    if !unknown.is_empty() {
+        return Err(format!(
+            "Unknown placeholder(s) in filename template: {{{}}} (supported: {})",
+            unknown.join("}, {"),
+            FILENAME_PLACEHOLDERS.map(|name| format!("{{{}}}", name)).join(", ")
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+fn render_filename_template(template: &str, values: &[(&str, String)]) -> String {
+    let placeholder_regex = Regex::new(r"\{([^{}]*)\}").unwrap();
+
+    placeholder_regex
+        .replace_all(template, |caps: &regex::Captures| {
+            values
+                .iter()
+                .find(|(name, _)| *name == &caps[1])
+                .map(|(_, value)| sanitize_filename(value))
+                .unwrap_or_default()
+        })
+        .to_string()
+}
+
+fn sanitize_filename(name: &str) -> String {
+    let invalid_chars = Regex::new(r#"[<>:"/\\|?*]"#).unwrap();
+    invalid_chars.replace_all(name, "_").to_string()
+}
+
+// RSS 2.0 feed with iTunes tags so the output folder can be hosted and
+// subscribed to from a podcast app
+struct PodcastFeed {
+    title: String,
+    author: Option<String>,
+    base_url: Option<String>,
+    image: Option<String>,
+    items: Vec<PodcastItem>,
+}
+
+struct PodcastItem {
+    title: String,
+    href: String,
+    length: u64,
+    mime_type: &'static str,
+    duration_secs: f64,
+}
+
+impl PodcastFeed {
+    fn new(title: &str) -> Self {
+        Self {
+            title: title.to_string(),
+            author: None,
+            base_url: None,
+            image: None,
+            items: Vec::new(),
+        }
+    }
+
+    fn author(mut self, author: Option<&str>) -> Self {
+        self.author = author.map(str::to_string);
+        self
+    }
+
+    fn base_url(mut self, base_url: Option<&str>) -> Self {
+        self.base_url = base_url.map(|url| url.trim_end_matches('/').to_string());
+        self
+    }
+
+    fn image(mut self, href: Option<&str>) -> Self {
+        self.image = href.map(str::to_string);
+        self
+    }
+
+    fn item(mut self, item: PodcastItem) -> Self {
+        self.items.push(item);
+        self
+    }
+
+    fn url(&self, href: &str) -> String {
+        let encoded = href
+            .split('/')
+            .map(encode_url_segment)
+            .collect::<Vec<_>>()
+            .join("/");
+        match &self.base_url {
+            Some(base) => format!("{}/{}", base, encoded),
+            None => encoded,
+        }
+    }
+
+    fn write(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let mut out = BufWriter::new(File::create(path)?);
+
+        writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            out,
+            r#"<rss version="2.0" xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd">"#
+        )?;
+        writeln!(out, "  <channel>")?;
+        writeln!(out, "    <title>{}</title>", xml_escape(&self.title))?;
+        writeln!(out, "    <description>{}</description>", xml_escape(&self.title))?;
+        if let Some(base) = &self.base_url {
+            writeln!(out, "    <link>{}</link>", xml_escape(base))?;
+        }
+        if let Some(author) = &self.author {
+            writeln!(out, "    <itunes:author>{}</itunes:author>", xml_escape(author))?;
+        }
+        if let Some(image) = &self.image {
+            writeln!(out, r#"    <itunes:image href="{}"/>"#, xml_escape(&self.url(image)))?;
+        }
+        writeln!(out, "    <itunes:type>serial</itunes:type>")?;
+
+        // Podcast apps sort by date, so space the items a minute apart in reading order
+        let first_date = unix_timestamp().saturating_sub(60 * self.items.len() as u64);
+        for (index, item) in self.items.iter().enumerate() {
+            let url = self.url(&item.href);
+            writeln!(out, "    <item>")?;
+            writeln!(out, "      <title>{}</title>", xml_escape(&item.title))?;
+            writeln!(
+                out,
+                r#"      <enclosure url="{}" length="{}" type="{}"/>"#,
+                xml_escape(&url),
+                item.length,
+                item.mime_type
+            )?;
+            writeln!(out, r#"      <guid isPermaLink="false">{}</guid>"#, xml_escape(&item.href))?;
+            writeln!(out, "      <pubDate>{}</pubDate>", rfc2822_date(first_date + 60 * index as u64))?;
+            writeln!(
+                out,
+                "      <itunes:duration>{}</itunes:duration>",
+                format_duration(Duration::from_secs_f64(item.duration_secs))
+            )?;
+            writeln!(out, "      <itunes:episode>{}</itunes:episode>", index + 1)?;
+            writeln!(out, "    </item>")?;
+        }
+
+        writeln!(out, "  </channel>")?;
+        writeln!(out, "</rss>")?;
+        out.flush()?;
+
+        Ok(())
+    }
+}
+
+fn write_podcast_feed(
+    output_dir: &Path,
+    metadata: &BookMetadata,
+    outputs: &[ChapterOutput],
+    format: &AudioFormat,
+    base_url: Option<&str>,
+    cover: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mime_type = match format {
+        AudioFormat::Vorbis => "audio/ogg",
+        AudioFormat::Flac => "audio/flac",
+        AudioFormat::Mp3 => "audio/mpeg",
+        AudioFormat::Wav => "audio/wav",
+    };
+    let relative = |path: &Path| {
+        path.strip_prefix(output_dir)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .replace('\\', "/")
+    };
+
+    let cover_href = cover.map(relative);
+    let mut feed = PodcastFeed::new(&metadata.title)
+        .author(metadata.author.as_deref())
+        .base_url(base_url)
+        .image(cover_href.as_deref());
+
+    for output in outputs {
+        for (part, file) in output.files.iter().enumerate() {
+            let title = if output.files.len() > 1 {
+                format!("{} (part {})", output.title, part + 1)
+            } else {
+                output.title.clone()
+            };
+            feed = feed.item(PodcastItem {
+                title,
+                href: relative(&file.path),
+                length: fs::metadata(&file.path).map(|meta| meta.len()).unwrap_or(0),
+                mime_type,
+                duration_secs: file.duration_secs,
+            });
+        }
+    }
+
+    feed.write(&output_dir.join("feed.xml"))
+}
+
+// Synthesizes one chapter, numbered as in --dry-run, and writes it encoded to
+// stdout. The processor keeps chunks as WAV so the audio is encoded only once.
+fn stream_chapter(
+    processor: &EpubProcessor,
+    chapter: &Chapter,
+    chapter_count: usize,
+    format: &AudioFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let number = chapter.order + processor.config.chapter_number_base;
+    info!("🎤 Converting chapter {} \"{}\"...", number, chapter.title);
+    let work_dir = tempfile::tempdir()?;
+    let result = processor.process_single_chapter(chapter, work_dir.path(), (1, 1), chapter_count, &|_| {});
+    processor.tts_engine.save_cache_index()?;
+    let output = result.map_err(|e| e.to_string())?;
+    if !output.complete {
+        return Err("Interrupted".into());
+    }
+
+    let files: Vec<PathBuf> = output.files.into_iter().map(|file| file.path).collect();
+    let joined = work_dir.path().join("chapter.wav");
+    concat_wavs(&files, &joined, processor.config.wav_spec())?;
+    processor.tts_engine.convert_audio_as(format, &joined, Path::new(STDOUT_PATH), None)
+}
+
+// Replaces a chapter's chunk files with one "{chapter}_{title}.{ext}" in the
+// output folder through the merge path, which encodes the chunks' joined WAVs
+// once: no generation loss, and no encoder padding at the seams, where separate
+// files get a gap from every player that reopens the output between them
+fn join_chapter_chunks(
+    processor: &EpubProcessor,
+    output_dir: &Path,
+    output: &mut ChapterOutput,
+    (track, total_tracks): (usize, usize),
+    chapter_count: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if output.files.len() < 2 {
+        return Ok(());
+    }
+    let inputs: Vec<PathBuf> = output.files.iter().map(|file| file.path.clone()).collect();
+    let joined = processor.joined_chapter_path(output_dir, output.order, &output.title, chapter_count);
+    // Written aside first, so --resume only ever finds a whole joined file
+    let partial = joined.with_extension(format!("partial.{}", processor.get_file_extension()));
+
+    let metadata = match processor.config.tag_preset {
+        TagPreset::None => Vec::new(),
+        TagPreset::Audiobook => AudioTags {
+            title: output.title.clone(),
+            album: processor.book.title.clone(),
+            artist: processor.book.author.clone(),
+            series: processor.book.series.clone(),
+            series_part: processor.book.series_part(),
+            album_sort: processor.book.sort_title(),
+            track,
+            total_tracks,
+        }
+        .ffmpeg_metadata(),
+    };
+    let engine = &processor.tts_engine;
+    engine.merge_audio(&inputs, &partial, &metadata)?;
+    fs::rename(&partial, &joined)?;
+    // The joined WAV follows the file, for merging the book or splitting it
+    fs::rename(engine.merge_wav_path(&partial), engine.merge_wav_path(&joined))?;
+    for input in &inputs {
+        fs::remove_file(input)?;
+        let _ = fs::remove_file(engine.merge_wav_path(input));
+    }
+    // The chapter folder goes once empty; transcripts keep it
+    if let Some(chapter_dir) = inputs[0].parent() {
+        let _ = fs::remove_dir(chapter_dir);
+    }
+
+    output.files = vec![ChunkOutput {
+        duration_secs: output.duration_secs(),
+        path: joined,
+    }];
+    Ok(())
+}
+
+// Joins the chapter files into one "{book title}.{ext}" and, for Vorbis and FLAC,
+// marks each chapter start with CHAPTERnnn/CHAPTERnnnNAME comments
+fn merge_book(
+    processor: &EpubProcessor,
+    output_dir: &Path,
+    outputs: &[ChapterOutput],
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let inputs: Vec<PathBuf> = outputs
+        .iter()
+        .flat_map(|output| output.files.iter().map(|file| file.path.clone()))
+        .collect();
+    if inputs.is_empty() {
+        return Err("No audio files to merge".into());
+    }
+
+    let book = &processor.book;
+    let mut metadata = vec![format!("title={}", book.title), format!("album={}", book.title)];
+    if let Some(author) = &book.author {
+        metadata.push(format!("artist={}", author));
+    }
+    if let Some(series) = &book.series {
+        metadata.push(format!("series={}", series));
+    }
+    if let Some(part) = book.series_part() {
+        metadata.push(format!("series-part={}", part));
+    }
+    if let Some(album_sort) = book.sort_title() {
+        metadata.push(format!("album_sort={}", album_sort));
+    }
+    if matches!(processor.config.output_format, AudioFormat::Vorbis | AudioFormat::Flac) {
+        metadata.extend(vorbis_chapter_comments(&chapter_marks(outputs)));
+    }
+
+    let merged_path = output_dir.join(format!(
+        "{}.{}",
+        sanitize_filename(&book.title),
+        processor.get_file_extension()
+    ));
+    processor.tts_engine.merge_audio(&inputs, &merged_path, &metadata)?;
+
+    Ok(merged_path)
+}
+
+// Gives the merged FLAC an embedded CUESHEET with one track per chapter, and a
+// seek table with a point at each chapter start and every 10 seconds. metaflac
+// reads index points as sample offsets, except on CD-DA audio (44.1 kHz 16-bit
+// stereo), where they must be MM:SS:FF with 75 frames a second
+fn embed_flac_cuesheet(
+    processor: &EpubProcessor,
+    merged_path: &Path,
+    outputs: &[ChapterOutput],
+) -> Result<(), Box<dyn std::error::Error>> {
+    if !ProcessCommand::new("which")
+        .arg("metaflac")
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+    {
+        warn!("⚠️  metaflac not found; the merged FLAC keeps its chapter comments but gets no cuesheet");
+        return Ok(());
+    }
+
+    let spec = processor.config.wav_spec();
+    let cdda = spec.sample_rate == 44_100 && spec.channels == 2 && spec.bits_per_sample == 16;
+    let marks = chapter_marks(outputs);
+    let max_tracks = if cdda { 99 } else { 254 };
+    if marks.len() > max_tracks {
+        warn!("⚠️  A FLAC cuesheet holds at most {} tracks; {} chapters get no cuesheet", max_tracks, marks.len());
+        return Ok(());
+    }
+
+    let file_name = merged_path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    let mut cue = format!("FILE \"{}\" WAVE\n", file_name.replace('"', "'"));
+    for (index, (title, start_secs)) in marks.iter().enumerate() {
+        let offset = if cdda {
+            let frames = (start_secs * 75.0).round() as u64;
+            format!("{:02}:{:02}:{:02}", frames / 75 / 60, frames / 75 % 60, frames % 75)
+        } else {
+            ((start_secs * spec.sample_rate as f64).round() as u64).to_string()
+        };
+        cue.push_str(&format!("  TRACK {:02} AUDIO\n", index + 1));
+        cue.push_str(&format!("    TITLE \"{}\"\n", title.replace('"', "'")));
+        cue.push_str(&format!("    INDEX 01 {}\n", offset));
+    }
+
+    let mut cue_file = tempfile::Builder::new().suffix(".cue").tempfile()?;
+    cue_file.write_all(cue.as_bytes())?;
+    cue_file.flush()?;
+
+    let output = ProcessCommand::new("metaflac")
+        .arg(format!("--import-cuesheet-from={}", cue_file.path().display()))
+        .arg("--add-seekpoint=10s")
+        .arg(merged_path)
+        .output()?;
+    if !output.status.success() {
+        return Err(format!(
+            "metaflac could not embed the cuesheet: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    info!("   Embedded a cuesheet with {} chapter tracks", marks.len());
+    Ok(())
+}
+
+// Regroups all chunk files, in book order, into "part_001.{ext}"... of about
+// `limit_secs` each. Parts only break between chunks, so never mid-sentence,
+// and ignore chapter boundaries.
+fn split_into_parts(
+    processor: &EpubProcessor,
+    output_dir: &Path,
+    outputs: &[ChapterOutput],
+    limit_secs: u64,
+) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let mut parts: Vec<Vec<PathBuf>> = Vec::new();
+    let mut current = Vec::new();
+    let mut current_secs = 0.0;
+    for file in outputs.iter().flat_map(|output| output.files.iter()) {
+        let secs = match file.duration_secs {
+            // Files kept by --resume were not synthesized in this run
+            secs if secs > 0.0 => secs,
+            _ => probe_duration_secs(&file.path).unwrap_or(0.0),
+        };
+        if !current.is_empty() && current_secs + secs > limit_secs as f64 {
+            parts.push(std::mem::take(&mut current));
+            current_secs = 0.0;
+        }
+        current.push(file.path.clone());
+        current_secs += secs;
+    }
+    if !current.is_empty() {
+        parts.push(current);
+    }
+
+    let book = &processor.book;
+    let width = parts.len().to_string().len().max(3);
+    let mut written = Vec::new();
+    for (index, inputs) in parts.iter().enumerate() {
+        let number = index + 1;
+        let mut metadata = vec![
+            format!("title={} - Part {}", book.title, number),
+            format!("album={}", book.title),
+            format!("track={}/{}", number, parts.len()),
+        ];
+        if let Some(author) = &book.author {
+            metadata.push(format!("artist={}", author));
+        }
+
+        let part_path = output_dir.join(format!(
+            "part_{:0width$}.{}",
+            number,
+            processor.get_file_extension(),
+            width = width
+        ));
+        processor.tts_engine.merge_audio(inputs, &part_path, &metadata)?;
+        written.push(part_path);
+    }
+
+    Ok(written)
+}
+
+// "3", "2-5" or a comma-separated mix such as "0,2-5,9"
+fn parse_chapter_list(value: &str) -> Result<Vec<usize>, String> {
+    let invalid = || format!("Invalid chapter list \"{}\"; use e.g. 0, 2-5 or 0,2-5,9", value);
+    let mut chapters = Vec::new();
+    for item in value.split(',').map(str::trim) {
+        let (first, last) = item.split_once('-').unwrap_or((item, item));
+        let first: usize = first.trim().parse().map_err(|_| invalid())?;
+        let last: usize = last.trim().parse().map_err(|_| invalid())?;
+        if first > last {
+            return Err(invalid());
+        }
+        chapters.extend(first..=last);
+    }
+    Ok(chapters)
+}
+
+// "30m", "1h", "1h30m", "90s"; a bare number is minutes
+fn parse_split_duration(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    if let Ok(minutes) = value.parse::<u64>() {
+        return Ok(minutes * 60);
+    }
+
+    let mut total = 0;
+    let mut digits = String::new();
+    for c in value.chars() {
+        match c {
+            '0'..='9' => digits.push(c),
+            'h' | 'm' | 's' if !digits.is_empty() => {
+                let amount: u64 = digits.parse().map_err(|_| format!("Invalid duration \"{}\"", value))?;
+                total += amount * match c {
+                    'h' => 3600,
+                    'm' => 60,
+                    _ => 1,
+                };
+                digits.clear();
+            }
+            _ => return Err(format!("Invalid duration \"{}\"; use e.g. 30m, 1h or 1h30m", value)),
+        }
+    }
+    if !digits.is_empty() || total == 0 {
+        return Err(format!("Invalid duration \"{}\"; use e.g. 30m, 1h or 1h30m", value));
+    }
+    Ok(total)
+}
+
+// ReplayGain 2.0 reference level
+const REPLAYGAIN_REFERENCE_LUFS: f64 = -18.0;
+
+// Tags every file with its track gain and the set's album gain. rsgain or
+// loudgain are used when installed; otherwise ffmpeg measures EBU R128 loudness
+// and remuxes the tags in without re-encoding. (The R128_* gain tags are Opus
+// only, which we do not produce.)
+fn apply_replaygain(format: &AudioFormat, files: &[PathBuf]) -> Result<(), Box<dyn std::error::Error>> {
+    if files.is_empty() {
+        return Ok(());
+    }
+    if matches!(format, AudioFormat::Wav) {
+        warn!("⚠️  WAV files cannot carry ReplayGain tags; skipping");
+        return Ok(());
+    }
+
+    let scanner = ["rsgain", "loudgain"].into_iter().find(|program| {
+        ProcessCommand::new("which")
+            .arg(program)
+            .output()
+            .map(|output| output.status.success())
+            .unwrap_or(false)
+    });
+    if let Some(program) = scanner {
+        let mut cmd = ProcessCommand::new(program);
+        if program == "rsgain" {
+            cmd.arg("custom");
+        }
+        // -a: album gain over all files, -s i: write ReplayGain 2.0 tags
+        let output = cmd.arg("-a").arg("-s").arg("i").arg("--").args(files).output()?;
+        if !output.status.success() {
+            return Err(format!("{} failed: {}", program, String::from_utf8_lossy(&output.stderr).trim()).into());
+        }
+        return Ok(());
+    }
+
+    let mut measured = Vec::new();
+    for file in files {
+        let (loudness, peak) = measure_loudness(file)?;
+        measured.push((file, loudness, peak, probe_duration_secs(file).unwrap_or(1.0)));
+    }
+
+    // Album loudness is the duration-weighted mean of the tracks' energy
+    let total_secs: f64 = measured.iter().map(|(_, _, _, secs)| secs).sum();
+    let energy: f64 = measured
+        .iter()
+        .map(|(_, loudness, _, secs)| secs * 10f64.powf(loudness / 10.0))
+        .sum();
+    let album_loudness = 10.0 * (energy / total_secs.max(f64::EPSILON)).log10();
+    let album_peak = measured.iter().map(|(_, _, peak, _)| *peak).fold(0.0, f64::max);
+
+    for (file, loudness, peak, _) in &measured {
+        write_audio_tags(
+            file,
+            format,
+            &[
+                format!("REPLAYGAIN_TRACK_GAIN={:.2} dB", REPLAYGAIN_REFERENCE_LUFS - loudness),
+                format!("REPLAYGAIN_TRACK_PEAK={:.6}", peak),
+                format!("REPLAYGAIN_ALBUM_GAIN={:.2} dB", REPLAYGAIN_REFERENCE_LUFS - album_loudness),
+                format!("REPLAYGAIN_ALBUM_PEAK={:.6}", album_peak),
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+// Integrated loudness (LUFS) and linear true peak, from ffmpeg's ebur128 summary
+fn measure_loudness(path: &Path) -> Result<(f64, f64), Box<dyn std::error::Error>> {
+    let output = ProcessCommand::new("ffmpeg")
+        .arg("-hide_banner")
+        .arg("-nostats")
+        .arg("-i")
+        .arg(path)
+        .arg("-filter_complex")
+        .arg("ebur128=peak=true")
+        .arg("-f")
+        .arg("null")
+        .arg("-")
+        .output()
+        .map_err(|_| "ReplayGain needs rsgain, loudgain or ffmpeg")?;
+    if !output.status.success() {
+        return Err(format!("ffmpeg could not measure the loudness of {}", path.display()).into());
+    }
+
+    // Per-frame lines also carry "I:"; the summary comes last. Silence reads -inf
+    // or -70 LUFS and is clamped to the gate.
+    let log = String::from_utf8_lossy(&output.stderr);
+    let last_value = |pattern: &str| -> Option<f64> {
+        let regex = Regex::new(pattern).unwrap();
+        let value = regex.captures_iter(&log).last()?[1].to_string();
+        Some(value.parse::<f64>().unwrap_or(f64::NEG_INFINITY).max(-70.0))
+    };
+    let loudness = last_value(r"I:\s+(-?inf|-?[\d.]+) LUFS")
+        .ok_or_else(|| format!("No loudness reported for {}", path.display()))?;
+    let peak_db = last_value(r"Peak:\s+(-?inf|-?[\d.]+) dBFS").unwrap_or(0.0);
+    Ok((loudness, 10f64.powf(peak_db / 20.0)))
+}
+
+// Adds tags to an encoded file by remuxing it, leaving the audio as is
+fn write_audio_tags(path: &Path, format: &AudioFormat, tags: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let temp_path = tempfile::Builder::new()
+        .suffix(&format!(".{}", format.extension()))
+        .tempfile_in(dir)?
+        .into_temp_path();
+
+    // Vorbis comments live on the stream; FLAC and ID3 tags on the container
+    let scope = match format {
+        AudioFormat::Vorbis => "-metadata:s:a:0",
+        _ => "-metadata",
+    };
+    let mut cmd = ProcessCommand::new("ffmpeg");
+    cmd.arg("-v").arg("error").arg("-i").arg(path).arg("-map").arg("0").arg("-c").arg("copy");
+    for tag in tags {
+        cmd.arg(scope).arg(tag);
+    }
+    let output = cmd.arg("-y").arg(&temp_path).output()?;
+    if !output.status.success() {
+        return Err(format!("ffmpeg failed to tag {}: {}", path.display(), String::from_utf8_lossy(&output.stderr).trim()).into());
+    }
+    temp_path.persist(path)?;
+    Ok(())
+}
+
+// Start offset of each chapter in the merged file
+fn chapter_marks(outputs: &[ChapterOutput]) -> Vec<(String, f64)> {
+    let mut start_secs = 0.0;
+    outputs
+        .iter()
+        .map(|output| {
+            let mark = (output.title.clone(), start_secs);
+            start_secs += output
+                .files
+                .iter()
+                .map(|file| match file.duration_secs {
+                    // Files kept by --resume were not synthesized in this run
+                    secs if secs > 0.0 => secs,
+                    _ => probe_duration_secs(&file.path).unwrap_or(0.0),
+                })
+                .sum::<f64>();
+            mark
+        })
+        .collect()
+}
+
+// HH:MM:SS.mmm, as chapter comments and .chapters.txt use
+fn chapter_timestamp(secs: f64) -> String {
+    let millis = (secs * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+fn vorbis_chapter_comments(marks: &[(String, f64)]) -> Vec<String> {
+    marks
+        .iter()
+        .enumerate()
+        .flat_map(|(index, (title, start_secs))| {
+            [
+                format!("CHAPTER{:03}={}", index + 1, chapter_timestamp(*start_secs)),
+                format!("CHAPTER{:03}NAME={}", index + 1, title),
+            ]
+        })
+        .collect()
+}
+
+// For players and servers that ignore the merged file's own chapter markers:
+// "<book>.chapters.txt" with one "HH:MM:SS.mmm Title" line per chapter, and
+// the metadata.json Audiobookshelf reads from a book's folder
+fn write_chapters_sidecar(
+    book: &BookMetadata,
+    merged_path: &Path,
+    outputs: &[ChapterOutput],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let marks = chapter_marks(outputs);
+    let total_secs = probe_duration_secs(merged_path)
+        .unwrap_or_else(|| outputs.iter().map(ChapterOutput::duration_secs).sum());
+
+    let mut text = String::new();
+    for (title, start_secs) in &marks {
+        text.push_str(&format!("{} {}\n", chapter_timestamp(*start_secs), title));
+    }
+    fs::write(merged_path.with_extension("chapters.txt"), text)?;
+
+    let chapters: Vec<serde_json::Value> = marks
+        .iter()
+        .enumerate()
+        .map(|(index, (title, start_secs))| {
+            let end_secs = marks.get(index + 1).map_or(total_secs, |(_, next)| *next);
+            serde_json::json!({
+                "id": index,
+                "start": start_secs,
+                "end": end_secs,
+                "title": title,
+            })
+        })
+        .collect();
+    let metadata = serde_json::json!({
+        "title": book.title,
+        "authors": book.author.iter().collect::<Vec<_>>(),
+        // Audiobookshelf's "Name #N" form
+        "series": book
+            .series
+            .iter()
+            .map(|series| match book.series_index {
+                Some(index) => format!("{} #{}", series, index),
+                None => series.clone(),
+            })
+            .collect::<Vec<_>>(),
+        "chapters": chapters,
+    });
+    let metadata_path = merged_path.with_file_name("metadata.json");
+    serde_json::to_writer_pretty(File::create(metadata_path)?, &metadata)?;
+    Ok(())
+}
+
+fn probe_duration_secs(path: &Path) -> Option<f64> {
+    let output = ProcessCommand::new("ffprobe")
+        .arg("-v")
+        .arg("error")
+        .arg("-show_entries")
+        .arg("format=duration")
+        .arg("-of")
+        .arg("default=noprint_wrappers=1:nokey=1")
+        .arg(path)
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn encode_url_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+// RFC 2822 date for RSS, e.g. "Tue, 03 Sep 2024 14:05:00 +0000"
+fn rfc2822_date(timestamp: u64) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let days = (timestamp / 86_400) as i64;
+    let secs = timestamp % 86_400;
+
+    // Civil-from-days conversion (proleptic Gregorian calendar)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} +0000",
+        WEEKDAYS[(days % 7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        secs / 3_600,
+        secs % 3_600 / 60,
+        secs % 60
+    )
+}
+
+// Moves the chapters numbered in `sequence` (spine order, offset by the
+// chapter number base) to the front in that order, then renumbers every
+// chapter by its new playback position
+fn reorder_chapters(
+    mut chapters: Vec<Chapter>,
+    sequence: &[usize],
+    base: usize,
+) -> Result<Vec<Chapter>, Box<dyn std::error::Error>> {
+    let mut ordered = Vec::with_capacity(chapters.len());
+    for &number in sequence {
+        let position = chapters
+            .iter()
+            .position(|chapter| chapter.source_order + base == number)
+            .ok_or_else(|| {
+                if ordered.iter().any(|chapter: &Chapter| chapter.source_order + base == number) {
+                    format!("Chapter {} is listed more than once in --order", number)
+                } else {
+                    format!(
+                        "--order names chapter {}, but this book has chapters {} to {}",
+                        number,
+                        base,
+                        (ordered.len() + chapters.len() + base).saturating_sub(1)
+                    )
+                }
+            })?;
+        ordered.push(chapters.remove(position));
+    }
+    ordered.append(&mut chapters);
+
+    for (order, chapter) in ordered.iter_mut().enumerate() {
+        chapter.order = order;
+    }
+    debug!(
+        "Playback order: {:?}",
+        ordered.iter().map(|chapter| chapter.source_order + base).collect::<Vec<_>>()
+    );
+    Ok(ordered)
+}
+
+// "audiobook.{m3u,pls,xspf}" listing every file in playback order (which --order
+// may have moved away from spine order) by its path from the output folder,
+// with its title and length
+fn create_playlist(
+    output_dir: &Path,
+    outputs: &[ChapterOutput],
+    format: PlaylistFormat,
+    book: &BookMetadata,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut outputs: Vec<&ChapterOutput> = outputs.iter().collect();
+    outputs.sort_by_key(|output| output.order);
+
+    // (path relative to the playlist, title, seconds)
+    let entries: Vec<(String, String, f64)> = outputs
+        .iter()
+        .flat_map(|output| {
+            output.files.iter().enumerate().map(move |(index, file)| {
+                let path = file.path.strip_prefix(output_dir).unwrap_or(&file.path);
+                let path = path
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy().into_owned())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                let title = if output.files.len() > 1 {
+                    format!("{} (part {})", output.title, index + 1)
+                } else {
+                    output.title.clone()
+                };
+                // Files kept by --resume were not synthesized in this run
+                let secs = match file.duration_secs {
+                    secs if secs > 0.0 => secs,
+                    _ => probe_duration_secs(&file.path).unwrap_or(0.0),
+                };
+                (path, title, secs)
+            })
+        })
+        .collect();
+
+    let playlist_path = output_dir.join(format!("audiobook.{}", format.extension()));
+    let mut playlist_file = BufWriter::new(File::create(playlist_path)?);
+
+    match format {
+        PlaylistFormat::M3u => {
+            writeln!(playlist_file, "#EXTM3U")?;
+            for (path, title, secs) in &entries {
+                writeln!(playlist_file, "#EXTINF:{},{}", secs.round(), title)?;
+                writeln!(playlist_file, "{}", path)?;
+            }
+        }
+        PlaylistFormat::Pls => {
+            writeln!(playlist_file, "[playlist]")?;
+            for (number, (path, title, secs)) in (1..).zip(&entries) {
+                writeln!(playlist_file, "File{}={}", number, path)?;
+                writeln!(playlist_file, "Title{}={}", number, title)?;
+                writeln!(playlist_file, "Length{}={}", number, secs.round())?;
+            }
+            writeln!(playlist_file, "NumberOfEntries={}", entries.len())?;
+            writeln!(playlist_file, "Version=2")?;
+        }
+        PlaylistFormat::Xspf => {
+            writeln!(playlist_file, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+            writeln!(playlist_file, "<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">")?;
+            writeln!(playlist_file, "  <title>{}</title>", xml_escape(&book.title))?;
+            if let Some(author) = &book.author {
+                writeln!(playlist_file, "  <creator>{}</creator>", xml_escape(author))?;
+            }
+            writeln!(playlist_file, "  <trackList>")?;
+            for (number, (path, title, secs)) in (1..).zip(&entries) {
+                // Locations are URIs, relative to the playlist
+                let location: Vec<String> = path.split('/').map(encode_url_segment).collect();
+                writeln!(playlist_file, "    <track>")?;
+                writeln!(playlist_file, "      <location>{}</location>", location.join("/"))?;
+                writeln!(playlist_file, "      <title>{}</title>", xml_escape(title))?;
+                if let Some(author) = &book.author {
+                    writeln!(playlist_file, "      <creator>{}</creator>", xml_escape(author))?;
+                }
+                writeln!(playlist_file, "      <album>{}</album>", xml_escape(&book.title))?;
+                writeln!(playlist_file, "      <trackNum>{}</trackNum>", number)?;
+                writeln!(playlist_file, "      <duration>{}</duration>", (secs * 1000.0).round())?;
+                writeln!(playlist_file, "    </track>")?;
+            }
+            writeln!(playlist_file, "  </trackList>")?;
+            writeln!(playlist_file, "</playlist>")?;
+        }
+    }
+
+    Ok(())
+}
+
+// Set by the Ctrl-C handler; workers finish their current chunk and stop
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+// The first Ctrl-C stops the run after the chunks in flight; a second one exits at once
+fn install_interrupt_handler() -> Result<(), Box<dyn std::error::Error>> {
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::Relaxed) {
+            std::process::exit(130);
+        }
+        warn!("⏸️  Interrupted; finishing the current chunks (press Ctrl-C again to quit now)");
+    })?;
+    Ok(())
+}
+
+// Written to the output folder when a run is interrupted; removed once a run completes
+const RESUME_MARKER: &str = "resume.json";
+
+#[derive(Debug, Serialize)]
+struct ResumeMarker {
+    interrupted_at: u64,
+    chapters: Vec<ResumeChapter>,
+}
+
+#[derive(Debug, Serialize)]
+struct ResumeChapter {
+    order: usize,
+    title: String,
+    complete: bool,
+    chunks_completed: usize,
+}
+
+fn write_resume_marker(output_dir: &Path, outputs: &[ChapterOutput]) -> Result<(), Box<dyn std::error::Error>> {
+    let marker = ResumeMarker {
+        interrupted_at: unix_timestamp(),
+        chapters: outputs
+            .iter()
+            .map(|output| ResumeChapter {
+                order: output.order,
+                title: output.title.clone(),
+                complete: output.complete,
+                chunks_completed: output.files.len(),
+            })
+            .collect(),
+    };
+
+    let mut file = tempfile::NamedTempFile::new_in(output_dir)?;
+    serde_json::to_writer_pretty(&mut file, &marker)?;
+    file.persist(output_dir.join(RESUME_MARKER))?;
+    Ok(())
+}
+
+// Settings for a run that live outside Config
+struct RunOptions {
+    overrides: Option<PathBuf>,
+    dry_run: bool,
+    estimate_only: bool,
+    // Chapter number to write to stdout instead of converting the whole book
+    stream_chapter: Option<usize>,
+    // Spine item to print the text of, stage by stage with show_cleaning
+    resource: Option<String>,
+    show_cleaning: bool,
+    podcast_feed: bool,
+    feed_base_url: Option<String>,
+    progress: ProgressMode,
+    // Set in batch mode, where per-book bars stack under the overall one
+    batch_progress: Option<MultiProgress>,
+}
+
+fn convert_book(
+    input_path: &Path,
+    output: Option<&Path>,
+    config: &Config,
+    options: &RunOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    info!("🔄 Initializing EPUB to Audiobook Converter...");
+    let start_time = Instant::now();
+
+    let processor_config = if options.stream_chapter.is_some() {
+        Config {
+            output_format: AudioFormat::Wav,
+            ..config.clone()
+        }
+    } else {
+        config.clone()
+    };
+    let mut processor = EpubProcessor::new(processor_config)?;
+    let metadata = processor.read_metadata(input_path)?;
+    processor.set_book(&book_id(input_path), metadata.clone());
+    if let Some(path) = &options.overrides {
+        processor.set_overrides(ChapterOverrides::load(path)?)?;
+    }
+
+    if let Some(href) = &options.resource {
+        return print_resource(&processor, input_path, href, options.show_cleaning);
+    }
+
+    // In low-memory mode a first pass keeps only each chapter's outline; the
+    // text is extracted again chapter by chapter during conversion
+    info!("📖 Extracting chapters from EPUB...");
+    if config.low_memory && !config.chapter_order.is_empty() {
+        return Err("--order needs every chapter up front and cannot be combined with --low-memory".into());
+    }
+    let (chapters, mut manifest) = if config.low_memory {
+        let outline = processor
+            .iter_chapters(input_path)
+            .filter(|chapter| chapter.as_ref().map_or(true, |chapter| config.converts_chapter(chapter.order)))
+            .map(|chapter| chapter.map(|chapter| ManifestChapter::new(&chapter, config.effective_wpm())))
+            .collect::<Result<Vec<_>, _>>()?;
+        (None, BookManifest::from_outline(&metadata, outline))
+    } else {
+        let mut chapters = processor.extract_chapters(input_path)?;
+        chapters.retain(|chapter| config.converts_chapter(chapter.order));
+        let manifest = BookManifest::new(&metadata, &chapters, config.effective_wpm());
+        (Some(chapters), manifest)
+    };
+    let chapter_count = manifest.chapters.len();
+    info!("✅ Found {} chapters", chapter_count);
+    if chapter_count == 0 && !config.only_chapters.is_empty() {
+        return Err("--chapters matches none of this book's chapters; see --dry-run for their numbers".into());
+    }
+
+    if options.estimate_only {
+        print_estimate(&processor, &manifest);
+        return Ok(());
+    }
+
+    if options.dry_run {
+        return print_dry_run(&processor, input_path, &manifest);
+    }
+
+    if let Some(number) = options.stream_chapter {
+        let base = config.chapter_number_base;
+        let chapter = match chapters {
+            Some(chapters) => chapters.into_iter().find(|chapter| chapter.order + base == number),
+            None => processor
+                .iter_chapters(input_path)
+                .find(|chapter| chapter.as_ref().map_or(true, |chapter| chapter.order + base == number))
+                .transpose()?,
+        };
+        let chapter = chapter.ok_or_else(|| {
+            format!(
+                "Chapter {} not found; this book has chapters {} to {}",
+                number,
+                base,
+                (chapter_count + base).saturating_sub(1)
+            )
+        })?;
+        return stream_chapter(&processor, &chapter, chapter_count, &config.output_format);
+    }
+
+    // In batch mode every book gets its own folder named after its title, led
+    // by the series and position so a series lists in reading order
+    let output_dir = output.ok_or("An output directory is required")?;
+    let book_dir;
+    let output_dir = if options.batch_progress.is_some() {
+        book_dir = output_dir.join(sanitize_filename(&metadata.sort_title().unwrap_or_else(|| metadata.title.clone())));
+        book_dir.as_path()
+    } else {
+        output_dir
+    };
+    // An empty book would otherwise "succeed" with an empty folder and playlist
+    if chapter_count == 0 {
+        let mut hint = "try --include-nonlinear or --announce-images, or --audit to see what was skipped";
+        if processor.cleaning_report.lock().unwrap().is_some() {
+            fs::create_dir_all(output_dir)?;
+            processor.write_cleaning_report(output_dir)?;
+            hint = "cleaning_report.json lists what was skipped";
+        }
+        return Err(format!(
+            "No convertible chapters found in {}; the EPUB may be empty, image-only, or fully filtered by the cleanup settings ({})",
+            input_path.display(),
+            hint
+        )
+        .into());
+    }
+    prepare_output_dir(output_dir, config.output_policy)?;
+
+    // Written before conversion, so it is there even if the run is interrupted
+    processor.write_cleaning_report(output_dir)?;
+
+    info!(
+        "📊 Total words: {} (~{} of audio)",
+        manifest.total_words,
+        format_duration(manifest.estimated_duration())
+    );
+
+    // Each chapter goes to a single worker, so workers past the chapter count
+    // sit idle; only mentioned when the count was chosen rather than defaulted
+    if config.max_workers > chapter_count.max(1) && config.max_workers != num_cpus::get() {
+        warn!(
+            "⚠️  {} workers but only {} chapter(s); chapters are converted one per worker, so the rest will idle",
+            config.max_workers, chapter_count
+        );
+    }
+
+    info!("🎤 Converting chapters to audio...");
+    let progress = CliProgress::new(&manifest.chapters, options.progress, options.batch_progress.as_ref())?;
+    let result = match chapters {
+        Some(chapters) => processor.process_chapters(chapters, output_dir, |event| progress.handle(event)),
+        None => processor.process_chapters_lazily(
+            processor
+                .iter_chapters(input_path)
+                .filter(|chapter| chapter.as_ref().map_or(true, |chapter| config.converts_chapter(chapter.order))),
+            chapter_count,
+            output_dir,
+            |event| progress.handle(event),
+        ),
+    };
+    progress.finish();
+    let mut outputs = result?;
+
+    if outputs.iter().any(|output| !output.complete) {
+        write_resume_marker(output_dir, &outputs)?;
+        let done = outputs.iter().filter(|output| output.complete).count();
+        return Err(format!(
+            "Interrupted after {} of {} chapters; re-run with --resume to continue",
+            done,
+            outputs.len()
+        )
+        .into());
+    }
+    let marker_path = output_dir.join(RESUME_MARKER);
+    if marker_path.exists() {
+        fs::remove_file(marker_path)?;
+    }
+
+    if config.join_chunks {
+        info!("🔗 Joining each chapter's chunks into one file...");
+        let total_tracks = outputs.len();
+        for (index, output) in outputs.iter_mut().enumerate() {
+            join_chapter_chunks(&processor, output_dir, output, (index + 1, total_tracks), chapter_count)?;
+        }
+    }
+
+    let summary = processor.summary(&outputs, start_time.elapsed());
+    manifest.record_outputs(&outputs, output_dir);
+    manifest.summary = Some(summary.clone());
+    manifest.write(output_dir)?;
+
+    info!("📝 Creating playlist...");
+    create_playlist(output_dir, &outputs, config.playlist_format, &metadata)?;
+
+    // The chapters form one ReplayGain album; the merged file and the parts are
+    // each measured as albums of their own
+    let mut replaygain_albums: Vec<Vec<PathBuf>> =
+        vec![outputs.iter().flat_map(|output| output.files.iter().map(|file| file.path.clone())).collect()];
+
+    if config.merge_output {
+        info!("🔗 Merging chapters into a single file...");
+        let merged_path = merge_book(&processor, output_dir, &outputs)?;
+        info!("   Wrote {}", merged_path.display());
+        if matches!(config.output_format, AudioFormat::Flac) {
+            embed_flac_cuesheet(&processor, &merged_path, &outputs)?;
+        }
+        if config.chapters_sidecar {
+            write_chapters_sidecar(&metadata, &merged_path, &outputs)?;
+        }
+        replaygain_albums.push(vec![merged_path]);
+    }
+
+    if let Some(limit_secs) = config.split_duration_secs {
+        info!("✂️  Splitting into parts of about {}...", format_duration(Duration::from_secs(limit_secs)));
+        let parts = split_into_parts(&processor, output_dir, &outputs, limit_secs)?;
+        info!("   Wrote {} parts", parts.len());
+        replaygain_albums.push(parts);
+    }
+
+    if config.replaygain {
+        info!("🔊 Writing ReplayGain tags...");
+        for album in &replaygain_albums {
+            apply_replaygain(&config.output_format, album)?;
+        }
+    }
+
+    if options.podcast_feed {
+        info!("📡 Writing podcast feed...");
+        let cover = processor.extract_cover(input_path, output_dir).unwrap_or_else(|e| {
+            warn!("Could not extract cover image: {}", e);
+            None
+        });
+        write_podcast_feed(
+            output_dir,
+            &metadata,
+            &outputs,
+            &config.output_format,
+            options.feed_base_url.as_deref(),
+            cover.as_deref(),
+        )?;
+    }
+
+    let duration = start_time.elapsed();
+    info!("✅ Conversion completed in {:.2?}", duration);
+    summary.log();
+    info!("📁 Output saved to: {}", output_dir.display());
+
+    Ok(())
+}
+
+// Expands directories to the EPUBs they contain, in name order
+fn collect_epubs(inputs: &[&String]) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let mut books = Vec::new();
+    for input in inputs {
+        let path = Path::new(input);
+        if path.is_dir() {
+            let mut found: Vec<PathBuf> = fs::read_dir(path)?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| {
+                    path.is_file()
+                        && path
+                            .extension()
+                            .map(|ext| ext.eq_ignore_ascii_case("epub"))
+                            .unwrap_or(false)
+                })
+                .collect();
+            found.sort();
+            books.extend(found);
+        } else {
+            books.push(path.to_path_buf());
+        }
+    }
+
+    if books.is_empty() {
+        return Err("No EPUB files found in the given inputs".into());
+    }
+    Ok(books)
+}
+
+// Converts each book in turn, carrying on past failures and reporting them at the end
+fn run_batch(
+    books: &[PathBuf],
+    output: Option<&Path>,
+    config: &Config,
+    mut options: RunOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let multi = if log_enabled!(Level::Info) && !options.dry_run && options.progress == ProgressMode::Bar {
+        MultiProgress::new()
+    } else {
+        MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+    };
+    let overall = multi.add(ProgressBar::new(books.len() as u64));
+    overall.set_style(ProgressStyle::default_bar().template("📚 [{bar:40.green/white}] {pos}/{len} books {msg}")?);
+    options.batch_progress = Some(multi.clone());
+
+    let mut failures = Vec::new();
+    for book in books {
+        overall.set_message(book.file_name().unwrap_or_default().to_string_lossy().to_string());
+        let result = validate_epub(book).and_then(|_| convert_book(book, output, config, &options));
+        if let Err(e) = result {
+            multi.suspend(|| error!("❌ {}: {}", book.display(), e));
+            failures.push((book, e));
+        }
+        overall.inc(1);
+    }
+    overall.finish_and_clear();
+
+    info!(
+        "📚 Batch finished: {} converted, {} failed",
+        books.len() - failures.len(),
+        failures.len()
+    );
+    for (book, error) in &failures {
+        warn!("   {}: {}", book.display(), error);
+    }
+
+    let message = format!("{} of {} books failed", failures.len(), books.len());
+    let first_code = failures.first().map(|(_, e)| exit_code(e.as_ref()));
+    if failures.is_empty() {
+        Ok(())
+    } else if failures.len() < books.len() {
+        Err(ConversionError::PartialFailure(message).into())
+    } else if first_code != Some(1) && failures.iter().all(|(_, e)| Some(exit_code(e.as_ref())) == first_code) {
+        // Every book failed the same way (no TTS engine, say); that cause's code is more useful
+        Err(failures.swap_remove(0).1)
+    } else {
+        Err(message.into())
+    }
+}
+
+// Conventional config locations, most specific first
+fn config_file_candidates() -> Vec<PathBuf> {
+    let mut candidates = vec![PathBuf::from("ebuptts.toml")];
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+    if let Some(config_home) = config_home {
+        candidates.push(config_home.join("ebuptts").join("config.toml"));
+    }
+    candidates
+}
+
+fn find_config_file(matches: &ArgMatches) -> Result<Option<PathBuf>, Box<dyn std::error::Error>> {
+    if let Some(path) = matches.get_one::<String>("config") {
+        let path = PathBuf::from(path);
+        if !path.is_file() {
+            return Err(format!("Config file {} not found", path.display()).into());
+        }
+        return Ok(Some(path));
+    }
+    if matches.get_flag("no-config") {
+        return Ok(None);
+    }
+    Ok(config_file_candidates().into_iter().find(|path| path.is_file()))
+}
+
+// Everything a run would trip over before reading a book: values a config file
+// can set outside the ranges the flags enforce, and missing programs
+fn config_problems(config: &Config) -> Vec<String> {
+    let mut problems = Vec::new();
+    let installed = |program: &str| {
+        ProcessCommand::new("which")
+            .arg(program)
+            .output()
+            .map(|output| output.status.success())
+            .unwrap_or(false)
+    };
+
+    if !(0.0..=1.0).contains(&config.quality) {
+        problems.push(format!("quality {} is outside 0.0-1.0", config.quality));
+    }
+    if !(0.25..=4.0).contains(&config.voice_speed) {
+        problems.push(format!("voice_speed {} is outside 0.25-4.0", config.voice_speed));
+    }
+    if !(0.0..=2.0).contains(&config.voice_pitch) {
+        problems.push(format!("voice_pitch {} is outside 0.0-2.0", config.voice_pitch));
+    }
+    if config.amplitude > 200 {
+        problems.push(format!("amplitude {} is above 200", config.amplitude));
+    }
+    if config.max_chunk_size == 0 {
+        problems.push("max_chunk_size must be at least 1".to_string());
+    }
+    if config.min_chunk_size > config.max_chunk_size {
+        problems.push(format!(
+            "min_chunk_size {} is above max_chunk_size {}",
+            config.min_chunk_size, config.max_chunk_size
+        ));
+    }
+    if config.max_workers == 0 {
+        problems.push("max_workers must be at least 1".to_string());
+    }
+    if !(1..=2).contains(&config.channels) {
+        problems.push(format!("channels {} is not 1 or 2", config.channels));
+    }
+    if ![16, 24, 32].contains(&config.bits_per_sample) {
+        problems.push(format!("bits_per_sample {} is not 16, 24 or 32", config.bits_per_sample));
+    }
+    if config.sample_rate == 0 {
+        problems.push("sample_rate must be above 0".to_string());
+    }
+    if config.chunk_timeout_secs == Some(0) {
+        problems.push("chunk_timeout_secs must be at least 1".to_string());
+    }
+    if let Some(bitrate) = config.bitrate_kbps.filter(|bitrate| !(8..=512).contains(bitrate)) {
+        problems.push(format!("bitrate_kbps {} is outside 8-512", bitrate));
+    }
+    if config.fade_ms > 10_000 {
+        problems.push(format!("fade_ms {} is above 10000", config.fade_ms));
+    }
+    if let Some(level) = config.split_heading_level.filter(|level| !(1..=6).contains(level)) {
+        problems.push(format!("split_heading_level {} is outside 1-6", level));
+    }
+    if let Some(Err(e)) = config.text_chapter_regex.as_deref().map(Regex::new) {
+        problems.push(format!("Invalid text_chapter_regex: {}", e));
+    }
+    if let Err(e) = validate_filename_template(&config.filename_template) {
+        problems.push(e.to_string());
+    }
+
+    // A named engine is only looked up on first use, so check it is installed here
+    match select_synthesizer(config) {
+        Ok(None) => problems.push(ConversionError::no_tts_engine().to_string()),
+        Ok(Some(_)) if !["auto", "mock"].contains(&config.engine.as_str()) && !installed(&config.engine) => {
+            problems.push(format!("TTS engine {} is not installed", config.engine))
+        }
+        Ok(Some(_)) => {}
+        Err(e) => problems.push(e.to_string()),
+    }
+
+    let encoders: &[&str] = match config.output_format {
+        AudioFormat::Vorbis => &["oggenc", "ffmpeg"],
+        AudioFormat::Flac => &["flac", "ffmpeg"],
+        AudioFormat::Mp3 => &["lame", "ffmpeg"],
+        AudioFormat::Wav => &[],
+    };
+    if !encoders.is_empty() && !encoders.iter().any(|encoder| installed(encoder)) {
+        problems.push(format!("No {} encoder found; install {}", config.output_format.extension(), encoders.join(" or ")));
+    }
+    if config.merge_output && !installed("ffmpeg") {
+        problems.push("merge_output requires ffmpeg".to_string());
+    }
+    if config.join_chunks && !installed("ffmpeg") {
+        problems.push("join_chunks requires ffmpeg".to_string());
+    }
+
+    problems
+}
+
+// --check-config: the effective settings as JSON on stdout, problems on stderr
+fn check_config(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    println!("{}", serde_json::to_string_pretty(config)?);
+    let problems = config_problems(config);
+    for problem in &problems {
+        error!("❌ {}", problem);
+    }
+    if problems.is_empty() {
+        info!("✅ Configuration is valid");
+        Ok(())
+    } else {
+        Err(format!("{} problem(s) found in the configuration", problems.len()).into())
+    }
+}
+
+// Contents of the text file named by a path-valued option, if given
+fn read_text_arg(matches: &ArgMatches, name: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    match matches.get_one::<String>(name) {
+        Some(path) => fs::read_to_string(path)
+            .map(Some)
+            .map_err(|e| format!("Could not read --{} file {}: {}", name, path, e).into()),
+        None => Ok(None),
+    }
+}
+
+// Keys are Config field names; anything left out keeps its default
+fn load_config_file(path: &Path) -> Result<Config, Box<dyn std::error::Error>> {
+    let content = fs::read_to_string(path)?;
+    toml::from_str(&content).map_err(|e| format!("Invalid config file {}: {}", path.display(), e).into())
+}
+
+// Precedence: command line, then config file, then built-in defaults. Only
+// options actually typed on the command line replace the file's values.
+fn merge_cli_config(file: Config, cli: Config, matches: &ArgMatches) -> Config {
+    let given = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    let mut config = file;
+
+    if given("format") {
+        config.output_format = cli.output_format;
+    }
+    if given("quality") {
+        config.quality = cli.quality;
+    }
+    if given("bitrate") {
+        config.bitrate_kbps = cli.bitrate_kbps;
+    }
+    if given("engine") {
+        config.engine = cli.engine;
+    }
+    if given("espeak-data") {
+        config.espeak_data_path = cli.espeak_data_path;
+    }
+    if given("voice") {
+        config.voice = cli.voice;
+    }
+    if given("dialogue-voice") {
+        config.dialogue_voice = cli.dialogue_voice;
+    }
+    if given("speed") {
+        config.voice_speed = cli.voice_speed;
+    }
+    if given("channels") {
+        config.channels = cli.channels;
+    }
+    if given("bit-depth") {
+        config.bits_per_sample = cli.bits_per_sample;
+    }
+    if given("amplitude") {
+        config.amplitude = cli.amplitude;
+    }
+    if given("workers") {
+        config.max_workers = cli.max_workers;
+    }
+    if given("oversubscribe") {
+        config.oversubscribe = cli.oversubscribe;
+    }
+    if given("chunk-timeout") {
+        config.chunk_timeout_secs = cli.chunk_timeout_secs;
+    }
+    if given("no-aggressive") {
+        config.preprocessing_aggressive = cli.preprocessing_aggressive;
+    }
+    if given("no-cache") {
+        config.cache_enabled = cli.cache_enabled;
+    }
+    if given("cache-dir") {
+        config.cache_dir = cli.cache_dir;
+    }
+    if given("tmp-dir") {
+        config.tmp_dir = cli.tmp_dir;
+    }
+    if given("include-nonlinear") {
+        config.include_nonlinear = cli.include_nonlinear;
+    }
+    if given("include-href") {
+        config.include_hrefs = cli.include_hrefs;
+    }
+    if given("exclude-href") {
+        config.exclude_hrefs = cli.exclude_hrefs;
+    }
+    if given("no-announce-titles") {
+        config.announce_titles = cli.announce_titles;
+    }
+    if given("merge-untitled") {
+        config.merge_untitled_fragments = cli.merge_untitled_fragments;
+    }
+    if given("split-heading") {
+        config.split_heading_level = cli.split_heading_level;
+    }
+    if given("split-pagebreaks") {
+        config.split_on_pagebreaks = cli.split_on_pagebreaks;
+    }
+    if given("split-regex") {
+        config.text_chapter_regex = cli.text_chapter_regex;
+    }
+    if given("strip-running-headers") {
+        config.strip_running_headers = cli.strip_running_headers;
+    }
+    if given("dash-pauses") {
+        config.dash_to_pause = cli.dash_to_pause;
+    }
+    if given("pause-scale") {
+        config.pause_scale = cli.pause_scale;
+    }
+    if given("normalize-caps") {
+        config.normalize_caps = cli.normalize_caps;
+    }
+    if given("mark-blockquotes") {
+        config.mark_blockquotes = cli.mark_blockquotes;
+    }
+    if given("announce-images") {
+        config.announce_skipped_images = cli.announce_skipped_images;
+    }
+    if given("clean-titles") {
+        config.clean_chapter_titles = cli.clean_chapter_titles;
+    }
+    if given("fade-ms") {
+        config.fade_ms = cli.fade_ms;
+    }
+    if given("hash-word") {
+        config.hash_word = cli.hash_word;
+    }
+    if given("trim-silence") {
+        config.trim_chunk_silence = cli.trim_chunk_silence;
+    }
+    if given("overwrite") || given("resume") {
+        config.output_policy = cli.output_policy;
+    }
+    if given("tables") {
+        config.table_mode = cli.table_mode;
+    }
+    if given("images") {
+        config.image_alt = cli.image_alt;
+    }
+    if given("ruby") {
+        config.ruby_mode = cli.ruby_mode;
+    }
+    if given("tag-preset") {
+        config.tag_preset = cli.tag_preset;
+    }
+    if given("title") {
+        config.book_title = cli.book_title;
+    }
+    if given("author") {
+        config.book_author = cli.book_author;
+    }
+    if given("series") {
+        config.book_series = cli.book_series;
+    }
+    if given("playlist-format") {
+        config.playlist_format = cli.playlist_format;
+    }
+    if given("merge") {
+        config.merge_output = cli.merge_output;
+    }
+    if given("join-chunks") {
+        config.join_chunks = cli.join_chunks;
+    }
+    if given("chapters-sidecar") {
+        config.chapters_sidecar = cli.chapters_sidecar;
+    }
+    if given("split-duration") {
+        config.split_duration_secs = cli.split_duration_secs;
+    }
+    if given("replaygain") {
+        config.replaygain = cli.replaygain;
+    }
+    if given("transcripts") {
+        config.emit_transcripts = cli.emit_transcripts;
+    }
+    if given("audit") {
+        config.audit = cli.audit;
+    }
+    if given("subtitles") {
+        config.subtitles = cli.subtitles;
+    }
+    if given("keep-wav") {
+        config.keep_wav = cli.keep_wav;
+    }
+    if given("low-memory") {
+        config.low_memory = cli.low_memory;
+    }
+    if given("order") {
+        config.chapter_order = cli.chapter_order;
+    }
+    if given("intro") {
+        config.intro_text = cli.intro_text;
+    }
+    if given("outro") {
+        config.outro_text = cli.outro_text;
+    }
+    if given("chapters") {
+        config.only_chapters = cli.only_chapters;
+    }
+    if given("name-template") {
+        config.filename_template = cli.filename_template;
+    }
+    if given("chapter-base") {
+        config.chapter_number_base = cli.chapter_number_base;
+    }
+
+    config
+}
+
+fn cli() -> Command {
+    Command::new("EPUB to Audiobook Converter")
+    .version("1.0")
+    .author("Advanced TTS Converter")
+    .about("Converts EPUB files to high-quality audiobooks using CPU-optimized TTS")
+    .after_help(EXIT_CODES_HELP)
+    .arg(
+        Arg::new("input")
+        .short('i')
+        .long("input")
+        .value_name("FILE")
+        .help("Input EPUB file or directory of EPUBs; repeat for several books")
+        .action(ArgAction::Append)
+        .required_unless_present_any(["clear-cache", "benchmark", "selftest", "list-voices", "check-config"]),
+    )
+    .arg(
+        Arg::new("output")
+        .short('o')
+        .long("output")
+        .value_name("DIR")
+        .help("Output directory")
+        .required_unless_present_any(["dry-run", "estimate-only", "stdout", "clear-cache", "benchmark", "selftest", "list-voices"]),
+    )
+    .arg(
+        Arg::new("format")
+        .short('f')
+        .long("format")
+        .value_name("FORMAT")
+        .help("Output audio format")
+        .value_parser(["vorbis", "flac", "mp3", "wav"])
+        .default_value("vorbis"),
+    )
+    .arg(
+        Arg::new("quality")
+        .short('q')
+        .long("quality")
+        .value_name("FLOAT")
+        .help("Audio quality (0.0-1.0)")
+        .value_parser(clap::value_parser!(f32))
+        .default_value("0.7"),
+    )
+    .arg(
+        Arg::new("bitrate")
+        .short('b')
+        .long("bitrate")
+        .value_name("KBPS")
+        .help("Target bitrate for Vorbis/MP3 (mutually exclusive with --quality)")
+        .value_parser(clap::value_parser!(u32).range(8..=512))
+        .conflicts_with("quality"),
+    )
+    .arg(
+        Arg::new("channels")
+        .long("channels")
+        .value_name("NUM")
+        .help("Channels of the intermediate WAV audio")
+        .value_parser(clap::value_parser!(u16).range(1..=2))
+        .default_value("1"),
+    )
+    .arg(
+        Arg::new("bit-depth")
+        .long("bit-depth")
+        .value_name("BITS")
+        .help("Bit depth of the intermediate WAV audio")
+        .value_parser(["16", "24", "32"])
+        .default_value("16"),
+    )
+    .arg(
+        Arg::new("speed")
+        .short('s')
+        .long("speed")
+        .value_name("FLOAT")
+        .help("Voice speed multiplier")
+        .value_parser(clap::value_parser!(f32))
+        .default_value("1.0"),
+    )
+    .arg(
+        Arg::new("amplitude")
+        .long("amplitude")
+        .value_name("0-200")
+        .help("espeak amplitude (volume)")
+        .value_parser(clap::value_parser!(u32).range(0..=200))
+        .default_value("100"),
+    )
+    .arg(
+        Arg::new("engine")
+        .long("engine")
+        .value_name("ENGINE")
+        .help("TTS engine; 'mock' writes silence without any TTS installed")
+        .value_parser(TTS_ENGINES)
+        .default_value("auto"),
+    )
+    .arg(
+        Arg::new("espeak-data")
+        .long("espeak-data")
+        .value_name("DIR")
+        .help("espeak-ng-data directory (or its parent) to load voices from, passed to espeak as --path"),
+    )
+    .arg(
+        Arg::new("voice")
+        .long("voice")
+        .value_name("VOICE")
+        .help("espeak voice, optionally with a variant (e.g. en+f3, en-us+whisper)")
+        .default_value("en"),
+    )
+    .arg(
+        Arg::new("dialogue-voice")
+        .long("dialogue-voice")
+        .value_name("VOICE")
+        .help("Experimental: read \"quoted\" dialogue in this voice (e.g. en+m3)"),
+    )
+    .arg(
+        Arg::new("overrides")
+        .long("overrides")
+        .value_name("FILE")
+        .help("JSON file with per-chapter voice, voice_speed and voice_pitch overrides"),
+    )
+    .arg(
+        Arg::new("workers")
+        .short('w')
+        .long("workers")
+        .value_name("NUM")
+        .help("Number of worker threads")
+        .value_parser(clap::value_parser!(usize))
+        .default_value(&num_cpus::get().to_string()),
+    )
+    .arg(
+        Arg::new("oversubscribe")
+        .long("oversubscribe")
+        .help("Use all --workers even when there are more than CPU cores")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+        Arg::new("chunk-timeout")
+        .long("chunk-timeout")
+        .value_name("SECS")
+        .help("Kill a TTS or encoder process stuck on one chunk after this many seconds and retry the chunk once")
+        .value_parser(clap::value_parser!(u64).range(1..)),
+    )
+    .arg(
+        Arg::new("name-template")
+        .long("name-template")
+        .value_name("TEMPLATE")
+        .help("Chunk file name template; placeholders: {chapter_order}, {chapter_title}, {chunk}, {book_title}, {author}, {ext}")
+        .default_value(DEFAULT_FILENAME_TEMPLATE),
+    )
+    .arg(
+        Arg::new("chapter-base")
+        .long("chapter-base")
+        .value_name("BASE")
+        .help("Number of the first chapter in folder and file names")
+        .value_parser(["0", "1"])
+        .default_value("0"),
+    )
+    .arg(
+        Arg::new("tables")
+        .long("tables")
+        .value_name("MODE")
+        .help("How to narrate tables")
+        .value_parser(["linearize", "skip", "keep"])
+        .default_value("linearize"),
+    )
+    .arg(
+        Arg::new("images")
+        .long("images")
+        .value_name("MODE")
+        .help("Skip images, read their alt text, or read their <figcaption> captions")
+        .value_parser(["skip", "alt", "caption"])
+        .default_value("skip"),
+    )
+    .arg(
+        Arg::new("ruby")
+        .long("ruby")
+        .value_name("MODE")
+        .help("Read <ruby> annotated text as its base text or as its <rt> reading (e.g. furigana)")
+        .value_parser(["base", "reading"])
+        .default_value("base"),
+    )
+    .arg(
+        Arg::new("overwrite")
+        .long("overwrite")
+        .help("Clear a non-empty output directory before converting")
+        .action(ArgAction::SetTrue)
+        .conflicts_with("resume"),
+    )
+    .arg(
+        Arg::new("resume")
+        .long("resume")
+        .help("Continue into a non-empty output directory, keeping files that already exist")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+        Arg::new("no-aggressive")
+        .long("no-aggressive")
+        .help("Skip aggressive text cleanup (symbols, hyphenation, abbreviations, sentence spacing)")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+        Arg::new("no-cache")
+        .long("no-cache")
+        .help("Do not read or write the TTS audio cache")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+        Arg::new("cache-dir")
+        .long("cache-dir")
+        .value_name("DIR")
+        .help("Audio cache location [default: $XDG_CACHE_HOME/ebuptts or ~/.cache/ebuptts]"),
+    )
+    .arg(
+        Arg::new("tmp-dir")
+        .long("tmp-dir")
+        .value_name("DIR")
+        .help("Directory for the run's scratch files [default: the system temp directory]"),
+    )
+    .arg(
+        Arg::new("hash-word")
+        .long("hash-word")
+        .value_name("WORD")
+        .help("How aggressive cleanup reads \"#\"")
+        .value_parser(["number", "hashtag"])
+        .default_value("number"),
+    )
+    .arg(
+        Arg::new("dash-pauses")
+        .long("dash-pauses")
+        .help("Read em-dashes and parenthetical asides as short pauses")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+        Arg::new("pause-scale")
+        .long("pause-scale")
+        .value_name("FLOAT")
+        .help("Lengthen pauses: above 1.0, periods pause longer than commas and paragraph ends longest")
+        .value_parser(clap::value_parser!(f32))
+        .default_value("1.0"),
+    )
+    .arg(
+        Arg::new("normalize-caps")
+        .long("normalize-caps")
+        .help("Lowercase shouted all-caps words (\"THE END\") while keeping acronyms (\"NASA\")")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+        Arg::new("mark-blockquotes")
+        .long("mark-blockquotes")
+        .help("Pause before and after blockquotes and epigraphs")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+        Arg::new("announce-images")
+        .long("announce-images")
+        .help("Read \"Illustration\" for image-only pages instead of skipping them")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+        Arg::new("clean-titles")
+        .long("clean-titles")
+        .help("Strip a leading \"Chapter N\", roman numeral or number from chapter headings")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+        Arg::new("fade-ms")
+        .long("fade-ms")
+        .value_name("MS")
+        .help("Fade each chapter in and out over this many milliseconds (0 to disable)")
+        .value_parser(clap::value_parser!(u32).range(0..=10_000))
+        .default_value("50"),
+    )
+    .arg(
+        Arg::new("no-announce-titles")
+        .long("no-announce-titles")
+        .help("Do not read the chapter title at the start of each chapter")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+        Arg::new("merge-untitled")
+        .long("merge-untitled")
+        .help("Append spine items without a heading to the previous chapter instead of numbering them")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+        Arg::new("split-heading")
+        .long("split-heading")
+        .value_name("LEVEL")
+        .help("Start a new chapter at every heading of this level (1-6), even within one file")
+        .value_parser(clap::value_parser!(u8).range(1..=6)),
+    )
+    .arg(
+        Arg::new("split-pagebreaks")
+        .long("split-pagebreaks")
+        .help("Start a new chapter at epub:type=\"chapter\" elements and page-break-before styles, even within one file")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+        Arg::new("split-regex")
+        .long("split-regex")
+        .value_name("REGEX")
+        .help("Also start a new chapter at each line of text matching REGEX (e.g. '^CHAPTER ' or '^\\* \\* \\*$')"),
+    )
+    .arg(
+        Arg::new("strip-running-headers")
+        .long("strip-running-headers")
+        .help("Remove lines repeated at the top or bottom of most chapters, such as the book title")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+        Arg::new("include-nonlinear")
+        .long("include-nonlinear")
+        .help("Also convert spine items marked linear=\"no\" (footnotes, pop-ups)")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+        Arg::new("include-href")
+        .long("include-href")
+        .value_name("GLOB")
+        .help("Only convert spine items whose href or file name matches; repeatable")
+        .action(ArgAction::Append),
+    )
+    .arg(
+        Arg::new("exclude-href")
+        .long("exclude-href")
+        .value_name("GLOB")
+        .help("Skip spine items whose href or file name matches; repeatable")
+        .action(ArgAction::Append),
+    )
+    .arg(
+        Arg::new("verbose")
+        .short('v')
+        .long("verbose")
+        .help("Increase log verbosity (-v debug, -vv trace)")
+        .action(ArgAction::Count),
+    )
+    .arg(
+        Arg::new("quiet")
+        .long("quiet")
+        .help("Only log errors")
+        .action(ArgAction::SetTrue)
+        .conflicts_with("verbose"),
+    )
+    .arg(
+        Arg::new("clear-cache")
+        .long("clear-cache")
+        .value_name("BOOK")
+        .help("Remove cached audio, only for BOOK (input file name without extension) if given")
+        .num_args(0..=1)
+        .default_missing_value(""),
+    )
+    .arg(
+        Arg::new("merge")
+        .long("merge")
+        .help("Also join all chapters into one file with chapter markers (requires ffmpeg)")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+        Arg::new("join-chunks")
+        .long("join-chunks")
+        .help("Join each chapter's chunks into one gapless file per chapter (requires ffmpeg)")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+        Arg::new("chapters-sidecar")
+        .long("chapters-sidecar")
+        .help("Write the merged file's chapter start times to .chapters.txt and an Audiobookshelf metadata.json")
+        .action(ArgAction::SetTrue)
+        .requires("merge"),
+    )
+    .arg(
+        Arg::new("replaygain")
+        .long("replaygain")
+        .help("Tag the output with ReplayGain track and album gain (rsgain/loudgain, else ffmpeg)")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+        Arg::new("split-duration")
+        .long("split-duration")
+        .value_name("DURATION")
+        .help("Also regroup the audio into part_001, part_002... files of about this length, e.g. 30m (requires ffmpeg)")
+        .value_parser(parse_split_duration),
+    )
+    .arg(
+        Arg::new("transcripts")
+        .long("transcripts")
+        .help("Save each chapter's cleaned text, as read aloud, to a .txt beside its audio")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+        Arg::new("audit")
+        .long("audit")
+        .help("Write cleaning_report.json listing what text cleanup removed, expanded or skipped")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+        Arg::new("subtitles")
+        .long("subtitles")
+        .value_name("FORMAT")
+        .help("Write per-chapter subtitles with one cue per sentence")
+        .value_parser(["srt", "vtt"]),
+    )
+    .arg(
+        Arg::new("keep-wav")
+        .long("keep-wav")
+        .help("Keep each chunk's intermediate WAV beside the encoded file")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+        Arg::new("low-memory")
+        .long("low-memory")
+        .help("Extract and convert chapters one at a time to bound memory use on very large books")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+        Arg::new("order")
+        .long("order")
+        .value_name("N,N,...")
+        .help("Play these chapters first, in this order (numbers as in --dry-run); the rest follow in spine order")
+        .value_parser(clap::value_parser!(usize))
+        .value_delimiter(',')
+        .num_args(1..)
+        .conflicts_with("low-memory"),
+    )
+    .arg(
+        Arg::new("chapters")
+        .long("chapters")
+        .value_name("LIST")
+        .help("Only convert these chapters, numbered as in --dry-run, e.g. 0 or 2-5,9")
+        .value_parser(parse_chapter_list),
+    )
+    .arg(
+        Arg::new("intro")
+        .long("intro")
+        .value_name("FILE")
+        .help("Text file read before the first chapter, as a chapter of its own; {title} and {author} are filled in"),
+    )
+    .arg(
+        Arg::new("outro")
+        .long("outro")
+        .value_name("FILE")
+        .help("Text file read after the last chapter, as a chapter of its own; {title} and {author} are filled in"),
+    )
+    .arg(
+        Arg::new("trim-silence")
+        .long("trim-silence")
+        .help("Trim silence at chunk edges to a fixed gap so merged chapters have no audible seams")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+        Arg::new("tag-preset")
+        .long("tag-preset")
+        .value_name("PRESET")
+        .help("Tag output files; 'audiobook' tags chapters as tracks of one album, marked as an audiobook")
+        .value_parser(["none", "audiobook"])
+        .default_value("none"),
+    )
+    .arg(
+        Arg::new("title")
+        .long("title")
+        .value_name("TITLE")
+        .help("Book title for tags, file names, manifest, playlist and feed, instead of the EPUB's"),
+    )
+    .arg(
+        Arg::new("author")
+        .long("author")
+        .value_name("AUTHOR")
+        .help("Book author, instead of the EPUB's"),
+    )
+    .arg(
+        Arg::new("series")
+        .long("series")
+        .value_name("SERIES")
+        .help("Series the book belongs to, instead of the EPUB's"),
+    )
+    .arg(
+        Arg::new("playlist-format")
+        .long("playlist-format")
+        .value_name("FORMAT")
+        .help("Format of the playlist listing every file in order")
+        .value_parser(["m3u", "pls", "xspf"])
+        .default_value("m3u"),
+    )
+    .arg(
+        Arg::new("podcast-feed")
+        .long("podcast-feed")
+        .help("Write an RSS podcast feed (feed.xml) for the output directory")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+        Arg::new("feed-base-url")
+        .long("feed-base-url")
+        .value_name("URL")
+        .help("Base URL the output directory is hosted at; feed enclosures are relative otherwise")
+        .requires("podcast-feed"),
+    )
+    .arg(
+        Arg::new("list-voices")
+        .long("list-voices")
+        .help("List the voices of the selected engine (--engine) and exit")
+        .action(ArgAction::SetTrue)
+        .conflicts_with_all(["input", "selftest", "benchmark"]),
+    )
+    .arg(
+        Arg::new("selftest")
+        .long("selftest")
+        .help("Synthesize and encode a short phrase in every format, then check each file decodes to audible audio")
+        .action(ArgAction::SetTrue)
+        .conflicts_with_all(["input", "benchmark"]),
+    )
+    .arg(
+        Arg::new("check-config")
+        .long("check-config")
+        .help("Validate the settings from flags, config file and defaults, print them as JSON, and exit")
+        .action(ArgAction::SetTrue)
+        .conflicts_with_all(["input", "selftest", "benchmark"]),
+    )
+    .arg(
+        Arg::new("benchmark")
+        .long("benchmark")
+        .help("Synthesize a built-in text at several chunk sizes (cache off) and report throughput")
+        .action(ArgAction::SetTrue)
+        .conflicts_with_all(["input", "dry-run"]),
+    )
+    .arg(
+        Arg::new("chapter")
+        .long("chapter")
+        .value_name("N")
+        .help("Chapter to convert, numbered as in --dry-run")
+        .value_parser(clap::value_parser!(usize))
+        .requires("stdout"),
+    )
+    .arg(
+        Arg::new("stdout")
+        .long("stdout")
+        .help("Write the encoded --chapter to stdout, e.g. to pipe into a player")
+        .action(ArgAction::SetTrue)
+        .requires("chapter")
+        .conflicts_with_all(["dry-run", "estimate-only", "merge", "split-duration", "podcast-feed"]),
+    )
+    .arg(
+        Arg::new("resource")
+        .long("resource")
+        .value_name("HREF")
+        .help("Print the cleaned text of one spine item (e.g. OEBPS/ch07.xhtml) instead of converting")
+        .conflicts_with_all(["dry-run", "estimate-only", "stdout"]),
+    )
+    .arg(
+        Arg::new("show-cleaning")
+        .long("show-cleaning")
+        .help("With --resource, print the HTML size, the html2text output and the cleaned text")
+        .action(ArgAction::SetTrue)
+        .requires("resource"),
+    )
+    .arg(
+        Arg::new("config")
+        .long("config")
+        .value_name("FILE")
+        .help("TOML settings file (default: ./ebuptts.toml or $XDG_CONFIG_HOME/ebuptts/config.toml)")
+        .conflicts_with("no-config"),
+    )
+    .arg(
+        Arg::new("no-config")
+        .long("no-config")
+        .help("Do not load a config file found by convention")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+        Arg::new("estimate-only")
+        .long("estimate-only")
+        .help("Report characters, words, audio length and engine cost without synthesizing")
+        .action(ArgAction::SetTrue)
+        .conflicts_with("dry-run"),
+    )
+    .arg(
+        Arg::new("progress")
+        .long("progress")
+        .value_name("MODE")
+        .help("How to show progress; \"auto\" draws a bar only on an interactive terminal")
+        .value_parser(["auto", "bar", "plain", "machine", "none"])
+        .default_value("auto"),
+    )
+    .arg(
+        Arg::new("no-progress")
+        .long("no-progress")
+        .help("Do not show progress (same as --progress none)")
+        .action(ArgAction::SetTrue)
+        .conflicts_with("progress"),
+    )
+    .arg(
+        Arg::new("dry-run")
+        .long("dry-run")
+        .help("List the chapters that would be converted without synthesizing audio")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+        Arg::new("json-errors")
+        .long("json-errors")
+        .help("Also report a fatal error as one JSON line on stderr")
+        .action(ArgAction::SetTrue),
+    )
+}
+
+fn main() {
+    let matches = cli().get_matches();
+
+    init_logging(matches.get_count("verbose"), matches.get_flag("quiet"));
+
+    if let Err(e) = run(&matches) {
+        report_error(e.as_ref(), matches.get_flag("json-errors"));
+        std::process::exit(exit_code(e.as_ref()));
+    }
+}
+
+fn run(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let audio_format = match matches.get_one::<String>("format").unwrap().as_str() {
+        "vorbis" => AudioFormat::Vorbis,
+        "flac" => AudioFormat::Flac,
+        "mp3" => AudioFormat::Mp3,
+        "wav" => AudioFormat::Wav,
+        _ => AudioFormat::Vorbis,
+    };
+
+    let table_mode = match matches.get_one::<String>("tables").unwrap().as_str() {
+        "skip" => TableMode::Skip,
+        "keep" => TableMode::Keep,
+        _ => TableMode::Linearize,
+    };
+
+    let image_alt = match matches.get_one::<String>("images").unwrap().as_str() {
+        "alt" => ImageMode::Alt,
+        "caption" => ImageMode::Caption,
+        _ => ImageMode::Skip,
+    };
+
+    let ruby_mode = match matches.get_one::<String>("ruby").unwrap().as_str() {
+        "reading" => RubyMode::Reading,
+        _ => RubyMode::Base,
+    };
+
+    let subtitles = match matches.get_one::<String>("subtitles").map(String::as_str) {
+        Some("srt") => Some(SubtitleFormat::Srt),
+        Some("vtt") => Some(SubtitleFormat::Vtt),
+        _ => None,
+    };
+
+    let tag_preset = match matches.get_one::<String>("tag-preset").unwrap().as_str() {
+        "audiobook" => TagPreset::Audiobook,
+        _ => TagPreset::None,
+    };
+
+    let playlist_format = match matches.get_one::<String>("playlist-format").unwrap().as_str() {
+        "pls" => PlaylistFormat::Pls,
+        "xspf" => PlaylistFormat::Xspf,
+        _ => PlaylistFormat::M3u,
+    };
+
+    let filename_template = matches.get_one::<String>("name-template").unwrap().clone();
+
+    let output_policy = if matches.get_flag("overwrite") {
+        OutputPolicy::Overwrite
+    } else if matches.get_flag("resume") {
+        OutputPolicy::Resume
+    } else {
+        OutputPolicy::Refuse
+    };
+
+    let cli_config = Config {
+        output_format: audio_format,
+        quality: *matches.get_one::<f32>("quality").unwrap(),
+        bitrate_kbps: matches.get_one::<u32>("bitrate").copied(),
+        engine: matches.get_one::<String>("engine").unwrap().clone(),
+        espeak_data_path: matches.get_one::<String>("espeak-data").map(PathBuf::from),
+        voice: matches.get_one::<String>("voice").unwrap().clone(),
+        dialogue_voice: matches.get_one::<String>("dialogue-voice").cloned(),
+        voice_speed: *matches.get_one::<f32>("speed").unwrap(),
+        channels: *matches.get_one::<u16>("channels").unwrap(),
+        bits_per_sample: matches.get_one::<String>("bit-depth").unwrap().parse()?,
+        amplitude: *matches.get_one::<u32>("amplitude").unwrap(),
+        max_workers: *matches.get_one::<usize>("workers").unwrap(),
+        oversubscribe: matches.get_flag("oversubscribe"),
+        chunk_timeout_secs: matches.get_one::<u64>("chunk-timeout").copied(),
+        preprocessing_aggressive: !matches.get_flag("no-aggressive"),
+        cache_enabled: !matches.get_flag("no-cache"),
+        cache_dir: matches.get_one::<String>("cache-dir").map(PathBuf::from),
+        tmp_dir: matches.get_one::<String>("tmp-dir").map(PathBuf::from),
+        include_nonlinear: matches.get_flag("include-nonlinear"),
+        include_hrefs: matches.get_many::<String>("include-href").unwrap_or_default().cloned().collect(),
+        exclude_hrefs: matches.get_many::<String>("exclude-href").unwrap_or_default().cloned().collect(),
+        announce_titles: !matches.get_flag("no-announce-titles"),
+        merge_untitled_fragments: matches.get_flag("merge-untitled"),
+        split_heading_level: matches.get_one::<u8>("split-heading").copied(),
+        split_on_pagebreaks: matches.get_flag("split-pagebreaks"),
+        text_chapter_regex: matches.get_one::<String>("split-regex").cloned(),
+        strip_running_headers: matches.get_flag("strip-running-headers"),
+        dash_to_pause: matches.get_flag("dash-pauses"),
+        pause_scale: *matches.get_one::<f32>("pause-scale").unwrap(),
+        normalize_caps: matches.get_flag("normalize-caps"),
+        mark_blockquotes: matches.get_flag("mark-blockquotes"),
+        announce_skipped_images: matches.get_flag("announce-images"),
+        clean_chapter_titles: matches.get_flag("clean-titles"),
+        fade_ms: *matches.get_one::<u32>("fade-ms").unwrap(),
+        hash_word: matches.get_one::<String>("hash-word").unwrap().clone(),
+        trim_chunk_silence: matches.get_flag("trim-silence"),
+        output_policy,
+        table_mode,
+        image_alt,
+        ruby_mode,
+        tag_preset,
+        book_title: matches.get_one::<String>("title").cloned(),
+        book_author: matches.get_one::<String>("author").cloned(),
+        book_series: matches.get_one::<String>("series").cloned(),
+        playlist_format,
+        merge_output: matches.get_flag("merge"),
+        join_chunks: matches.get_flag("join-chunks"),
+        chapters_sidecar: matches.get_flag("chapters-sidecar"),
+        replaygain: matches.get_flag("replaygain"),
+        split_duration_secs: matches.get_one::<u64>("split-duration").copied(),
+        emit_transcripts: matches.get_flag("transcripts"),
+        audit: matches.get_flag("audit"),
+        subtitles,
+        keep_wav: matches.get_flag("keep-wav"),
+        low_memory: matches.get_flag("low-memory"),
+        chapter_order: matches.get_many::<usize>("order").unwrap_or_default().copied().collect(),
+        intro_text: read_text_arg(matches, "intro")?,
+        outro_text: read_text_arg(matches, "outro")?,
+        only_chapters: matches.get_one::<Vec<usize>>("chapters").cloned().unwrap_or_default(),
+        filename_template,
+        chapter_number_base: matches.get_one::<String>("chapter-base").unwrap().parse()?,
+        ..Default::default()
+    };
+
+    let mut config = match find_config_file(matches)? {
+        Some(path) => {
+            info!("⚙️  Loading settings from {}", path.display());
+            merge_cli_config(load_config_file(&path)?, cli_config, matches)
+        }
+        None => cli_config,
+    };
+
+    if matches.get_flag("check-config") {
+        return check_config(&config);
+    }
+    validate_filename_template(&config.filename_template)?;
+
+    if let Some(book) = matches.get_one::<String>("clear-cache") {
+        let book = Some(book.as_str()).filter(|book| !book.is_empty());
+        let removed = clear_cache(&config.cache_dir.clone().unwrap_or_else(default_cache_dir), book)?;
+        info!("🧹 Removed {} cached entries", removed);
+        return Ok(());
+    }
+
+    // Every temp file and directory of the run lands in one scratch directory,
+    // removed when main returns
+    let scratch_dir = tempfile::Builder::new()
+        .prefix("ebuptts-")
+        .tempdir_in(config.tmp_dir.clone().unwrap_or_else(std::env::temp_dir))?;
+    let _ = tempfile::env::override_temp_dir(scratch_dir.path());
+    debug!("Scratch files go to {}", scratch_dir.path().display());
+
+    install_interrupt_handler()?;
+
+    // Workers beyond the core count only add context switching, and each
+    // chapter already runs its encoder beside the synthesizer
+    let cores = num_cpus::get();
+    if config.max_workers > cores {
+        if config.oversubscribe {
+            warn!("⚠️  {} workers on {} CPU cores; expect no speedup beyond {}", config.max_workers, cores, cores);
+        } else {
+            warn!(
+                "⚠️  {} workers requested but only {} CPU cores; using {} (--oversubscribe keeps {})",
+                config.max_workers, cores, cores, config.max_workers
+            );
+            config.max_workers = cores;
+        }
+    }
+
+    // Configure Rayon thread pool
+    rayon::ThreadPoolBuilder::new()
+    .num_threads(config.max_workers)
+    .build_global()?;
+
+    if matches.get_flag("list-voices") {
+        return print_voices(&config);
+    }
+
+    if matches.get_flag("selftest") {
+        return run_selftest(config);
+    }
+
+    if matches.get_flag("benchmark") {
+        return run_benchmark(config);
+    }
+
+    let options = RunOptions {
+        overrides: matches.get_one::<String>("overrides").map(PathBuf::from),
+        dry_run: matches.get_flag("dry-run"),
+        estimate_only: matches.get_flag("estimate-only"),
+        stream_chapter: matches.get_one::<usize>("chapter").copied(),
+        resource: matches.get_one::<String>("resource").cloned(),
+        show_cleaning: matches.get_flag("show-cleaning"),
+        podcast_feed: matches.get_flag("podcast-feed"),
+        feed_base_url: matches.get_one::<String>("feed-base-url").cloned(),
+        progress: if matches.get_flag("no-progress") {
+            ProgressMode::None
+        } else {
+            ProgressMode::from_arg(matches.get_one::<String>("progress").unwrap())
+        },
+        batch_progress: None,
+    };
+    let output_dir = matches.get_one::<String>("output").map(Path::new);
+
+    // Several -i values or a directory of EPUBs convert one book after another
+    let inputs: Vec<&String> = matches.get_many::<String>("input").unwrap().collect();
+    if options.stream_chapter.is_some() && (inputs.len() > 1 || Path::new(inputs[0]).is_dir()) {
+        return Err("--stdout converts a chapter of a single EPUB".into());
+    }
+    if options.resource.is_some() && (inputs.len() > 1 || Path::new(inputs[0]).is_dir()) {
+        return Err("--resource reads a file of a single EPUB".into());
+    }
+    if inputs.len() == 1 && !Path::new(inputs[0]).is_dir() {
+        let input_path = Path::new(inputs[0]);
+        validate_epub(input_path)?;
+        return convert_book(input_path, output_dir, &config, &options);
+    }
+
+    let books = collect_epubs(&inputs)?;
+    if config.book_title.is_some() && books.len() > 1 {
+        return Err("--title names a single book and cannot be used when converting several".into());
+    }
+    run_batch(&books, output_dir, &config, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::{prop, prop_assert_eq, proptest};
+
+
+    fn text_processor(config: Config) -> TextProcessor {
+        TextProcessor::new(&config)
+    }
+
+    // One content document of a fixture book
+    struct FixtureDoc {
+        heading: String,
+        xhtml: Vec<u8>,
+        linear: bool,
+        // Manifest href, as written in the OPF; "ch{n}.xhtml" when unset
+        href: Option<&'static str>,
+    }
+
+    fn chapter(heading: &str, body: &str) -> FixtureDoc {
+        FixtureDoc {
+            heading: heading.to_string(),
+            xhtml: format!(
+                r#"<?xml version="1.0" encoding="utf-8"?>
+<html xmlns="http://www.w3.org/1999/xhtml"><head><title>{0}</title></head>
+<body><h1>{0}</h1><p>{1}</p></body></html>"#,
+                heading, body
+            )
+            .into_bytes(),
+            linear: true,
+            href: None,
+        }
+    }
+
+    // A minimal EPUB 2 book: one spine item per document, in order, with an NCX
+    // table of contents
+    fn build_epub(dir: &Path, title: &str, docs: &[FixtureDoc]) -> PathBuf {
+        use zip::write::{SimpleFileOptions, ZipWriter};
+
+        let path = dir.join("book.epub");
+        let mut zip = ZipWriter::new(File::create(&path).unwrap());
+        let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        let mut add = |name: &str, content: &[u8]| {
+            zip.start_file(name, options).unwrap();
+            zip.write_all(content).unwrap();
+        };
+
+        // The mimetype has to come first, uncompressed
+        add("mimetype", b"application/epub+zip");
+        add(
+            "META-INF/container.xml",
+            br#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles>
+</container>"#,
+        );
+
+        let mut manifest = String::new();
+        let mut spine = String::new();
+        let mut nav_points = String::new();
+        for (index, doc) in docs.iter().enumerate() {
+            let id = format!("ch{}", index + 1);
+            let href = doc.href.map_or_else(|| format!("{}.xhtml", id), str::to_string);
+            manifest.push_str(&format!(
+                r#"<item id="{}" href="{}" media-type="application/xhtml+xml"/>"#,
+                id, href
+            ));
+            let linear = if doc.linear { "" } else { r#" linear="no""# };
+            spine.push_str(&format!(r#"<itemref idref="{}"{}/>"#, id, linear));
+            nav_points.push_str(&format!(
+                r#"<navPoint id="nav{}" playOrder="{}"><navLabel><text>{}</text></navLabel><content src="{}"/></navPoint>"#,
+                id,
+                index + 1,
+                doc.heading,
+                href
+            ));
+            add(&format!("OEBPS/{}", percent_decode(&href)), &doc.xhtml);
+        }
+        add(
+            "OEBPS/content.opf",
+            format!(
+                r#"<?xml version="1.0" encoding="utf-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="2.0" unique-identifier="id">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:title>{}</dc:title><dc:creator>Test Author</dc:creator>
+    <dc:identifier id="id">test-book</dc:identifier><dc:language>en</dc:language>
+  </metadata>
+  <manifest><item id="ncx" href="toc.ncx" media-type="application/x-dtbncx+xml"/>{}</manifest>
+  <spine toc="ncx">{}</spine>
+</package>"#,
+                title, manifest, spine
+            )
+            .as_bytes(),
+        );
+        add(
+            "OEBPS/toc.ncx",
+            format!(
+                r#"<?xml version="1.0" encoding="utf-8"?>
+<ncx xmlns="http://www.daisy.org/z3986/2005/ncx/" version="2005-1">
+  <head><meta name="dtb:uid" content="test-book"/></head>
+  <docTitle><text>{}</text></docTitle>
+  <navMap>{}</navMap>
+</ncx>"#,
+                title, nav_points
+            )
+            .as_bytes(),
+        );
+        zip.finish().unwrap();
+        path
+    }
+
+    fn sample_epub(dir: &Path) -> PathBuf {
+        build_epub(
+            dir,
+            "Test Book",
+            &[
+                chapter("The Start", "It was a dark and stormy night. The rain fell."),
+                chapter("The Middle", "Things happened, as things do."),
+                chapter("The End", "And that was that."),
+            ],
+        )
+    }
+
+    // Runs everything with the silent mock engine, so no TTS install is needed
+    fn mock_config() -> Config {
+        Config {
+            engine: "mock".to_string(),
+            output_format: AudioFormat::Wav,
+            cache_enabled: false,
+            ..Config::default()
+        }
+    }
+
+    fn cached_engine(cache_dir: &Path) -> TTSEngine {
+        TTSEngine::new(Config {
+            cache_enabled: true,
+            cache_dir: Some(cache_dir.to_path_buf()),
+            ..mock_config()
+        })
+        .unwrap()
+    }
+
+    fn run_options() -> RunOptions {
+        RunOptions {
+            overrides: None,
+            dry_run: false,
+            estimate_only: false,
+            stream_chapter: None,
+            resource: None,
+            show_cleaning: false,
+            podcast_feed: false,
+            feed_base_url: None,
+            progress: ProgressMode::None,
+            batch_progress: None,
+        }
+    }
+
+    // Converts the book into a fresh directory and lists what ended up there
+    fn convert(epub: &Path, config: &Config) -> (tempfile::TempDir, Vec<String>) {
+        convert_with(epub, config, &run_options())
+    }
+
+    fn convert_with(epub: &Path, config: &Config, options: &RunOptions) -> (tempfile::TempDir, Vec<String>) {
+        let output = tempfile::tempdir().unwrap();
+        convert_book(epub, Some(output.path()), config, options).unwrap();
+        let mut files: Vec<String> = fs::read_dir(output.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        (output, files)
+    }
+
+    #[test]
+    fn non_linear_spine_items_are_skipped_unless_asked_for() {
+        let dir = tempfile::tempdir().unwrap();
+        let note = FixtureDoc {
+            linear: false,
+            ..chapter("Footnote", "A pop-up note.")
+        };
+        let epub = build_epub(
+            dir.path(),
+            "Test Book",
+            &[chapter("One", "First words."), note, chapter("Two", "Second words.")],
+        );
+        let titles = |config: Config| -> Vec<String> {
+            let processor = EpubProcessor::new(config).unwrap();
+            processor.extract_chapters(&epub).unwrap().into_iter().map(|chapter| chapter.title).collect()
+        };
+
+        assert_eq!(titles(mock_config()), ["One", "Two"]);
+        let config = Config {
+            include_nonlinear: true,
+            ..mock_config()
+        };
+        assert_eq!(titles(config), ["One", "Footnote", "Two"]);
+    }
+
+    #[test]
+    fn a_run_without_sentence_ends_is_split_under_the_limit() {
+        let processor = text_processor(Config::default());
+        let text = "lorem ipsum dolor sit amet ".repeat(200);
+        assert!(text.len() >= 5000);
+
+        let chunks = processor.split_into_chunks(&text, 0, 300);
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 300), "{:?}", chunks);
+        assert_eq!(chunks.join(" "), text.trim());
+    }
+
+    #[test]
+    fn long_paragraphs_come_through_unwrapped() {
+        let dir = tempfile::tempdir().unwrap();
+        let paragraph = "This paragraph runs well past eighty columns, so a hard wrap would break it \
+                         across lines somewhere in the middle of a sentence";
+        let epub = build_epub(dir.path(), "Test Book", &[chapter("One", paragraph)]);
+        let processor = EpubProcessor::new(mock_config()).unwrap();
+
+        let chapters = processor.extract_chapters(&epub).unwrap();
+        assert!(paragraph.len() > 80);
+        assert!(
+            chapters[0].content.lines().any(|line| line.contains(paragraph)),
+            "{:?}",
+            chapters[0].content
+        );
+    }
+
+    #[test]
+    fn pruning_one_book_keeps_entries_another_book_uses() {
+        let mut index = CacheIndex::default();
+        index.add("only-a", Some("book-a"));
+        index.add("shared", Some("book-a"));
+        index.add("shared", Some("book-b"));
+        index.add("only-b", Some("book-b"));
+
+        assert_eq!(index.prune(Some("book-a")), ["only-a"]);
+        let mut kept: Vec<&String> = index.entries.keys().collect();
+        kept.sort();
+        assert_eq!(kept, ["only-b", "shared"]);
+        assert!(index.entries["shared"].books.iter().eq(["book-b"]));
+
+        // The index survives a save and reload, and a full prune releases the rest
+        let dir = tempfile::tempdir().unwrap();
+        index.save(dir.path()).unwrap();
+        let mut reloaded = CacheIndex::load(dir.path());
+        let mut released = reloaded.prune(None);
+        released.sort();
+        assert_eq!(released, ["only-b", "shared"]);
+        assert!(reloaded.entries.is_empty());
+    }
+
+    #[test]
+    fn filename_templates_reject_unknown_placeholders() {
+        assert!(validate_filename_template("{author} - {book_title} - {chapter_order} {chapter_title}.{ext}").is_ok());
+        let error = validate_filename_template("{chunk}_{title}.{ext}").unwrap_err().to_string();
+        assert!(error.contains("{title}"), "{}", error);
+    }
+
+    #[test]
+    fn filename_templates_sanitize_each_value() {
+        let name = render_filename_template(
+            "{author} - {chapter_title}.{ext}",
+            &[("author", "A/B".to_string()), ("chapter_title", "Why?".to_string()), ("ext", "ogg".to_string())],
+        );
+        assert_eq!(name, "A_B - Why_.ogg");
+    }
+
+    #[test]
+    fn conversion_names_files_from_the_template() {
+        let dir = tempfile::tempdir().unwrap();
+        let epub = build_epub(dir.path(), "Test Book", &[chapter("Only", "Just one chapter.")]);
+        let config = Config {
+            filename_template: "{author} - {book_title} - {chunk}.{ext}".to_string(),
+            ..mock_config()
+        };
+        let (output, files) = convert(&epub, &config);
+
+        let chapter_dir = files.iter().find(|name| output.path().join(name).is_dir()).unwrap();
+        let names: Vec<String> = fs::read_dir(output.path().join(chapter_dir))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        assert!(names.contains(&"Test Author - Test Book - 000.wav".to_string()), "{:?}", names);
+    }
+
+    #[test]
+    fn a_template_without_the_chunk_fails_once_a_chapter_has_two_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let epub = build_epub(
+            dir.path(),
+            "Test Book",
+            &[chapter("Long", "The first sentence is here. The second sentence follows it.")],
+        );
+        let config = Config {
+            filename_template: "{chapter_title}.{ext}".to_string(),
+            min_chunk_size: 0,
+            max_chunk_size: 30,
+            ..mock_config()
+        };
+        let output = tempfile::tempdir().unwrap();
+        let error = convert_book(&epub, Some(output.path()), &config, &run_options()).unwrap_err();
+        assert!(error.to_string().contains("add {chunk}"), "{}", error);
+    }
+
+    #[test]
+    fn latin1_chapters_decode_from_their_declared_charset() {
+        let dir = tempfile::tempdir().unwrap();
+        let latin1 = FixtureDoc {
+            heading: "Cafe".to_string(),
+            xhtml: b"<?xml version=\"1.0\" encoding=\"iso-8859-1\"?>\n\
+                <html xmlns=\"http://www.w3.org/1999/xhtml\"><head><title>Caf\xe9</title></head>\n\
+                <body><h1>Caf\xe9</h1><p>A na\xefve r\xe9sum\xe9 over cr\xe8me br\xfbl\xe9e.</p></body></html>"
+                .to_vec(),
+            linear: true,
+            href: None,
+        };
+        let epub = build_epub(dir.path(), "Test Book", &[latin1, chapter("Two", "Plain words.")]);
+        let processor = EpubProcessor::new(mock_config()).unwrap();
+
+        let chapters = processor.extract_chapters(&epub).unwrap();
+        assert_eq!(chapters[0].title, "Caf\u{e9}");
+        assert!(chapters[0].content.contains("na\u{ef}ve r\u{e9}sum\u{e9} over cr\u{e8}me br\u{fb}l\u{e9}e"));
+        assert!(!chapters[0].content.contains('\u{fffd}'));
+    }
+
+    #[test]
+    fn meta_charset_is_honored_for_html_resources() {
+        let html = b"<html><head><meta charset=\"windows-1252\"></head><body>\x93Quoted\x94</body></html>";
+        let text = decode_resource(html, Path::new("ch1.html"));
+        assert!(text.contains("\u{201c}Quoted\u{201d}"), "{}", text);
+    }
+
+    #[test]
+    fn chapter_overrides_change_only_their_chapter() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("overrides.json");
+        fs::write(&path, r#"{"1": {"voice_speed": 2.0}}"#).unwrap();
+        let overrides = ChapterOverrides::load(&path).unwrap();
+        let global = Config {
+            voice_speed: 1.5,
+            ..mock_config()
+        };
+
+        assert_eq!(overrides.apply(&global, 0).voice_speed, 1.5);
+        let second = overrides.apply(&global, 1);
+        assert_eq!(second.voice_speed, 2.0);
+        assert_eq!(second.voice, global.voice);
+        assert_eq!(second.voice_pitch, global.voice_pitch);
+    }
+
+    // Seconds of WAV audio in each chapter folder, in chapter order
+    fn chapter_durations(output: &Path) -> Vec<f64> {
+        let mut dirs: Vec<PathBuf> = fs::read_dir(output)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.is_dir())
+            .collect();
+        dirs.sort();
+        dirs.iter()
+            .map(|dir| {
+                fs::read_dir(dir)
+                    .unwrap()
+                    .map(|entry| entry.unwrap().path())
+                    .filter(|path| path.extension().is_some_and(|ext| ext == "wav"))
+                    .map(|path| wav_duration_secs(&path).unwrap())
+                    .sum()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_chapter_override_changes_the_speed_of_that_chapter_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let text = "The same words are read in both chapters, at whatever speed is asked for.";
+        let epub = build_epub(dir.path(), "Test Book", &[chapter("Same", text), chapter("Same", text)]);
+        let overrides = dir.path().join("overrides.json");
+        fs::write(&overrides, r#"{"1": {"voice_speed": 2.0}}"#).unwrap();
+
+        let options = RunOptions {
+            overrides: Some(overrides),
+            ..run_options()
+        };
+        let (output, _) = convert_with(&epub, &mock_config(), &options);
+        let durations = chapter_durations(output.path());
+        assert_eq!(durations.len(), 2);
+        assert!((durations[1] / durations[0] - 0.5).abs() < 0.05, "{:?}", durations);
+    }
+
+    #[test]
+    fn unicode_quotes_dashes_and_ellipses_are_normalized() {
+        let processor = text_processor(Config::default());
+        let cleaned = processor.clean_text(
+            "\u{ab}Bonjour\u{bb} and \u{2039}salut\u{203a}, a well\u{2011}known fact \u{2015} or not\u{2026}. Wait\u{2026}\u{2026}",
+            false,
+        );
+        assert_eq!(cleaned, "\"Bonjour\" and 'salut', a well-known fact - or not... Wait...");
+    }
+
+    #[test]
+    fn the_chapter_title_is_announced_before_the_body() {
+        let dir = tempfile::tempdir().unwrap();
+        let epub = build_epub(
+            dir.path(),
+            "Test Book",
+            &[chapter("The Start", "It began."), chapter("Chapter 2", "It went on."), chapter("The End", "And that was that.")],
+        );
+        let processor = EpubProcessor::new(mock_config()).unwrap();
+        let chapters = processor.extract_chapters(&epub).unwrap();
+
+        let config = mock_config();
+        let announce = |chapter: &Chapter| chapter.title_announcement(config.spoken_chapter_number(chapter.order));
+        assert_eq!(announce(&chapters[0]), "Chapter 1. The Start.");
+        assert_eq!(announce(&chapters[2]), "Chapter 3. The End.");
+        // A title that is only the number is not read twice
+        assert_eq!(announce(&chapters[1]), "Chapter 2.");
+    }
+
+    #[test]
+    fn numbered_titles_are_announced_alone() {
+        let chapter = |title: &str| Chapter {
+            title: title.to_string(),
+            content: String::new(),
+            order: 0,
+            source_order: 0,
+            word_count: 0,
+            bookend: false,
+        };
+        assert_eq!(chapter("Chapter 1: The Beginning").title_announcement(1), "Chapter 1: The Beginning.");
+        assert_eq!(chapter("Chapter Three").title_announcement(3), "Chapter Three.");
+        assert_eq!(chapter("CHAPTER IV").title_announcement(4), "CHAPTER IV.");
+        assert_eq!(chapter("Chapter Twenty-One").title_announcement(21), "Chapter Twenty-One.");
+        // A word that only starts like a number is part of the title
+        assert_eq!(chapter("Chapters Tenfold").title_announcement(2), "Chapter 2. Chapters Tenfold.");
+        assert_eq!(chapter("Chapter Tenacity").title_announcement(2), "Chapter 2. Chapter Tenacity.");
+    }
+
+    #[test]
+    fn an_intro_does_not_shift_the_spoken_chapter_numbers() {
+        let dir = tempfile::tempdir().unwrap();
+        let epub = build_epub(dir.path(), "Test Book", &[chapter("The Start", "It began.")]);
+        let config = Config {
+            intro_text: Some("This is {title}.".to_string()),
+            chapter_number_base: 1,
+            ..mock_config()
+        };
+        let processor = EpubProcessor::new(config.clone()).unwrap();
+        let chapters = processor.extract_chapters(&epub).unwrap();
+
+        assert!(chapters[0].bookend);
+        assert_eq!(chapters[1].order, 1);
+        let spoken = chapters[1].title_announcement(config.spoken_chapter_number(chapters[1].order));
+        assert_eq!(spoken, "Chapter 1. The Start.");
+    }
+
+    #[test]
+    fn untitled_fragments_merge_into_the_previous_chapter() {
+        let dir = tempfile::tempdir().unwrap();
+        let fragment = FixtureDoc {
+            xhtml: br#"<?xml version="1.0" encoding="utf-8"?>
+<html xmlns="http://www.w3.org/1999/xhtml"><head><title>Part</title></head>
+<body><p>More of the first.</p></body></html>"#
+                .to_vec(),
+            ..chapter("", "")
+        };
+        let epub = build_epub(
+            dir.path(),
+            "Test Book",
+            &[chapter("One", "First words."), fragment, chapter("Two", "Second words.")],
+        );
+        let chapters = |config: Config| {
+            EpubProcessor::new(config).unwrap().extract_chapters(&epub).unwrap()
+        };
+
+        let split = chapters(mock_config());
+        let titles: Vec<&str> = split.iter().map(|chapter| chapter.title.as_str()).collect();
+        assert_eq!(titles, ["One", "Chapter 2", "Two"]);
+
+        let merged = chapters(Config {
+            merge_untitled_fragments: true,
+            ..mock_config()
+        });
+        let titles: Vec<&str> = merged.iter().map(|chapter| chapter.title.as_str()).collect();
+        assert_eq!(titles, ["One", "Two"]);
+        assert!(merged[0].content.contains("More of the first."), "{}", merged[0].content);
+        assert_eq!(merged[0].word_count, split[0].word_count + split[1].word_count);
+    }
+
+    // Cleaning throughput with the regexes compiled once, against compiling them
+    // for every chunk as the methods used to. Run with
+    // `cargo test --release -- --ignored --nocapture cleaning_throughput`.
+    #[test]
+    #[ignore]
+    fn cleaning_throughput() {
+        let paragraph = "Mr. Smith walked in\u{2014}late, as always\u{2014}and said \u{201c}Hello!\u{201d} \
+                         The inter-\nnational deal (worth $1,250.50) closed in 1914-1918, e.g. yesterday. ";
+        let chunks: Vec<String> = (0..2000).map(|i| format!("{} {}", paragraph, i)).collect();
+        let total: usize = chunks.iter().map(String::len).sum();
+        let config = Config::default();
+
+        let start = Instant::now();
+        let processor = text_processor(config.clone());
+        for chunk in &chunks {
+            std::hint::black_box(processor.clean_text(chunk, true));
+        }
+        let compiled_once = start.elapsed();
+
+        let start = Instant::now();
+        for chunk in &chunks {
+            std::hint::black_box(text_processor(config.clone()).clean_text(chunk, true));
+        }
+        let compiled_per_chunk = start.elapsed();
+
+        let rate = |elapsed: Duration| total as f64 / elapsed.as_secs_f64() / 1e6;
+        println!(
+            "{} chunks, {} chars: compiled once {:.2?} ({:.2} MB/s), per chunk {:.2?} ({:.2} MB/s)",
+            chunks.len(),
+            total,
+            compiled_once,
+            rate(compiled_once),
+            compiled_per_chunk,
+            rate(compiled_per_chunk)
+        );
+        assert!(compiled_once < compiled_per_chunk);
+    }
+
+    #[test]
+    fn the_aggressive_cleanup_toggle_changes_the_text() {
+        // The GUI unticks the box by passing --no-aggressive
+        let parse = |extra: &[&str]| {
+            let args = ["epub_audiobook_converter", "-i", "book.epub", "-o", "out"].iter().chain(extra);
+            cli().try_get_matches_from(args).unwrap()
+        };
+        assert!(parse(&["--no-aggressive"]).get_flag("no-aggressive"));
+        assert!(!parse(&[]).get_flag("no-aggressive"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let epub = build_epub(dir.path(), "Test Book", &[chapter("One", "It ended.Then it began.")]);
+        let content = |preprocessing_aggressive: bool| {
+            let processor = EpubProcessor::new(Config {
+                preprocessing_aggressive,
+                ..mock_config()
+            })
+            .unwrap();
+            processor.extract_chapters(&epub).unwrap().remove(0).content
+        };
+        let aggressive = content(true);
+        let gentle = content(false);
+        assert!(aggressive.contains("It ended. Then it began."), "{}", aggressive);
+        assert!(gentle.contains("It ended.Then it began."), "{}", gentle);
+    }
+
+    #[test]
+    fn merged_chapters_start_where_the_previous_ones_end() {
+        let output = |order: usize, title: &str, durations: &[f64]| ChapterOutput {
+            order,
+            title: title.to_string(),
+            files: durations
+                .iter()
+                .enumerate()
+                .map(|(index, &duration_secs)| ChunkOutput {
+                    path: PathBuf::from(format!("{}_{}.wav", order, index)),
+                    duration_secs,
+                })
+                .collect(),
+            complete: true,
+        };
+        let outputs = [
+            output(0, "One", &[60.5, 30.25]),
+            output(1, "Two", &[3600.0]),
+            output(2, "Three", &[1.0]),
+        ];
+
+        let marks = chapter_marks(&outputs);
+        assert_eq!(marks, [("One".to_string(), 0.0), ("Two".to_string(), 90.75), ("Three".to_string(), 3690.75)]);
+
+        let comments = vorbis_chapter_comments(&marks);
+        assert_eq!(
+            comments,
+            [
+                "CHAPTER001=00:00:00.000",
+                "CHAPTER001NAME=One",
+                "CHAPTER002=00:01:30.750",
+                "CHAPTER002NAME=Two",
+                "CHAPTER003=01:01:30.750",
+                "CHAPTER003NAME=Three",
+            ]
+        );
+
+        // Reading the timestamps back gives the chapter starts
+        let starts: Vec<f64> = comments
+            .iter()
+            .filter(|comment| !comment.contains("NAME="))
+            .map(|comment| {
+                let (_, timestamp) = comment.split_once('=').unwrap();
+                timestamp
+                    .split(':')
+                    .map(|part| part.parse::<f64>().unwrap())
+                    .fold(0.0, |secs, part| secs * 60.0 + part)
+            })
+            .collect();
+        assert_eq!(starts, [0.0, 90.75, 3690.75]);
+    }
+
+    #[test]
+    fn one_file_splits_into_a_chapter_per_heading() {
+        let dir = tempfile::tempdir().unwrap();
+        let book = FixtureDoc {
+            heading: "Everything".to_string(),
+            xhtml: br#"<?xml version="1.0" encoding="utf-8"?>
+<html xmlns="http://www.w3.org/1999/xhtml"><head><title>Everything</title></head><body>
+<h2>First</h2><p>Alpha words.</p><p>More alpha.</p>
+<h2>Second</h2><p>Beta words.</p><h3>An inner heading</h3><p>Still beta.</p>
+<h2>Third</h2><p>Gamma words.</p>
+</body></html>"#
+                .to_vec(),
+            linear: true,
+            href: None,
+        };
+        let epub = build_epub(dir.path(), "Test Book", &[book]);
+        let processor = EpubProcessor::new(Config {
+            split_heading_level: Some(2),
+            ..mock_config()
+        })
+        .unwrap();
+
+        let chapters = processor.extract_chapters(&epub).unwrap();
+        let titles: Vec<&str> = chapters.iter().map(|chapter| chapter.title.as_str()).collect();
+        assert_eq!(titles, ["First", "Second", "Third"]);
+        assert!(chapters[0].content.contains("More alpha"));
+        assert!(chapters[1].content.contains("Still beta"));
+        assert!(!chapters[1].content.contains("Gamma"));
+    }
+
+    #[test]
+    fn percent_encoded_spine_hrefs_are_found() {
+        let dir = tempfile::tempdir().unwrap();
+        let spaced = FixtureDoc {
+            href: Some("chapter%201.xhtml"),
+            ..chapter("Spaced Out", "Found despite the space in its name.")
+        };
+        let epub = build_epub(dir.path(), "Test Book", &[chapter("One", "First words."), spaced]);
+        let processor = EpubProcessor::new(mock_config()).unwrap();
+
+        let chapters = processor.extract_chapters(&epub).unwrap();
+        let titles: Vec<&str> = chapters.iter().map(|chapter| chapter.title.as_str()).collect();
+        assert_eq!(titles, ["One", "Spaced Out"]);
+        assert!(chapters[1].content.contains("Found despite the space"));
+    }
+
+    #[test]
+    fn dashes_and_parentheticals_become_pauses() {
+        let processor = text_processor(Config {
+            dash_to_pause: true,
+            ..Config::default()
+        });
+        assert_eq!(
+            processor.clean_text("She opened the door\u{2014}and froze.", false),
+            "She opened the door, and froze."
+        );
+        assert_eq!(
+            processor.clean_text("The house (which was old) creaked.", false),
+            "The house, which was old, creaked."
+        );
+        // A pause never doubles up with the punctuation it runs into
+        assert_eq!(processor.clean_text("He left\u{2014}.", false), "He left.");
+
+        let plain = text_processor(Config::default());
+        assert_eq!(
+            plain.clean_text("She opened the door\u{2014}and froze.", false),
+            "She opened the door-and froze."
+        );
+    }
+
+    #[test]
+    fn synthetic_epub_reads_back_in_spine_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let epub = sample_epub(dir.path());
+        let processor = EpubProcessor::new(mock_config()).unwrap();
+
+        let metadata = processor.read_metadata(&epub).unwrap();
+        assert_eq!(metadata.title, "Test Book");
+        assert_eq!(metadata.author.as_deref(), Some("Test Author"));
+
+        let chapters = processor.extract_chapters(&epub).unwrap();
+        let titles: Vec<&str> = chapters.iter().map(|chapter| chapter.title.as_str()).collect();
+        assert_eq!(titles, ["The Start", "The Middle", "The End"]);
+        assert_eq!(chapters.iter().map(|chapter| chapter.order).collect::<Vec<_>>(), [0, 1, 2]);
+        assert!(chapters[0].content.contains("dark and stormy night"));
+    }
+
+    #[test]
+    fn titles_come_from_the_first_heading() {
+        let processor = EpubProcessor::new(mock_config()).unwrap();
+        assert_eq!(
+            processor.extract_title("<body><h2 class=\"ch\">Into the Woods</h2><h1>Later</h1></body>"),
+            Some("Into the Woods".to_string())
+        );
+        assert_eq!(processor.extract_title("<h3>Fish &amp; Chips</h3>"), Some("Fish & Chips".to_string()));
+        assert_eq!(processor.extract_title("<h4>Too deep</h4><p>Body only.</p>"), None);
+    }
+
+    #[test]
+    fn the_mock_engine_renders_silence_of_the_spoken_length() {
+        let config = mock_config();
+        let synthesizer = select_synthesizer(&config).unwrap().unwrap();
+        assert_eq!(synthesizer.name(), "mock");
+
+        let text = "one two three four five six seven eight nine ten";
+        let wav = synthesizer.synthesize(text, &config).unwrap().into_wav().unwrap();
+        let mut reader = hound::WavReader::new(std::io::Cursor::new(wav)).unwrap();
+        let expected = 10.0 * 60.0 / config.effective_wpm();
+        let duration = reader.duration() as f32 / reader.spec().sample_rate as f32;
+        assert!((duration - expected).abs() < 0.01, "{} vs {}", duration, expected);
+        assert!(reader.samples::<i32>().all(|sample| sample.unwrap() == 0));
+
+        // The engine on top of it encodes without any TTS installed
+        let output = tempfile::tempdir().unwrap();
+        let engine = TTSEngine::new(mock_config()).unwrap();
+        let path = output.path().join("chunk.wav");
+        let speech = engine.text_to_speech(text, &path).unwrap();
+        assert!(!speech.cache_hit);
+        assert!((speech.duration_secs - expected as f64).abs() < 0.01);
+    }
+
+    #[test]
+    fn concurrent_identical_chunks_share_one_intact_cache_file() {
+        let cache = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let engine = cached_engine(cache.path());
+        let text = "Every thread asks for exactly these words at the same moment.";
+
+        let durations: Vec<f64> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..16)
+                .map(|worker| {
+                    let engine = &engine;
+                    let path = output.path().join(format!("{}.wav", worker));
+                    scope.spawn(move || {
+                        engine.text_to_speech(text, &path).unwrap();
+                        wav_duration_secs(&path).unwrap()
+                    })
+                })
+                .collect();
+            workers.into_iter().map(|worker| worker.join().unwrap()).collect()
+        });
+        assert!(durations[0] > 0.0);
+        assert!(durations.iter().all(|duration| *duration == durations[0]), "{:?}", durations);
+
+        let cached: Vec<PathBuf> = fs::read_dir(cache.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "wav"))
+            .collect();
+        assert_eq!(cached.len(), 1, "{:?}", cached);
+        assert_eq!(wav_duration_secs(&cached[0]).unwrap(), durations[0]);
+    }
+
+    #[test]
+    fn mock_engine_converts_the_whole_book() {
+        let dir = tempfile::tempdir().unwrap();
+        let epub = sample_epub(dir.path());
+        let (output, files) = convert(&epub, &mock_config());
+
+        let chapter_dirs: Vec<&String> = files.iter().filter(|name| output.path().join(name).is_dir()).collect();
+        assert_eq!(chapter_dirs.len(), 3, "{:?}", files);
+        for chapter_dir in chapter_dirs {
+            let audio: Vec<PathBuf> = fs::read_dir(output.path().join(chapter_dir))
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "wav"))
+                .collect();
+            assert!(!audio.is_empty(), "no audio in {}", chapter_dir);
+            for path in audio {
+                assert!(wav_duration_secs(&path).unwrap() > 0.0, "{} is empty", path.display());
+            }
+        }
+    }
+
+    #[test]
+    fn a_batch_converts_every_book_and_reports_the_broken_ones() {
+        let library = tempfile::tempdir().unwrap();
+        for title in ["First Book", "Second Book"] {
+            let scratch = tempfile::tempdir().unwrap();
+            let epub = build_epub(scratch.path(), title, &[chapter("Only", "A few words.")]);
+            fs::copy(epub, library.path().join(format!("{}.epub", title))).unwrap();
+        }
+        fs::write(library.path().join("broken.epub"), b"not a zip").unwrap();
+        fs::write(library.path().join("notes.txt"), b"skipped").unwrap();
+
+        let input = library.path().to_string_lossy().into_owned();
+        let books = collect_epubs(&[&input]).unwrap();
+        assert_eq!(books.len(), 3, "{:?}", books);
+
+        let output = tempfile::tempdir().unwrap();
+        let error = run_batch(&books, Some(output.path()), &mock_config(), run_options()).unwrap_err();
+        assert_eq!(error.to_string(), "1 of 3 books failed");
+        let mut folders: Vec<String> = fs::read_dir(output.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        folders.sort();
+        assert_eq!(folders, [sanitize_filename("First Book"), sanitize_filename("Second Book")]);
+    }
+
+    #[test]
+    fn fades_attenuate_the_first_and_last_samples() {
+        let dir = tempfile::tempdir().unwrap();
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 1000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let input = dir.path().join("tone.wav");
+        let mut writer = hound::WavWriter::create(&input, spec).unwrap();
+        for _ in 0..1000 {
+            writer.write_sample(10_000i32).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let output = dir.path().join("faded.wav");
+        apply_fade(&input, &output, Fade { in_ms: 100, out_ms: 100 }).unwrap();
+        let samples: Vec<i32> = hound::WavReader::open(&output).unwrap().samples().map(Result::unwrap).collect();
+
+        assert_eq!(samples.len(), 1000);
+        assert_eq!(samples[0], 0);
+        assert_eq!(samples[50], 5_000);
+        assert_eq!(samples[500], 10_000);
+        assert_eq!(samples[950], 5_000);
+        assert!(samples[999] <= 100, "{}", samples[999]);
+    }
+
+    #[test]
+    fn symbols_are_spoken() {
+        let processor = text_processor(Config::default());
+        assert_eq!(processor.normalize_symbols("salt & pepper"), "salt and pepper");
+        assert_eq!(processor.normalize_symbols("me@example"), "me at example");
+        assert_eq!(processor.normalize_symbols("50% off"), "50 percent off");
+        assert_eq!(processor.normalize_symbols("Room #4"), "Room number 4");
+        let hashtags = text_processor(Config {
+            hash_word: "hashtag".to_string(),
+            ..Config::default()
+        });
+        assert_eq!(hashtags.normalize_symbols("#blessed"), "hashtag blessed");
+
+        assert_eq!(processor.normalize_symbols("$5"), "five dollars");
+        assert_eq!(processor.normalize_symbols("$1.01"), "one dollar and one cent");
+        assert_eq!(
+            processor.normalize_symbols("$1,250.50"),
+            "one thousand two hundred fifty dollars and fifty cents"
+        );
+        assert_eq!(processor.normalize_symbols("$3.00"), "three dollars");
+        assert_eq!(processor.normalize_symbols("pages 5-10"), "pages 5 to 10");
+        // Dates and phone numbers are longer runs and stay as they are
+        assert_eq!(processor.normalize_symbols("2024-05-01"), "2024-05-01");
+        // Ranges are spoken before the cleanup that drops them from plain text
+        assert_eq!(processor.clean_text("Read pages 5-10.", true), "Read pages 5 to 10.");
+    }
+
+    #[test]
+    fn engine_outputs_in_different_specs_merge_after_normalizing() {
+        let render = |spec: hound::WavSpec, secs: f32| {
+            let mut wav = Vec::new();
+            let mut writer = hound::WavWriter::new(std::io::Cursor::new(&mut wav), spec).unwrap();
+            let amplitude = (1i32 << (spec.bits_per_sample - 2)) as f32;
+            for frame in 0..(secs * spec.sample_rate as f32) as u32 {
+                let sample = (frame as f32 / 10.0).sin() * amplitude;
+                for _ in 0..spec.channels {
+                    writer.write_sample(sample as i32).unwrap();
+                }
+            }
+            writer.finalize().unwrap();
+            wav
+        };
+        let espeak = render(
+            hound::WavSpec {
+                channels: 1,
+                sample_rate: 22050,
+                bits_per_sample: 16,
+                sample_format: hound::SampleFormat::Int,
+            },
+            0.5,
+        );
+        let other = render(
+            hound::WavSpec {
+                channels: 2,
+                sample_rate: 44100,
+                bits_per_sample: 24,
+                sample_format: hound::SampleFormat::Int,
+            },
+            0.25,
+        );
+
+        let target = Config::default().wav_spec();
+        let mut merged = Vec::new();
+        let mut writer = hound::WavWriter::new(std::io::Cursor::new(&mut merged), target).unwrap();
+        for (wav, secs) in [(espeak, 0.5), (other, 0.25)] {
+            let canonical = to_canonical_wav(&wav, target).unwrap();
+            let mut reader = hound::WavReader::new(std::io::Cursor::new(canonical)).unwrap();
+            // Joining needs every piece in one spec, which is what normalizing buys
+            assert_eq!(reader.spec(), target);
+            let duration = reader.duration() as f32 / target.sample_rate as f32;
+            assert!((duration - secs).abs() < 0.01, "{} vs {}", duration, secs);
+            for sample in reader.samples::<i32>() {
+                writer.write_sample(sample.unwrap()).unwrap();
+            }
+        }
+        writer.finalize().unwrap();
+
+        let reader = hound::WavReader::new(std::io::Cursor::new(merged)).unwrap();
+        let duration = reader.duration() as f32 / target.sample_rate as f32;
+        assert!((duration - 0.75).abs() < 0.01, "{}", duration);
+    }
+
+    #[test]
+    fn invisible_characters_are_removed_before_cleaning() {
+        let text = "in\u{ad}vis\u{200b}ible\u{2060} text\u{a0}with\u{202f}odd\u{2009}spaces\u{feff}";
+        assert_eq!(normalize_invisibles(text), "invisible text with odd spaces");
+
+        let processor = text_processor(Config::default());
+        assert_eq!(
+            processor.clean_text("\u{feff}word\u{a0}\u{a0} joiner\u{200d}s .", false),
+            "word joiners."
+        );
+    }
+
+    #[test]
+    fn chapter_numbers_widen_with_the_chapter_count() {
+        let config = Config::default();
+        assert_eq!(config.chapter_number(7, 12), "007");
+        assert_eq!(config.chapter_number(0, 1500), "0000");
+        assert_eq!(config.chapter_number(1499, 1500), "1499");
+
+        let one_based = Config {
+            chapter_number_base: 1,
+            ..Config::default()
+        };
+        assert_eq!(one_based.chapter_number(0, 1500), "0001");
+        assert_eq!(one_based.chapter_number(1499, 1500), "1500");
+        assert_eq!(one_based.chapter_number(998, 999), "999");
+        assert_eq!(one_based.chapter_number(0, 1000), "0001");
+
+        // Equal widths keep name order and numeric order the same
+        let mut names: Vec<String> = (0..1500).rev().map(|order| config.chapter_number(order, 1500)).collect();
+        names.sort();
+        assert!(names.iter().enumerate().all(|(order, name)| name.parse::<usize>().unwrap() == order));
+    }
+
+    #[test]
+    fn stereo_input_is_downmixed_to_mono() {
+        let stereo = hound::WavSpec {
+            channels: 2,
+            sample_rate: 22050,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut wav = Vec::new();
+        {
+            let mut writer = hound::WavWriter::new(std::io::Cursor::new(&mut wav), stereo).unwrap();
+            for _ in 0..441 {
+                writer.write_sample(1_000i32).unwrap();
+                writer.write_sample(3_000i32).unwrap();
+            }
+            writer.finalize().unwrap();
         }
 
-        Err("No MP3 encoder found. Please install lame or ffmpeg".into())
+        let mono = to_canonical_wav(&wav, Config::default().wav_spec()).unwrap();
+        let mut reader = hound::WavReader::new(std::io::Cursor::new(mono)).unwrap();
+        assert_eq!(reader.spec().channels, 1);
+        let samples: Vec<i32> = reader.samples().map(Result::unwrap).collect();
+        assert_eq!(samples.len(), 441);
+        assert!(samples.iter().all(|sample| *sample == 2_000), "{:?}", &samples[..4]);
     }
 
-    fn encode_with_lame(
-        &self,
-        input_path: &Path,
-        output_path: &Path,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut cmd = ProcessCommand::new("lame");
-        cmd.arg("-V")
-        .arg(format!("{}", (9.0 - self.config.quality * 9.0) as u32))
-        .arg(input_path)
-        .arg(output_path);
+    #[test]
+    fn page_footers_go_but_ranges_in_prose_stay() {
+        let processor = text_processor(Config::default());
+        let text = "The years 1914-1918 were long.\n12-13\nPage 14\n  15  \nIt was page 16 of the diary.";
+        assert_eq!(
+            processor.clean_text(text, false),
+            "The years 1914-1918 were long. It was page 16 of the diary."
+        );
+        assert_eq!(
+            processor.clean_text(text, true),
+            "The years 1914 to 1918 were long. \
+             It was page 16 of the diary."
+        );
+    }
 
-        let output = cmd.output()?;
-        if !output.status.success() {
-            return Err("LAME encoding failed".into());
-        }
-        Ok(())
+    #[test]
+    fn shouted_caps_are_lowercased_but_acronyms_kept() {
+        let processor = text_processor(Config {
+            normalize_caps: true,
+            ..Config::default()
+        });
+        assert_eq!(processor.clean_text("NASA launched it.", false), "NASA launched it.");
+        assert_eq!(processor.clean_text("THE END", false), "the end");
+        assert_eq!(processor.clean_text("She read USA TODAY daily.", false), "She read USA today daily.");
+        assert_eq!(processor.clean_text("It was IMPORTANT.", false), "It was important.");
+
+        let untouched = text_processor(Config::default());
+        assert_eq!(untouched.clean_text("THE END", false), "THE END");
     }
 
-    fn encode_mp3_with_ffmpeg(
-        &self,
-        input_path: &Path,
-        output_path: &Path,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut cmd = ProcessCommand::new("ffmpeg");
-        cmd.arg("-i")
-        .arg(input_path)
-        .arg("-c:a")
-        .arg("libmp3lame")
-        .arg("-q:a")
-        .arg(format!("{}", (9.0 - self.config.quality * 9.0) as u32))
-        .arg("-y")
-        .arg(output_path);
+    #[test]
+    fn a_truncated_cache_file_is_synthesized_again() {
+        let cache = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let text = "These words were cached once, and then the file was cut short.";
+        let engine = cached_engine(cache.path());
+        let cache_path = cache.path().join(format!("{}.wav", engine.chunk_key(text, &engine.config)));
 
-        let output = cmd.output()?;
-        if !output.status.success() {
-            return Err("ffmpeg MP3 encoding failed".into());
-        }
-        Ok(())
+        // A cache file from an earlier run that never got a checksum recorded
+        let good = TTSEngine::new(mock_config()).unwrap();
+        good.text_to_speech(text, &output.path().join("good.wav")).unwrap();
+        let full = fs::read(output.path().join("good.wav")).unwrap();
+        fs::write(&cache_path, &full[..full.len() / 2]).unwrap();
+        assert!(check_cached_wav(&cache_path, engine.config.wav_spec()).is_err());
+
+        let speech = engine.text_to_speech(text, &output.path().join("again.wav")).unwrap();
+        assert!(!speech.cache_hit);
+        assert_eq!(fs::read(&cache_path).unwrap(), full);
+        assert!(check_cached_wav(&cache_path, engine.config.wav_spec()).is_ok());
     }
-}
 
-struct EpubProcessor {
-    text_processor: TextProcessor,
-    tts_engine: TTSEngine,
-    config: Config,
-}
+    #[test]
+    fn blockquotes_are_wrapped_in_pause_markers() {
+        let dir = tempfile::tempdir().unwrap();
+        let epub = build_epub(
+            dir.path(),
+            "Quoted",
+            &[chapter(
+                "Epigraph",
+                "Before the quote.</p><blockquote><p>All happy families are alike.</p></blockquote><p>After the quote.",
+            )],
+        );
 
-impl EpubProcessor {
-    fn new(config: Config) -> Result<Self, Box<dyn std::error::Error>> {
-        let tts_engine = TTSEngine::new(config.clone())?;
-        Ok(Self {
-            text_processor: TextProcessor::new(),
-           tts_engine,
-           config,
+        let processor = EpubProcessor::new(Config {
+            mark_blockquotes: true,
+            ..mock_config()
         })
+        .unwrap();
+        let chapters = processor.extract_chapters(&epub).unwrap();
+        let chunks = processor.chapter_chunks(&chapters[0]);
+        let position = |needle: &str| chunks.iter().position(|chunk| chunk.contains(needle)).unwrap();
+        let (before, quote, after) = (position("Before"), position("happy families"), position("After"));
+        assert!(chunks[before + 1..quote].iter().any(|chunk| chunk == PAUSE_MARKER));
+        assert!(chunks[quote + 1..after].iter().any(|chunk| chunk == PAUSE_MARKER));
+
+        let plain = EpubProcessor::new(mock_config()).unwrap();
+        let chapters = plain.extract_chapters(&epub).unwrap();
+        assert!(!chapters[0].content.contains(PAUSE_MARKER));
+        assert!(!plain.chapter_chunks(&chapters[0]).iter().any(|chunk| chunk == PAUSE_MARKER));
     }
 
-    fn extract_chapters(&self, epub_path: &Path) -> Result<Vec<Chapter>, Box<dyn std::error::Error>> {
-        let mut doc = EpubDoc::new(epub_path)?;
-        let mut chapters = Vec::new();
+    #[test]
+    fn image_modes_choose_between_silence_alt_text_and_captions() {
+        let html = r#"<figure><img src="map.png" alt="A map of the  valley"/><figcaption>The <em>valley</em> in spring</figcaption></figure>"#;
 
-        // Get spine (reading order)
-        let spine = doc.spine.clone();
+        let skipped = rewrite_images(html, ImageMode::Skip);
+        assert!(!skipped.contains("Figure") && !skipped.contains("valley"));
 
-        for (order, spine_item) in spine.iter().enumerate() {
-            if let Some(content) = doc.get_resource_by_path(&spine_item.0) {
-                let html_content = String::from_utf8_lossy(&content.0);
+        let alt = rewrite_images(html, ImageMode::Alt);
+        assert!(alt.contains("<p>Figure: A map of the valley.</p>"));
+        assert!(!alt.contains("spring"));
 
-                // Extract title from HTML
-                let title = self.extract_title(&html_content, order);
+        let caption = rewrite_images(html, ImageMode::Caption);
+        assert!(caption.contains("<p>Figure: The valley in spring.</p>"));
+        assert!(!caption.contains("A map"));
 
-                // Convert HTML to plain text
-                let plain_text = from_read(html_content.as_bytes(), 80);
+        // Decorative images with an empty alt stay silent
+        assert_eq!(rewrite_images(r#"<img src="rule.png" alt=""/>"#, ImageMode::Alt), "");
+    }
 
-                // Clean the text
-                let cleaned_text = self.text_processor.clean_text(
-                    &plain_text,
-                    self.config.preprocessing_aggressive,
-                );
+    #[test]
+    fn only_font_obfuscation_passes_the_drm_check() {
+        use zip::write::{SimpleFileOptions, ZipWriter};
 
-                if !cleaned_text.trim().is_empty() {
-                    let word_count = cleaned_text.split_whitespace().count();
-                    chapters.push(Chapter {
-                        title,
-                        content: cleaned_text,
-                        order,
-                        word_count,
-                    });
-                }
+        // The sample book with extra META-INF files added to the archive
+        let book_with = |files: &[(&str, String)]| {
+            let dir = tempfile::tempdir().unwrap();
+            let epub = sample_epub(dir.path());
+            let archive = fs::OpenOptions::new().read(true).write(true).open(&epub).unwrap();
+            let mut zip = ZipWriter::new_append(archive).unwrap();
+            for (name, content) in files {
+                zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+                zip.write_all(content.as_bytes()).unwrap();
             }
+            zip.finish().unwrap();
+            (dir, epub)
+        };
+        let encryption = |algorithm: &str, uri: &str| {
+            format!(
+                r#"<?xml version="1.0"?>
+<encryption xmlns="urn:oasis:names:tc:opendocument:xmlns:container" xmlns:enc="http://www.w3.org/2001/04/xmlenc#">
+  <enc:EncryptedData><enc:EncryptionMethod Algorithm="{}"/>
+    <enc:CipherData><enc:CipherReference URI="{}"/></enc:CipherData></enc:EncryptedData>
+</encryption>"#,
+                algorithm, uri
+            )
+        };
+        let processor = EpubProcessor::new(mock_config()).unwrap();
+
+        let (_dir, fonts) = book_with(&[(
+            "META-INF/encryption.xml",
+            encryption("http://www.idpf.org/2008/embedding", "OEBPS/fonts/serif.otf"),
+        )]);
+        assert_eq!(processor.extract_chapters(&fonts).unwrap().len(), 3);
+
+        let (_dir, encrypted) = book_with(&[(
+            "META-INF/encryption.xml",
+            encryption("http://www.w3.org/2001/04/xmlenc#aes128-cbc", "OEBPS/ch1.xhtml"),
+        )]);
+        let error = processor.extract_chapters(&encrypted).unwrap_err();
+        assert!(error.to_string().contains("DRM-protected"), "{}", error);
+
+        let (_dir, adept) = book_with(&[("META-INF/rights.xml", "<rights/>".to_string())]);
+        let error = processor.extract_chapters(&adept).unwrap_err();
+        assert!(error.to_string().contains("DRM-protected"), "{}", error);
+    }
+
+    #[test]
+    fn a_conversion_leaves_nothing_in_the_working_directory() {
+        // The conversion runs in a child process started in an empty directory,
+        // since changing this process's cwd would move every other test with it
+        if let Some(epub) = std::env::var_os("EBUPTTS_TEST_CONVERT") {
+            let cache = tempfile::tempdir().unwrap();
+            let config = Config {
+                cache_enabled: true,
+                cache_dir: Some(cache.path().to_path_buf()),
+                ..mock_config()
+            };
+            let (_output, files) = convert(Path::new(&epub), &config);
+            assert!(!files.is_empty());
+            return;
         }
 
-        Ok(chapters)
+        let dir = tempfile::tempdir().unwrap();
+        let epub = sample_epub(dir.path());
+        let cwd = tempfile::tempdir().unwrap();
+        let child = ProcessCommand::new(std::env::current_exe().unwrap())
+            .args(["--exact", "tests::a_conversion_leaves_nothing_in_the_working_directory"])
+            .env("EBUPTTS_TEST_CONVERT", &epub)
+            .current_dir(cwd.path())
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&child.stdout);
+        assert!(child.status.success(), "{}{}", stdout, String::from_utf8_lossy(&child.stderr));
+        assert!(stdout.contains("1 passed"), "{}", stdout);
+
+        let strays: Vec<_> = fs::read_dir(cwd.path()).unwrap().map(|entry| entry.unwrap().path()).collect();
+        assert!(strays.is_empty(), "left behind: {:?}", strays);
+        assert!(default_cache_dir().is_absolute());
     }
 
-    fn extract_title(&self, html: &str, order: usize) -> String {
-        // Try to extract title from h1, h2, h3 tags
-        let title_regex = Regex::new(r"<h[1-3][^>]*>([^<]+)</h[1-3]>").unwrap();
+    #[test]
+    fn a_corrupted_cache_file_is_regenerated_on_the_next_run() {
+        let cache = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let text = "A stray bit flip must not be served forever.";
 
-        if let Some(captures) = title_regex.captures(html) {
-            let title = captures.get(1).unwrap().as_str();
-            return html2text::from_read(title.as_bytes(), 80).trim().to_string();
-        }
+        let first = cached_engine(cache.path());
+        first.text_to_speech(text, &output.path().join("first.wav")).unwrap();
+        first.save_cache_index().unwrap();
+        let cache_path = cache.path().join(format!("{}.wav", first.chunk_key(text, &first.config)));
+        let original = fs::read(&cache_path).unwrap();
+
+        // Same length and still a readable WAV, so only the checksum can tell
+        let mut corrupted = original.clone();
+        *corrupted.last_mut().unwrap() ^= 0x55;
+        fs::write(&cache_path, &corrupted).unwrap();
 
-        format!("Chapter {}", order + 1)
+        let second = cached_engine(cache.path());
+        let speech = second.text_to_speech(text, &output.path().join("second.wav")).unwrap();
+        assert!(!speech.cache_hit);
+        assert_eq!(fs::read(&cache_path).unwrap(), original);
+
+        let third = cached_engine(cache.path());
+        assert!(third.text_to_speech(text, &output.path().join("third.wav")).unwrap().cache_hit);
     }
 
-    fn process_chapters(
-        &self,
-        chapters: Vec<Chapter>,
-        output_dir: &Path,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        fs::create_dir_all(output_dir)?;
+    #[test]
+    fn list_items_are_read_as_numbered_sentences_and_prose_keeps_its_markers() {
+        let html = r#"<ol start="3"><li>Third</li><li><em>Fourth!</em></li></ol><ul><li>Milk<ul><li>Oat</li></ul></li></ul>"#;
+        assert_eq!(
+            rewrite_lists(html),
+            "<p>Three. Third.</p><p>Four. <em>Fourth!</em></p><p>Milk.</p><p>Oat.</p>"
+        );
 
-        let progress_bar = ProgressBar::new(chapters.len() as u64);
-        progress_bar.set_style(
-            ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos:>7}/{len:7} {msg}")?
-            .progress_chars("█▉▊▋▌▍▎▏  ")
+        let dir = tempfile::tempdir().unwrap();
+        let epub = build_epub(
+            dir.path(),
+            "Test Book",
+            &[chapter(
+                "Lists",
+                "- Run, she said.</p><p>* * *</p><p>12. That was the year.</p><ol><li>First</li><li>Second</li></ol><p>",
+            )],
         );
+        let processor = EpubProcessor::new(mock_config()).unwrap();
+        let content = processor.extract_chapters(&epub).unwrap().remove(0).content;
+        assert!(content.contains("- Run, she said."), "{}", content);
+        assert!(content.contains("* * *"), "{}", content);
+        assert!(content.contains("12. That was the year."), "{}", content);
+        assert!(content.contains("One. First. Two. Second."), "{}", content);
+    }
 
-        chapters
-        .into_par_iter()
-        .progress_with(progress_bar)
-        .try_for_each(|chapter| -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-            self.process_single_chapter(&chapter, output_dir)?;
-            Ok(())
-        })?;
+    #[test]
+    fn chapter_titles_lose_their_leading_numbering() {
+        assert_eq!(clean_chapter_title("Chapter 1: The Beginning"), "The Beginning");
+        assert_eq!(clean_chapter_title("I. Dawn"), "Dawn");
+        assert_eq!(clean_chapter_title("12 Home"), "Home");
+        // Nothing left but the number, so the number is the title
+        assert_eq!(clean_chapter_title("Chapter 3"), "Chapter 3");
+        assert_eq!(clean_chapter_title("1984"), "1984");
+        // Numerals joined to the next word are part of the title
+        assert_eq!(clean_chapter_title("D-Day"), "D-Day");
+        assert_eq!(clean_chapter_title("X-Men Forever"), "X-Men Forever");
+        assert_eq!(clean_chapter_title("CD-ROM Days"), "CD-ROM Days");
+        assert_eq!(clean_chapter_title("3-D Printing"), "3-D Printing");
+        assert_eq!(clean_chapter_title("IV - The Return"), "The Return");
 
-        Ok(())
+        let dir = tempfile::tempdir().unwrap();
+        let epub = build_epub(
+            dir.path(),
+            "Numbered",
+            &[chapter("Chapter 1: The Beginning", "It began."), chapter("I. Dawn", "The sun rose.")],
+        );
+        let titles = |config: Config| -> Vec<String> {
+            let processor = EpubProcessor::new(config).unwrap();
+            processor.extract_chapters(&epub).unwrap().into_iter().map(|chapter| chapter.title).collect()
+        };
+        assert_eq!(titles(mock_config()), ["Chapter 1: The Beginning", "I. Dawn"]);
+        assert_eq!(
+            titles(Config {
+                clean_chapter_titles: true,
+                ..mock_config()
+            }),
+            ["The Beginning", "Dawn"]
+        );
     }
 
-    fn process_single_chapter(
-        &self,
-        chapter: &Chapter,
-        output_dir: &Path,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let safe_title = sanitize_filename(&chapter.title);
-        let chapter_dir = output_dir.join(format!("{:03}_{}", chapter.order, safe_title));
-        fs::create_dir_all(&chapter_dir)?;
+    #[test]
+    fn native_outputs_come_out_as_wav() {
+        let spec = |bits_per_sample: u16, sample_format: hound::SampleFormat| hound::WavSpec {
+            channels: 1,
+            sample_rate: 8000,
+            bits_per_sample,
+            sample_format,
+        };
+        let decode = |audio: OutputAudio| {
+            let wav = audio.into_wav().unwrap();
+            let mut reader = hound::WavReader::new(std::io::Cursor::new(wav)).unwrap();
+            let spec = reader.spec();
+            let samples: Vec<f32> = match spec.sample_format {
+                hound::SampleFormat::Float => reader.samples::<f32>().map(Result::unwrap).collect(),
+                hound::SampleFormat::Int => reader.samples::<i32>().map(|sample| sample.unwrap() as f32).collect(),
+            };
+            (spec, samples)
+        };
 
-        // Split chapter into chunks for better TTS processing
-        let chunks = self.text_processor.split_into_chunks(
-            &chapter.content,
-            self.config.chunk_size,
-        );
+        // A WAV passes through untouched
+        let wav = silent_wav(spec(16, hound::SampleFormat::Int), 3).unwrap();
+        assert_eq!(OutputAudio::wav(wav.clone()).into_wav().unwrap(), wav);
 
-        // Process chunks in sequence to maintain order
-        for (chunk_idx, chunk) in chunks.iter().enumerate() {
-            if chunk.trim().is_empty() {
-                continue;
-            }
+        // 8-bit raw PCM is unsigned, centred on 128
+        let raw = |data: Vec<u8>, spec| OutputAudio {
+            data,
+            format: NativeFormat::RawPcm(spec),
+        };
+        let (read, samples) = decode(raw(vec![128, 255, 0], spec(8, hound::SampleFormat::Int)));
+        assert_eq!(read, spec(8, hound::SampleFormat::Int));
+        assert_eq!(samples, [0.0, 127.0, -128.0]);
 
-            let output_filename = format!(
-                "{:03}_{}.{}",
-                chunk_idx,
-                safe_title,
-                self.get_file_extension()
-            );
-            let output_path = chapter_dir.join(output_filename);
+        let (_, samples) = decode(raw(vec![0x34, 0x12, 0xff, 0xff], spec(16, hound::SampleFormat::Int)));
+        assert_eq!(samples, [4660.0, -1.0]);
 
-            self.tts_engine.text_to_speech(chunk, &output_path)
-            .map_err(|e| -> Box<dyn std::error::Error + Send + Sync> {
-                format!("TTS failed for chunk {}: {}", chunk_idx, e).into()
-            })?;
-        }
+        // 24-bit samples are three bytes each and sign-extended
+        let (read, samples) = decode(raw(vec![0x56, 0x34, 0x12, 0x00, 0x00, 0x80], spec(24, hound::SampleFormat::Int)));
+        assert_eq!(read.bits_per_sample, 24);
+        assert_eq!(samples, [0x123456 as f32, -8_388_608.0]);
 
-        // Create metadata file
-        let metadata = serde_json::json!({
-            "title": chapter.title,
-            "order": chapter.order,
-            "word_count": chapter.word_count,
-            "chunks": chunks.len(),
-                                         "config": self.config
-        });
+        let data: Vec<u8> = [0.5f32, -0.25].iter().flat_map(|sample| sample.to_le_bytes()).collect();
+        let (read, samples) = decode(raw(data, spec(32, hound::SampleFormat::Float)));
+        assert_eq!(read.sample_format, hound::SampleFormat::Float);
+        assert_eq!(samples, [0.5, -0.25]);
 
-        let metadata_path = chapter_dir.join("metadata.json");
-        let metadata_file = File::create(metadata_path)?;
-        serde_json::to_writer_pretty(metadata_file, &metadata)?;
+        // Layouts a WAV header cannot describe are refused
+        assert!(raw(vec![0; 8], spec(64, hound::SampleFormat::Float)).into_wav().is_err());
+    }
 
-        Ok(())
+    // The mock engine, counting how often it is asked to synthesize
+    struct CountingSynthesizer(Arc<AtomicUsize>);
+
+    impl SpeechSynthesizer for CountingSynthesizer {
+        fn name(&self) -> &'static str {
+            MockSynthesizer.name()
+        }
+
+        fn synthesize(&self, text: &str, voice: &Config) -> Result<OutputAudio, Box<dyn std::error::Error>> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            MockSynthesizer.synthesize(text, voice)
+        }
     }
 
-    fn get_file_extension(&self) -> &'static str {
-        match self.config.output_format {
-            AudioFormat::Vorbis => "ogg",
-            AudioFormat::Flac => "flac",
-            AudioFormat::Mp3 => "mp3",
-            AudioFormat::Wav => "wav",
+    #[test]
+    fn switching_the_output_format_reuses_cached_audio() {
+        let cache = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let chunks = ["The first chunk.", "The second chunk.", "The third chunk."];
+        let counted = |format: AudioFormat| {
+            let calls = Arc::new(AtomicUsize::new(0));
+            let mut engine = TTSEngine::new(Config {
+                output_format: format,
+                cache_enabled: true,
+                cache_dir: Some(cache.path().to_path_buf()),
+                ..mock_config()
+            })
+            .unwrap();
+            engine.synthesizer = Some(Box::new(CountingSynthesizer(Arc::clone(&calls))));
+            (engine, calls)
+        };
+
+        let (wav, calls) = counted(AudioFormat::Wav);
+        for (index, chunk) in chunks.iter().enumerate() {
+            let speech = wav.text_to_speech(chunk, &output.path().join(format!("{}.wav", index))).unwrap();
+            assert!(!speech.cache_hit);
+        }
+        wav.save_cache_index().unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), chunks.len());
+
+        // Only the encoding step differs between formats; the WAV each chunk is
+        // encoded from has to come from the cache
+        let (mp3, calls) = counted(AudioFormat::Mp3);
+        for chunk in chunks {
+            assert!(mp3.render(chunk, &mp3.config).unwrap().cache_hit);
         }
+        assert_eq!(calls.load(Ordering::Relaxed), 0);
     }
-}
 
-fn sanitize_filename(name: &str) -> String {
-    let invalid_chars = Regex::new(r#"[<>:"/\\|?*]"#).unwrap();
-    invalid_chars.replace_all(name, "_").to_string()
-}
+    #[test]
+    fn chapter_landmarks_split_one_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let book = FixtureDoc {
+            heading: "Everything".to_string(),
+            xhtml: br#"<?xml version="1.0" encoding="utf-8"?>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops"><head><title>Everything</title></head><body>
+<section epub:type="chapter"><h2>Dawn</h2><p>Alpha words.</p><span epub:type="pagebreak" id="p2"/><p>More alpha.</p></section>
+<section epub:type="chapter"><h2>Noon</h2><p>Beta words.</p></section>
+<div style="page-break-before: always"><h2>Dusk</h2><p>Gamma words.</p></div>
+</body></html>"#
+                .to_vec(),
+            linear: true,
+            href: None,
+        };
+        let epub = build_epub(dir.path(), "Test Book", &[book]);
 
-fn create_playlist(output_dir: &Path, format: &AudioFormat) -> Result<(), Box<dyn std::error::Error>> {
-    let mut audio_files = Vec::new();
+        let processor = EpubProcessor::new(Config {
+            split_on_pagebreaks: true,
+            ..mock_config()
+        })
+        .unwrap();
+        let chapters = processor.extract_chapters(&epub).unwrap();
+        let titles: Vec<&str> = chapters.iter().map(|chapter| chapter.title.as_str()).collect();
+        assert_eq!(titles, ["Dawn", "Noon", "Dusk"]);
+        // A print page marker is not a chapter start
+        assert!(chapters[0].content.contains("More alpha"));
+        assert!(chapters[2].content.contains("Gamma words"));
 
-    // Collect all audio files in order
-    for entry in fs::read_dir(output_dir)? {
-        let entry = entry?;
-        let path = entry.path();
+        let whole = EpubProcessor::new(mock_config()).unwrap().extract_chapters(&epub).unwrap();
+        assert_eq!(whole.len(), 1);
+    }
 
-        if path.is_dir() {
-            for audio_entry in fs::read_dir(&path)? {
-                let audio_entry = audio_entry?;
-                let audio_path = audio_entry.path();
+    #[test]
+    fn pause_scale_lengthens_sentence_and_paragraph_pauses_most() {
+        let text = "Wait, listen. It is late.\n\nMorning came.";
+        let spoken = |pause_scale: f32| {
+            text_processor(Config {
+                pause_scale,
+                ..mock_config()
+            })
+            .speech_text(text, true)
+        };
 
-                if let Some(ext) = audio_path.extension() {
-                    if ext == "ogg" || ext == "flac" || ext == "mp3" || ext == "wav" {
-                        audio_files.push(audio_path);
-                    }
-                }
-            }
-        }
+        assert_eq!(spoken(1.0), "Wait, listen. It is late. Morning came.");
+        assert_eq!(spoken(2.0), "Wait, ... listen. ... ... It is late. ... ... ... Morning came.");
+    }
+
+    #[test]
+    fn text_dividers_split_a_flattened_chapter() {
+        let dir = tempfile::tempdir().unwrap();
+        let epub = build_epub(
+            dir.path(),
+            "Dividers",
+            &[chapter(
+                "Opening",
+                "It began quietly.</p><p>* * *</p><p>The Storm</p><p>Rain fell hard.</p><p>*  *  *</p><p>The Calm</p><p>Quiet at last.",
+            )],
+        );
+
+        let processor = EpubProcessor::new(Config {
+            text_chapter_regex: Some(r"^\s*\*\s*\*\s*\*\s*$".to_string()),
+            ..mock_config()
+        })
+        .unwrap();
+        let chapters = processor.extract_chapters(&epub).unwrap();
+        let titles: Vec<&str> = chapters.iter().map(|chapter| chapter.title.as_str()).collect();
+        assert_eq!(titles, ["Opening", "The Storm", "The Calm"]);
+        assert!(chapters[0].content.contains("It began quietly") && !chapters[0].content.contains("Rain"));
+        assert!(chapters[1].content.contains("Rain fell hard") && !chapters[1].content.contains("Quiet"));
+        assert!(chapters.iter().all(|chapter| !chapter.content.contains('*')));
+
+        let whole = EpubProcessor::new(mock_config()).unwrap().extract_chapters(&epub).unwrap();
+        assert_eq!(whole.len(), 1);
     }
 
-    audio_files.sort();
+    // Half a second of tone between the lead-in and tail silences espeak pads
+    // its output with
+    struct PaddedToneSynthesizer;
 
-    // Create M3U playlist
-    let playlist_path = output_dir.join("audiobook.m3u");
-    let mut playlist_file = BufWriter::new(File::create(playlist_path)?);
+    impl SpeechSynthesizer for PaddedToneSynthesizer {
+        fn name(&self) -> &'static str {
+            "padded-tone"
+        }
 
-    writeln!(playlist_file, "#EXTM3U")?;
-    for audio_file in audio_files {
-        if let Some(filename) = audio_file.file_name() {
-            writeln!(playlist_file, "{}", filename.to_string_lossy())?;
+        fn synthesize(&self, _text: &str, voice: &Config) -> Result<OutputAudio, Box<dyn std::error::Error>> {
+            let rate = voice.sample_rate as usize;
+            let silence = vec![0i16; rate * 3 / 10];
+            let tone = (0..rate / 2).map(|i| ((i as f32 * 440.0 * std::f32::consts::TAU / rate as f32).sin() * 16_000.0) as i16);
+            let data = silence
+                .iter()
+                .copied()
+                .chain(tone)
+                .chain(silence.iter().copied())
+                .flat_map(i16::to_le_bytes)
+                .collect();
+            Ok(OutputAudio {
+                data,
+                format: NativeFormat::RawPcm(hound::WavSpec {
+                    channels: 1,
+                    sample_rate: voice.sample_rate,
+                    bits_per_sample: 16,
+                    sample_format: hound::SampleFormat::Int,
+                }),
+            })
         }
     }
 
-    Ok(())
-}
+    #[test]
+    fn resume_keeps_a_chapter_joined_in_an_earlier_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let epub = build_epub(dir.path(), "Test Book", &[chapter("Only", "Just one chapter.")]);
+        let config = Config {
+            join_chunks: true,
+            output_policy: OutputPolicy::Resume,
+            ..mock_config()
+        };
+        let output = tempfile::tempdir().unwrap();
+        let joined = output.path().join("000_Only.wav");
+        fs::write(&joined, b"joined audio").unwrap();
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let matches = Command::new("EPUB to Audiobook Converter")
-    .version("1.0")
-    .author("Advanced TTS Converter")
-    .about("Converts EPUB files to high-quality audiobooks using CPU-optimized TTS")
-    .arg(
-        Arg::new("input")
-        .short('i')
-        .long("input")
-        .value_name("FILE")
-        .help("Input EPUB file")
-        .required(true),
-    )
-    .arg(
-        Arg::new("output")
-        .short('o')
-        .long("output")
-        .value_name("DIR")
-        .help("Output directory")
-        .required(true),
-    )
-    .arg(
-        Arg::new("format")
-        .short('f')
-        .long("format")
-        .value_name("FORMAT")
-        .help("Output audio format")
-        .value_parser(["vorbis", "flac", "mp3", "wav"])
-        .default_value("vorbis"),
-    )
-    .arg(
-        Arg::new("quality")
-        .short('q')
-        .long("quality")
-        .value_name("FLOAT")
-        .help("Audio quality (0.0-1.0)")
-        .value_parser(clap::value_parser!(f32))
-        .default_value("0.7"),
-    )
-    .arg(
-        Arg::new("speed")
-        .short('s')
-        .long("speed")
-        .value_name("FLOAT")
-        .help("Voice speed multiplier")
-        .value_parser(clap::value_parser!(f32))
-        .default_value("1.0"),
-    )
-    .arg(
-        Arg::new("workers")
-        .short('w')
-        .long("workers")
-        .value_name("NUM")
-        .help("Number of worker threads")
-        .value_parser(clap::value_parser!(usize))
-        .default_value(&num_cpus::get().to_string()),
-    )
-    .get_matches();
+        convert_book(&epub, Some(output.path()), &config, &run_options()).unwrap();
+        // Nothing was synthesized for it again
+        assert!(!output.path().join("000_Only").exists());
+        assert_eq!(fs::read(&joined).unwrap(), b"joined audio");
+        let playlist = fs::read_to_string(output.path().join("audiobook.m3u")).unwrap();
+        assert!(playlist.ends_with("\n000_Only.wav\n"), "{}", playlist);
+    }
 
-    let input_path = Path::new(matches.get_one::<String>("input").unwrap());
-    let output_dir = Path::new(matches.get_one::<String>("output").unwrap());
+    #[test]
+    fn trimmed_chunks_leave_little_silence_at_the_joins() {
+        // Seconds of silence before the first and after the last audible frame
+        let silent_edges = |path: &Path| {
+            let mut reader = hound::WavReader::open(path).unwrap();
+            let spec = reader.spec();
+            let threshold = ((1i64 << (spec.bits_per_sample - 1)) as f32 * SILENCE_THRESHOLD) as i32;
+            let samples: Vec<i32> = reader.samples().map(Result::unwrap).collect();
+            let frames: Vec<&[i32]> = samples.chunks(spec.channels as usize).collect();
+            let loud = |frame: &&[i32]| frame.iter().any(|sample| sample.abs() > threshold);
+            let first = frames.iter().position(loud).unwrap();
+            let last = frames.iter().rposition(loud).unwrap();
+            let secs = |frames: usize| frames as f64 / spec.sample_rate as f64;
+            (secs(first), secs(frames.len() - 1 - last))
+        };
+        // The silence a listener hears where one chunk file hands over to the next
+        let gaps = |trim_chunk_silence: bool| {
+            let output = tempfile::tempdir().unwrap();
+            let mut engine = TTSEngine::new(Config {
+                trim_chunk_silence,
+                ..mock_config()
+            })
+            .unwrap();
+            engine.synthesizer = Some(Box::new(PaddedToneSynthesizer));
+            let edges: Vec<(f64, f64)> = (0..3)
+                .map(|chunk| {
+                    let path = output.path().join(format!("{}.wav", chunk));
+                    engine.text_to_speech(&format!("Chunk {}.", chunk), &path).unwrap();
+                    silent_edges(&path)
+                })
+                .collect();
+            edges.windows(2).map(|pair| pair[0].1 + pair[1].0).collect::<Vec<f64>>()
+        };
 
-    let audio_format = match matches.get_one::<String>("format").unwrap().as_str() {
-        "vorbis" => AudioFormat::Vorbis,
-        "flac" => AudioFormat::Flac,
-        "mp3" => AudioFormat::Mp3,
-        "wav" => AudioFormat::Wav,
-        _ => AudioFormat::Vorbis,
-    };
+        let untrimmed = gaps(false);
+        assert!(untrimmed.iter().all(|gap| *gap > 0.55), "{:?}", untrimmed);
+        let trimmed = gaps(true);
+        let limit = 2.0 * SILENCE_KEEP_MS as f64 / 1000.0 + 0.01;
+        assert!(trimmed.iter().all(|gap| *gap <= limit), "{:?} over {}", trimmed, limit);
+    }
 
-    let config = Config {
-        output_format: audio_format,
-        quality: *matches.get_one::<f32>("quality").unwrap(),
-        voice_speed: *matches.get_one::<f32>("speed").unwrap(),
-        max_workers: *matches.get_one::<usize>("workers").unwrap(),
-        ..Default::default()
-    };
+    #[test]
+    fn a_short_tail_chunk_is_merged_upward() {
+        let processor = text_processor(mock_config());
+        let text = "Alpha beta gamma delta. Epsilon zeta eta theta. Tiny.";
 
-    // Configure Rayon thread pool
-    rayon::ThreadPoolBuilder::new()
-    .num_threads(config.max_workers)
-    .build_global()?;
+        let unbounded = processor.split_into_chunks(text, 0, 50);
+        assert_eq!(unbounded.last().map(String::as_str), Some("Tiny."));
 
-    println!("🔄 Initializing EPUB to Audiobook Converter...");
-    let start_time = Instant::now();
+        let chunks = processor.split_into_chunks(text, 10, 50);
+        assert_eq!(chunks, ["Alpha beta gamma delta.", "Epsilon zeta eta theta. Tiny."]);
+        assert!(chunks.iter().all(|chunk| (10..=50).contains(&chunk.len())));
 
-    let processor = EpubProcessor::new(config.clone())?;
+        // Config files written before the rename still set the maximum
+        let old: Config = toml::from_str("chunk_size = 500").unwrap();
+        assert_eq!(old.max_chunk_size, 500);
+    }
+
+    #[test]
+    fn a_cover_only_book_is_an_error_not_an_empty_folder() {
+        let dir = tempfile::tempdir().unwrap();
+        let cover = FixtureDoc {
+            heading: "Cover".to_string(),
+            xhtml: br#"<?xml version="1.0" encoding="utf-8"?>
+<html xmlns="http://www.w3.org/1999/xhtml"><head><title>Cover</title></head>
+<body><div><img src="cover.jpg" alt="Cover"/></div></body></html>"#
+                .to_vec(),
+            linear: true,
+            href: None,
+        };
+        let epub = build_epub(dir.path(), "Pictures Only", &[cover]);
+        let output = dir.path().join("out");
 
-    println!("📖 Extracting chapters from EPUB...");
-    let chapters = processor.extract_chapters(input_path)?;
-    println!("✅ Found {} chapters", chapters.len());
+        let error = convert_book(&epub, Some(&output), &mock_config(), &run_options()).unwrap_err();
+        assert!(error.to_string().starts_with("No convertible chapters found"), "{}", error);
+        assert!(!output.exists());
+    }
 
-    let total_words: usize = chapters.iter().map(|c| c.word_count).sum();
-    println!("📊 Total words: {}", total_words);
+    #[test]
+    fn playlists_list_every_file_in_chapter_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = |order: usize, title: &str, names: &[&str]| ChapterOutput {
+            order,
+            title: title.to_string(),
+            files: names
+                .iter()
+                .map(|name| ChunkOutput { path: dir.path().join(name), duration_secs: 61.6 })
+                .collect(),
+            complete: true,
+        };
+        // Out of order, to check the playlist follows `order`
+        let outputs = [
+            output(1, "Fish & Chips", &["chapters/02 fish.wav"]),
+            output(0, "Intro", &["chapters/01_a.wav", "chapters/01_b.wav"]),
+        ];
+        let book = BookMetadata {
+            title: "Tom <Tales>".to_string(),
+            author: Some("O'Brien".to_string()),
+            series: None,
+            series_index: None,
+        };
+        let playlist = |format: PlaylistFormat| {
+            create_playlist(dir.path(), &outputs, format, &book).unwrap();
+            fs::read_to_string(dir.path().join(format!("audiobook.{}", format.extension()))).unwrap()
+        };
 
-    println!("🎤 Converting chapters to audio...");
-    processor.process_chapters(chapters, output_dir)?;
+        assert_eq!(
+            playlist(PlaylistFormat::M3u),
+            "#EXTM3U\n\
+             #EXTINF:62,Intro (part 1)\nchapters/01_a.wav\n\
+             #EXTINF:62,Intro (part 2)\nchapters/01_b.wav\n\
+             #EXTINF:62,Fish & Chips\nchapters/02 fish.wav\n"
+        );
 
-    println!("📝 Creating playlist...");
-    create_playlist(output_dir, &config.output_format)?;
+        let pls = playlist(PlaylistFormat::Pls);
+        assert!(pls.starts_with("[playlist]\nFile1=chapters/01_a.wav\nTitle1=Intro (part 1)\nLength1=62\n"), "{}", pls);
+        assert!(pls.contains("File3=chapters/02 fish.wav\nTitle3=Fish & Chips\n"), "{}", pls);
+        assert!(pls.ends_with("NumberOfEntries=3\nVersion=2\n"), "{}", pls);
 
-    let duration = start_time.elapsed();
-    println!("✅ Conversion completed in {:.2?}", duration);
-    println!("📁 Output saved to: {}", output_dir.display());
+        let xspf = playlist(PlaylistFormat::Xspf);
+        assert!(xspf.contains("<title>Tom &lt;Tales&gt;</title>"), "{}", xspf);
+        assert!(xspf.contains("<creator>O&apos;Brien</creator>"), "{}", xspf);
+        assert_eq!(xspf.matches("<track>").count(), 3);
+        assert!(xspf.contains("<trackList>") && xspf.contains("</trackList>"), "{}", xspf);
+        assert!(xspf.contains("<location>chapters/02%20fish.wav</location>"), "{}", xspf);
+        assert!(xspf.contains("<title>Fish &amp; Chips</title>"), "{}", xspf);
+        assert!(xspf.contains("<trackNum>3</trackNum>\n      <duration>61600</duration>"), "{}", xspf);
+    }
 
-    Ok(())
+    // Book text is assembled from pieces that each trigger some cleanup rule
+    const TEXT_PIECES: &[&str] = &[
+        "word", "Word", "WORD", "ALL CAPS", "l", "O", " ", "  ", "\n", "\n\n", "\t",
+        ".", ",", "!", "?", ";", ":", "...", "…", "-", "--", "—", "–", "-\n", "(", ")",
+        "\"", "'", "“", "”", "&amp;", "&", "%", "$5", "1914-1918", "Mr.", "Dr. ", "e.g.",
+        "* * *", "Page 12", "\u{ad}", "\u{200b}", "IV", "42",
+    ];
+
+    proptest! {
+        #[test]
+        fn clean_text_is_idempotent(
+            pieces in prop::collection::vec(prop::sample::select(TEXT_PIECES), 0..40),
+            aggressive in prop::sample::select(&[false, true][..]),
+            dash_to_pause in prop::sample::select(&[false, true][..])
+        ) {
+            let processor = text_processor(Config { dash_to_pause, ..Config::default() });
+            let once = processor.clean_text(&pieces.concat(), aggressive);
+            prop_assert_eq!(processor.clean_text(&once, aggressive), once);
+        }
+    }
+
+    #[test]
+    fn ruby_reads_either_the_base_text_or_its_reading() {
+        let dir = tempfile::tempdir().unwrap();
+        let epub = build_epub(
+            dir.path(),
+            "Ruby",
+            &[chapter(
+                "Kanji",
+                "<ruby>漢<rp>(</rp><rt>かん</rt><rp>)</rp>字<rp>(</rp><rt>じ</rt><rp>)</rp></ruby>を読む",
+            )],
+        );
+        let content = |ruby_mode: RubyMode| {
+            let processor = EpubProcessor::new(Config {
+                ruby_mode,
+                ..mock_config()
+            })
+            .unwrap();
+            processor.extract_chapters(&epub).unwrap().remove(0).content
+        };
+
+        let base = content(RubyMode::Base);
+        assert!(base.contains("漢字を読む"), "{}", base);
+        assert!(!base.contains("かん") && !base.contains('('));
+
+        let reading = content(RubyMode::Reading);
+        assert!(reading.contains("かんじを読む"), "{}", reading);
+        assert!(!reading.contains('漢'));
+    }
+
+    #[test]
+    fn line_break_hyphens_join_split_words_and_keep_compounds() {
+        let processor = text_processor(Config::default());
+        assert_eq!(
+            processor.clean_text("the inter-\nnational and self-\naware folks", true),
+            "the international and self-aware folks"
+        );
+    }
+
+    #[test]
+    fn clearing_the_cache_leaves_other_files_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = "ab".repeat(32);
+        fs::write(dir.path().join(format!("{}.wav", key)), b"cached").unwrap();
+        fs::write(dir.path().join(CACHE_INDEX_FILE), b"{}").unwrap();
+        fs::write(dir.path().join("song.wav"), b"mine").unwrap();
+
+        assert_eq!(clear_cache(dir.path(), None).unwrap(), 1);
+        assert!(dir.path().join("song.wav").exists());
+        assert!(!dir.path().join(format!("{}.wav", key)).exists());
+        assert!(!dir.path().join(CACHE_INDEX_FILE).exists());
+
+        fs::remove_file(dir.path().join("song.wav")).unwrap();
+        let cache_dir = dir.path().to_path_buf();
+        fs::write(cache_dir.join(format!("{}.wav", key)), b"cached").unwrap();
+        clear_cache(&cache_dir, None).unwrap();
+        assert!(!cache_dir.exists());
+    }
 }